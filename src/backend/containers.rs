@@ -0,0 +1,379 @@
+mod parse;
+
+use super::{
+    CheckStatus, ItemOutcome, ItemReport, PendingUpdate, TransactionSummary, UpdateBackend,
+};
+use crate::runner::CommandRunner;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use parse::PackageManager;
+
+/// Exit status of `dnf check-upgrade` when updates are available
+const EXIT_UPDATES_AVAILABLE: i32 = 100;
+
+/// Tool that created a container and runs commands inside it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Toolbox,
+    Distrobox,
+}
+
+impl Tool {
+    fn program(self) -> &'static str {
+        match self {
+            Self::Toolbox => "toolbox",
+            Self::Distrobox => "distrobox",
+        }
+    }
+}
+
+/// A development container and what the last check found in it
+#[derive(Debug)]
+struct Container {
+    name: String,
+    tool: Tool,
+    manager: Option<PackageManager>,
+    pending: Vec<PendingUpdate>,
+    // Result reported in the run summary
+    outcome: ItemOutcome,
+    error: Option<String>,
+    // Why the container was skipped
+    detail: Option<String>,
+}
+
+impl Container {
+    fn new(name: String, tool: Tool) -> Self {
+        Self {
+            name,
+            tool,
+            manager: None,
+            pending: Vec::new(),
+            outcome: ItemOutcome::UpToDate,
+            error: None,
+            detail: None,
+        }
+    }
+
+    /// Host command line that runs `command` inside the container
+    fn wrap(&self, command: &[&str]) -> Vec<String> {
+        let mut args: Vec<String> = match self.tool {
+            Tool::Toolbox => vec!["run".into(), "--container".into(), self.name.clone()],
+            Tool::Distrobox => vec!["enter".into(), self.name.clone(), "--".into()],
+        };
+        args.extend(command.iter().map(|arg| arg.to_string()));
+        args
+    }
+
+    /// Runs a command inside the container quietly, failing on a non-zero exit
+    /// status other than the accepted ones
+    async fn capture(
+        &self,
+        runner: &mut CommandRunner,
+        command: &[&str],
+        accepted: &[i32],
+    ) -> Result<(i32, String)> {
+        let args = self.wrap(command);
+        let output = runner
+            .capture_command(self.tool.program(), &super::str_args(&args))
+            .await
+            .with_context(|| format!("Failed to run {} in {}", command[0], self.name))?;
+
+        let code = output.status.code().unwrap_or(-1);
+        if code != 0 && !accepted.contains(&code) {
+            return Err(anyhow::anyhow!(
+                "{} exited with status {}",
+                command.join(" "),
+                code
+            ));
+        }
+        Ok((code, String::from_utf8_lossy(&output.stdout).into_owned()))
+    }
+
+    /// Detects the package manager and lists pending updates
+    ///
+    /// Nothing in the container is changed: apt reads the package lists it
+    /// already has, and pacman's `checkupdates` syncs into a temporary database.
+    async fn check(&mut self, runner: &mut CommandRunner) -> Result<()> {
+        let (_, os_release) = self
+            .capture(runner, &["cat", "/etc/os-release"], &[])
+            .await?;
+        let release = parse::parse_os_release(&os_release);
+        let Some(manager) = release.package_manager() else {
+            self.outcome = ItemOutcome::Skipped;
+            self.detail = Some(format!("unsupported distribution '{}'", release.id));
+            return Ok(());
+        };
+        self.manager = Some(manager);
+
+        let mut pending = match manager {
+            PackageManager::Dnf5 | PackageManager::Dnf => {
+                let (code, output) = self
+                    .capture(
+                        runner,
+                        &[manager.command(), "check-upgrade", "--refresh"],
+                        &[EXIT_UPDATES_AVAILABLE],
+                    )
+                    .await?;
+                if code == EXIT_UPDATES_AVAILABLE {
+                    super::dnf5::parse_check_upgrade(&output)
+                        .into_iter()
+                        .map(|package| PendingUpdate {
+                            name: package.key(),
+                            current_version: None,
+                            new_version: Some(package.evr),
+                            origin: None,
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                }
+            }
+            PackageManager::Apt => {
+                let (_, output) = self
+                    .capture(runner, &["apt", "list", "--upgradable"], &[])
+                    .await?;
+                parse::parse_apt_upgradable(&output)
+            }
+            PackageManager::Pacman => {
+                // checkupdates comes with pacman-contrib; without it the local sync
+                // database is used as is. They exit with 2 and 1 when nothing is upgradable
+                let output = match self.capture(runner, &["checkupdates"], &[2]).await {
+                    Ok((_, output)) => output,
+                    Err(_) => self.capture(runner, &["pacman", "-Qu"], &[1]).await?.1,
+                };
+                parse::parse_pacman_upgrades(&output)
+            }
+        };
+
+        // Record which container each package belongs to
+        for update in &mut pending {
+            update.origin = Some(self.name.clone());
+        }
+        self.pending = pending;
+        Ok(())
+    }
+
+    /// Upgrades every package in the container, refreshing the package lists first
+    async fn upgrade(&self, runner: &mut CommandRunner, manager: PackageManager) -> Result<()> {
+        if manager == PackageManager::Apt {
+            let args = self.wrap(&["sudo", "apt-get", "update", "-qq"]);
+            let (status, _) = runner
+                .execute_command(self.tool.program(), &super::str_args(&args), false)
+                .await?;
+            if !status.success() {
+                return Err(anyhow::anyhow!("apt-get update failed"));
+            }
+        }
+
+        let command: &[&str] = match manager {
+            PackageManager::Dnf5 => &["sudo", "dnf5", "upgrade", "-y"],
+            PackageManager::Dnf => &["sudo", "dnf", "upgrade", "-y"],
+            PackageManager::Apt => &[
+                "sudo",
+                "env",
+                "DEBIAN_FRONTEND=noninteractive",
+                "apt-get",
+                "upgrade",
+                "-y",
+            ],
+            PackageManager::Pacman => &["sudo", "pacman", "-Syu", "--noconfirm"],
+        };
+
+        let args = self.wrap(command);
+        let (status, _) = runner
+            .execute_command(self.tool.program(), &super::str_args(&args), false)
+            .await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("{} upgrade failed", manager.command()));
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, error: anyhow::Error) {
+        self.outcome = ItemOutcome::Failed;
+        self.error = Some(format!("{:#}", error));
+    }
+}
+
+/// Updates packages inside toolbox and distrobox containers
+///
+/// Every container is checked and upgraded independently, so one failing
+/// container does not stop the others.
+#[derive(Debug, Default)]
+pub(crate) struct ContainersBackend {
+    // Containers found by the last check
+    containers: Vec<Container>,
+}
+
+impl ContainersBackend {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lists toolbox containers, then distrobox containers not already listed
+    async fn enumerate(runner: &mut CommandRunner) -> Vec<Container> {
+        let mut containers: Vec<Container> = Vec::new();
+
+        let listings = [
+            (Tool::Toolbox, ["list", "--containers"]),
+            (Tool::Distrobox, ["list", "--no-color"]),
+        ];
+        for (tool, args) in listings {
+            let Some(output) = runner.capture_command(tool.program(), &args).await else {
+                continue;
+            };
+            let output = String::from_utf8_lossy(&output.stdout);
+            let names = match tool {
+                Tool::Toolbox => parse::parse_toolbox_list(&output),
+                Tool::Distrobox => parse::parse_distrobox_list(&output),
+            };
+
+            for name in names {
+                if !containers.iter().any(|container| container.name == name) {
+                    containers.push(Container::new(name, tool));
+                }
+            }
+        }
+
+        containers
+    }
+
+    /// Fails with the names of failed containers, if any
+    fn failures(&self) -> Result<()> {
+        let failed: Vec<&str> = self
+            .containers
+            .iter()
+            .filter(|container| container.outcome == ItemOutcome::Failed)
+            .map(|container| container.name.as_str())
+            .collect();
+
+        if failed.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow::anyhow!(
+            "Updating containers failed: {}",
+            failed.join(", ")
+        ))
+    }
+}
+
+#[async_trait]
+impl UpdateBackend for ContainersBackend {
+    fn name(&self) -> &'static str {
+        "Containers"
+    }
+
+    async fn detect(&mut self, runner: &mut CommandRunner) -> bool {
+        runner.is_available("toolbox").await || runner.is_available("distrobox").await
+    }
+
+    async fn check(&mut self, runner: &mut CommandRunner) -> Result<CheckStatus> {
+        runner
+            .reporter()
+            .progress("Checking for container updates...");
+
+        self.containers = Self::enumerate(runner).await;
+        if self.containers.is_empty() {
+            runner
+                .reporter()
+                .progress("No toolbox or distrobox containers found.");
+            return Ok(CheckStatus::UpToDate);
+        }
+
+        let mut pending = Vec::new();
+        for container in &mut self.containers {
+            runner
+                .reporter()
+                .progress(&format!("Checking container {}...", container.name));
+            match container.check(runner).await {
+                Ok(()) => pending.extend(container.pending.iter().cloned()),
+                Err(e) => {
+                    runner.reporter().warn(&format!(
+                        "Could not check container {}: {:#}",
+                        container.name, e
+                    ));
+                    container.fail(e);
+                }
+            }
+        }
+
+        if pending.is_empty() {
+            self.failures()?;
+            runner
+                .reporter()
+                .progress("No container updates available.");
+            return Ok(CheckStatus::UpToDate);
+        }
+
+        runner
+            .reporter()
+            .progress("Container updates are available.");
+        Ok(CheckStatus::Available(pending))
+    }
+
+    async fn preview(&mut self, runner: &mut CommandRunner) -> Result<TransactionSummary> {
+        runner
+            .reporter()
+            .progress("Container dry run: no packages will be upgraded.");
+
+        let mut upgrade = 0;
+        for container in &mut self.containers {
+            if !container.pending.is_empty() {
+                container.outcome = ItemOutcome::Previewed;
+                upgrade += container.pending.len() as u32;
+            }
+        }
+
+        self.failures()?;
+        Ok(TransactionSummary {
+            upgrade,
+            ..TransactionSummary::default()
+        })
+    }
+
+    async fn apply(&mut self, runner: &mut CommandRunner) -> Result<bool> {
+        let mut any_updated = false;
+
+        for container in &mut self.containers {
+            let Some(manager) = container.manager else {
+                continue;
+            };
+            if container.pending.is_empty() {
+                continue;
+            }
+
+            runner.reporter().progress(&format!(
+                "Updating container {} with {}...",
+                container.name,
+                manager.command()
+            ));
+            match container.upgrade(runner, manager).await {
+                Ok(()) => {
+                    container.outcome = ItemOutcome::Updated;
+                    any_updated = true;
+                }
+                Err(e) => {
+                    runner
+                        .reporter()
+                        .error(&format!("Container {} failed: {:#}", container.name, e));
+                    container.fail(e);
+                }
+            }
+        }
+
+        self.failures()?;
+        Ok(any_updated)
+    }
+
+    fn items(&self) -> Vec<ItemReport> {
+        self.containers
+            .iter()
+            .map(|container| ItemReport {
+                name: container.name.clone(),
+                outcome: container.outcome,
+                error: container.error.clone(),
+                detail: container.detail.clone(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,216 @@
+//! Parsers for container listings and package managers running inside containers
+
+use crate::backend::PendingUpdate;
+
+/// Package managers that can be driven inside a container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PackageManager {
+    Dnf5,
+    Dnf,
+    Apt,
+    Pacman,
+}
+
+impl PackageManager {
+    /// Command name, as shown in progress messages
+    pub(crate) fn command(self) -> &'static str {
+        match self {
+            Self::Dnf5 => "dnf5",
+            Self::Dnf => "dnf",
+            Self::Apt => "apt-get",
+            Self::Pacman => "pacman",
+        }
+    }
+}
+
+/// Identity of a container's distribution, read from its os-release file
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct OsRelease {
+    pub(crate) id: String,
+    pub(crate) id_like: Vec<String>,
+    pub(crate) version_id: Option<String>,
+}
+
+impl OsRelease {
+    /// Picks the package manager for the distribution or one it derives from
+    pub(crate) fn package_manager(&self) -> Option<PackageManager> {
+        let family =
+            std::iter::once(self.id.as_str()).chain(self.id_like.iter().map(String::as_str));
+
+        for id in family {
+            match id {
+                // Fedora switched its default package manager to DNF5 in release 41
+                "fedora" if self.major_version().is_some_and(|version| version >= 41) => {
+                    return Some(PackageManager::Dnf5);
+                }
+                "fedora" | "rhel" | "centos" => return Some(PackageManager::Dnf),
+                "debian" | "ubuntu" => return Some(PackageManager::Apt),
+                "arch" => return Some(PackageManager::Pacman),
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    fn major_version(&self) -> Option<u32> {
+        self.version_id.as_deref()?.split('.').next()?.parse().ok()
+    }
+}
+
+/// Parses the `KEY=value` lines of an os-release file
+pub(crate) fn parse_os_release(output: &str) -> OsRelease {
+    let mut release = OsRelease::default();
+
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim() {
+            "ID" => release.id = value.to_string(),
+            "ID_LIKE" => release.id_like = value.split_whitespace().map(str::to_string).collect(),
+            "VERSION_ID" => release.version_id = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    release
+}
+
+/// Parses container names from `toolbox list --containers`
+pub(crate) fn parse_toolbox_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with("CONTAINER ID"))
+        // IDs and names never contain spaces, unlike the creation time
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parses container names from `distrobox list --no-color`
+pub(crate) fn parse_distrobox_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split('|').nth(1))
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && *name != "NAME")
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parses `apt list --upgradable` output
+pub(crate) fn parse_apt_upgradable(output: &str) -> Vec<PendingUpdate> {
+    output
+        .lines()
+        .filter_map(|line| {
+            // curl/noble-updates 8.5.0-2ubuntu10.6 amd64 [upgradable from: 8.5.0-2ubuntu10.5]
+            let (package, current) = line.split_once(" [upgradable from: ")?;
+            let mut columns = package.split_whitespace();
+            let (name, suite) = columns.next()?.split_once('/')?;
+            let new_version = columns.next()?;
+            Some(PendingUpdate {
+                name: name.to_string(),
+                current_version: Some(current.trim_end_matches(']').to_string()),
+                new_version: Some(new_version.to_string()),
+                origin: suite.split(',').next().map(str::to_string),
+            })
+        })
+        .collect()
+}
+
+/// Parses `pacman -Qu` output, skipping packages marked as ignored
+pub(crate) fn parse_pacman_upgrades(output: &str) -> Vec<PendingUpdate> {
+    output
+        .lines()
+        .filter(|line| !line.ends_with("[ignored]"))
+        .filter_map(|line| {
+            // linux 6.12.7.arch1-1 -> 6.12.8.arch1-1
+            let mut columns = line.split_whitespace();
+            let (Some(name), Some(current), Some("->"), Some(new)) = (
+                columns.next(),
+                columns.next(),
+                columns.next(),
+                columns.next(),
+            ) else {
+                return None;
+            };
+            Some(PendingUpdate {
+                name: name.to_string(),
+                current_version: Some(current.to_string()),
+                new_version: Some(new.to_string()),
+                origin: None,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOOLBOX_LIST: &str = include_str!("../../../tests/fixtures/containers/toolbox-list.txt");
+    const DISTROBOX_LIST: &str =
+        include_str!("../../../tests/fixtures/containers/distrobox-list.txt");
+    const APT_UPGRADABLE: &str =
+        include_str!("../../../tests/fixtures/containers/apt-list-upgradable.txt");
+    const PACMAN_QU: &str = include_str!("../../../tests/fixtures/containers/pacman-qu.txt");
+    const OS_RELEASE_FEDORA: &str =
+        include_str!("../../../tests/fixtures/containers/os-release-fedora.txt");
+    const OS_RELEASE_UBUNTU: &str =
+        include_str!("../../../tests/fixtures/containers/os-release-ubuntu.txt");
+
+    #[test]
+    fn parses_container_listings() {
+        assert_eq!(
+            parse_toolbox_list(TOOLBOX_LIST),
+            ["fedora-toolbox-41", "rust-dev"]
+        );
+        assert_eq!(
+            parse_distrobox_list(DISTROBOX_LIST),
+            ["ubuntu-dev", "arch", "rust-dev"]
+        );
+        assert!(parse_toolbox_list("").is_empty());
+    }
+
+    #[test]
+    fn detects_package_manager_from_os_release() {
+        let fedora = parse_os_release(OS_RELEASE_FEDORA);
+        assert_eq!(fedora.version_id.as_deref(), Some("41"));
+        assert_eq!(fedora.package_manager(), Some(PackageManager::Dnf5));
+
+        let ubuntu = parse_os_release(OS_RELEASE_UBUNTU);
+        assert_eq!(ubuntu.id_like, ["debian"]);
+        assert_eq!(ubuntu.package_manager(), Some(PackageManager::Apt));
+
+        let older = parse_os_release("ID=fedora\nVERSION_ID=40\n");
+        assert_eq!(older.package_manager(), Some(PackageManager::Dnf));
+        let rocky = parse_os_release("ID=\"rocky\"\nID_LIKE=\"rhel centos fedora\"\n");
+        assert_eq!(rocky.package_manager(), Some(PackageManager::Dnf));
+        assert_eq!(parse_os_release("ID=alpine\n").package_manager(), None);
+    }
+
+    #[test]
+    fn parses_apt_upgradable_packages() {
+        let updates = parse_apt_upgradable(APT_UPGRADABLE);
+
+        assert_eq!(updates.len(), 3);
+        assert_eq!(updates[0].name, "curl");
+        assert_eq!(
+            updates[0].current_version.as_deref(),
+            Some("8.5.0-2ubuntu10.5")
+        );
+        assert_eq!(updates[0].new_version.as_deref(), Some("8.5.0-2ubuntu10.6"));
+        assert_eq!(updates[0].origin.as_deref(), Some("noble-updates"));
+    }
+
+    #[test]
+    fn parses_pacman_upgrades() {
+        let updates = parse_pacman_upgrades(PACMAN_QU);
+        let names: Vec<_> = updates.iter().map(|u| u.name.as_str()).collect();
+
+        assert_eq!(names, ["linux", "mesa"]);
+        assert_eq!(updates[1].new_version.as_deref(), Some("1:24.3.3-1"));
+    }
+}
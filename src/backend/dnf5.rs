@@ -0,0 +1,822 @@
+mod parse;
+
+use super::{
+    CheckStatus, PendingUpdate, RebootRequirement, TransactionSummary, UpdateBackend, UpdateStage,
+    show_updates, str_args,
+};
+use crate::config::{Dnf5Config, SnapshotConfig};
+use crate::output::Reporter;
+use crate::preflight::SpaceRequirement;
+use crate::rollback::UndoStep;
+use crate::runner::CommandRunner;
+use crate::size;
+use crate::snapshot::{self, Snapshot};
+use anyhow::Result;
+use async_trait::async_trait;
+use colored::*;
+use parse::{Advisory, KERNEL_QUERY_FORMAT, PendingPackage, RPM_QUERY_FORMAT};
+
+pub(crate) use parse::parse_check_upgrade;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::process::ExitStatus;
+
+/// Where DNF5 keeps downloaded packages
+const CACHE_DIR: &str = "/var/cache/libdnf5";
+
+/// Space on /boot for one new kernel's initramfs and boot entry
+const KERNEL_BOOT_SPACE: u64 = 128 * 1024 * 1024;
+
+/// How DNF5 updates are applied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum UpdateMode {
+    /// Apply updates right away
+    #[default]
+    Immediate,
+    /// Prepare updates to be applied on the next reboot
+    Offline,
+}
+
+/// Whether the DNF5 cleanup removes unused dependencies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Autoremove {
+    /// Remove them after confirming in interactive runs; leave them otherwise
+    #[default]
+    Ask,
+    /// Remove them without asking, also in unattended runs
+    Always,
+    /// Never remove them; `dnf5 autoremove` can still be run by hand
+    Never,
+}
+
+/// Lowest advisory severity applied by `--advisory-severity`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AdvisorySeverity {
+    Low,
+    Moderate,
+    Important,
+    Critical,
+}
+
+impl AdvisorySeverity {
+    const ALL: [Self; 4] = [Self::Critical, Self::Important, Self::Moderate, Self::Low];
+
+    /// Name as printed by DNF5
+    fn label(self) -> &'static str {
+        match self {
+            Self::Low => "Low",
+            Self::Moderate => "Moderate",
+            Self::Important => "Important",
+            Self::Critical => "Critical",
+        }
+    }
+
+    /// Value for `--advisory-severities` selecting this severity and all higher ones
+    fn and_higher(self) -> String {
+        Self::ALL
+            .iter()
+            .filter(|severity| **severity >= self)
+            .map(|severity| severity.label().to_lowercase())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Counts security advisories by severity, such as `1 critical, 2 important`
+fn count_by_severity(advisories: &[Advisory]) -> String {
+    let mut counts: Vec<String> = Vec::new();
+    for severity in AdvisorySeverity::ALL
+        .iter()
+        .map(|s| s.label())
+        .chain(["None"])
+    {
+        let count = advisories
+            .iter()
+            .filter(|advisory| advisory.severity == severity)
+            .count();
+        if count > 0 {
+            counts.push(format!("{} {}", count, severity.to_lowercase()));
+        }
+    }
+    counts.join(", ")
+}
+
+/// Prints security advisories with their severity and CVE IDs
+fn show_advisories(reporter: &Reporter, advisories: &[Advisory]) {
+    if !reporter.is_text() {
+        return;
+    }
+
+    println!(
+        "{}",
+        format!("Security advisories ({}):", advisories.len())
+            .blue()
+            .bold()
+    );
+    let width = advisories.iter().map(|a| a.name.len()).max().unwrap_or(0);
+    for advisory in advisories {
+        let severity = format!("{:9}", advisory.severity);
+        let severity = match advisory.severity.as_str() {
+            "Critical" => severity.red().bold(),
+            "Important" => severity.red(),
+            "Moderate" => severity.yellow(),
+            _ => severity.normal(),
+        };
+        println!(
+            "  {:width$}  {}  {}",
+            advisory.name,
+            severity,
+            advisory.cves.join(", ").dimmed(),
+            width = width
+        );
+    }
+    println!();
+}
+
+/// Runs dnf5 with sudo in the C locale, so the summaries and tables it prints parse the same
+/// for every user
+pub(crate) async fn run_dnf5(
+    runner: &mut CommandRunner,
+    args: &[&str],
+) -> Result<(ExitStatus, String)> {
+    let mut env_args = vec!["LC_ALL=C", "dnf5"];
+    env_args.extend_from_slice(args);
+    let (status, output) = runner.execute_command("env", &env_args, true).await?;
+    Ok((status, output.to_string()))
+}
+
+/// Newest transaction in the DNF5 history, or None when it cannot be read
+///
+/// Only the leading transaction IDs are read, which do not depend on the locale.
+async fn latest_transaction(runner: &mut CommandRunner) -> Option<u64> {
+    let output = runner.capture_command("dnf5", &["history", "list"]).await?;
+    parse::parse_latest_transaction(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Resolves undoing a transaction with `--assumeno`, failing when DNF5 cannot do it
+///
+/// Undoing reinstalls the versions the transaction replaced, which the
+/// repositories may no longer offer.
+pub(crate) async fn resolve_undo(
+    runner: &mut CommandRunner,
+    id: u64,
+) -> Result<TransactionSummary> {
+    let id = id.to_string();
+    let (_, output) = run_dnf5(runner, &["history", "undo", &id, "--assumeno"]).await?;
+
+    parse::parse_transaction_summary(&output).ok_or_else(|| {
+        anyhow::anyhow!(
+            "DNF5 cannot undo transaction {}; the packages it replaced may no longer be available",
+            id
+        )
+    })
+}
+
+/// Updates system packages through DNF5 with sudo
+#[derive(Debug)]
+pub(crate) struct Dnf5Backend {
+    interactive: bool,
+    config: Dnf5Config,
+    // Upgrades found by the last check
+    pending: Vec<PendingPackage>,
+    // Mode used by the last successful apply, consulted by post_apply
+    applied_mode: Option<UpdateMode>,
+    // Security advisories for the pending upgrades, found by the last check
+    advisories: Vec<Advisory>,
+    // What the cleanup did, reported in the summary
+    notes: Vec<String>,
+    snapshot: SnapshotConfig,
+    // Snapshots taken around the last transaction
+    snapshots: Vec<Snapshot>,
+    // Transactions added to the DNF5 history by the last apply
+    undo: Vec<UndoStep>,
+}
+
+impl Dnf5Backend {
+    pub(crate) fn new(interactive: bool, config: Dnf5Config) -> Self {
+        Self {
+            interactive,
+            config,
+            pending: Vec::new(),
+            applied_mode: None,
+            advisories: Vec::new(),
+            notes: Vec::new(),
+            snapshot: SnapshotConfig::default(),
+            snapshots: Vec::new(),
+            undo: Vec::new(),
+        }
+    }
+
+    /// Snapshots the root filesystem around each transaction as configured
+    pub(crate) fn with_snapshots(mut self, config: SnapshotConfig) -> Self {
+        self.snapshot = config;
+        self
+    }
+
+    /// Takes the snapshot that a transaction about to run can be rolled back to
+    async fn snapshot_before(&mut self, runner: &mut CommandRunner) -> Result<()> {
+        if let Some(pre) = snapshot::before(runner, &self.snapshot).await? {
+            self.snapshots.push(pre);
+        }
+        Ok(())
+    }
+
+    /// Pairs the snapshot taken before a finished transaction with one taken now
+    ///
+    /// Offline transactions run during the next boot, so they get no post snapshot.
+    async fn snapshot_after(&mut self, runner: &mut CommandRunner) {
+        if self.applied_mode != Some(UpdateMode::Immediate) || self.snapshots.len() != 1 {
+            return;
+        }
+        if let Some(pre) = self.snapshots.first().cloned()
+            && let Some(post) = snapshot::after(runner, &self.snapshot, &pre).await
+        {
+            self.snapshots.push(post);
+        }
+    }
+
+    /// Appends the configured advisory filters to `base`
+    fn with_advisory_filters(&self, base: &[&str]) -> Vec<String> {
+        let mut args: Vec<String> = base.iter().map(|arg| arg.to_string()).collect();
+        if self.config.security {
+            args.push("--security".to_string());
+        }
+        if let Some(severity) = self.config.advisory_severity {
+            args.push(format!("--advisory-severities={}", severity.and_higher()));
+        }
+        args
+    }
+
+    /// Appends the configured advisory filters and package excludes to `base`
+    fn with_excludes(&self, base: &[&str]) -> Vec<String> {
+        let mut args = self.with_advisory_filters(base);
+        args.extend(
+            self.config
+                .exclude
+                .iter()
+                .map(|pattern| format!("--exclude={}", pattern)),
+        );
+        args
+    }
+
+    /// Arguments for a `dnf5 upgrade` transaction, with filters, excludes and extra arguments
+    fn upgrade_args(&self, base: &[&str]) -> Vec<String> {
+        let mut args = self.with_excludes(base);
+        args.extend(self.config.extra_args.iter().cloned());
+        args
+    }
+
+    /// Records the transaction an upgrade added to the DNF5 history, for `rollback last`
+    ///
+    /// `before` is the newest transaction before the upgrade, so an upgrade that
+    /// had nothing to do never claims an older transaction.
+    async fn record_transaction(&mut self, runner: &mut CommandRunner, before: Option<u64>) {
+        if let Some(id) = latest_transaction(runner).await
+            && before.is_none_or(|before| id > before)
+        {
+            self.undo.push(UndoStep::Dnf5Transaction { id });
+        }
+    }
+
+    /// Resolves an upgrade with `--assumeno` and reads its transaction summary
+    async fn resolve(
+        &self,
+        runner: &mut CommandRunner,
+        base: &[&str],
+    ) -> Result<TransactionSummary> {
+        // --assumeno makes dnf5 abort after printing the summary, so the exit status is not useful
+        let args = self.upgrade_args(base);
+        let (_, output) = run_dnf5(runner, &str_args(&args)).await?;
+
+        parse::parse_transaction_summary(&output)
+            .ok_or_else(|| anyhow::anyhow!("Could not read the DNF5 transaction summary"))
+    }
+
+    /// Looks up installed versions of the pending packages
+    async fn pending_updates(&self, runner: &mut CommandRunner) -> Vec<PendingUpdate> {
+        let keys: Vec<String> = self.pending.iter().map(PendingPackage::key).collect();
+
+        let mut args = vec!["-q", "--queryformat", RPM_QUERY_FORMAT];
+        args.extend(keys.iter().map(String::as_str));
+        let mut installed = match runner.capture_command("rpm", &args).await {
+            Some(output) => {
+                parse::parse_installed_versions(&String::from_utf8_lossy(&output.stdout))
+            }
+            None => HashMap::new(),
+        };
+
+        self.pending
+            .iter()
+            .zip(keys)
+            .map(|(package, key)| PendingUpdate {
+                current_version: installed.remove(&key),
+                name: key,
+                new_version: Some(package.evr.clone()),
+                origin: Some(package.repo.clone()),
+            })
+            .collect()
+    }
+
+    /// Lists the security advisories fixed by the pending upgrades
+    async fn list_advisories(&self, runner: &mut CommandRunner) -> Result<Vec<Advisory>> {
+        let args = self.with_advisory_filters(&["advisory", "list", "--updates"]);
+        let (status, list) = run_dnf5(runner, &str_args(&args)).await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("DNF5 advisory list failed"));
+        }
+
+        let mut args = args;
+        args.push("--with-cve".to_string());
+        let (_, cve_list) = run_dnf5(runner, &str_args(&args)).await?;
+
+        Ok(parse::parse_advisories(&list, &cve_list)
+            .into_iter()
+            .filter(|advisory| advisory.kind == "security")
+            .collect())
+    }
+
+    /// Asks a yes/no question in interactive runs, returning `default` otherwise
+    fn confirm(&self, reporter: &Reporter, question: &str, default: bool) -> Result<bool> {
+        if !self.interactive {
+            return Ok(default);
+        }
+
+        reporter.confirm(&format!("\n{}", question), default)
+    }
+
+    /// Removes orphaned dependencies after previewing them, returning the space freed
+    async fn autoremove(&mut self, runner: &mut CommandRunner) -> Result<u64> {
+        if self.config.autoremove == Autoremove::Never {
+            return Ok(0);
+        }
+
+        let (_, output) = run_dnf5(runner, &["autoremove", "--assumeno"]).await?;
+        let preview = parse::parse_transaction_summary(&output)
+            .ok_or_else(|| anyhow::anyhow!("Could not read the DNF5 autoremove summary"))?;
+        if preview.remove == 0 {
+            return Ok(0);
+        }
+
+        let question = format!(
+            "Remove {} unused dependencies with dnf5 autoremove?",
+            preview.remove
+        );
+        // Unattended runs only remove packages when configured to
+        let confirmed = match self.config.autoremove {
+            Autoremove::Always => true,
+            _ => self.interactive && self.confirm(runner.reporter(), &question, true)?,
+        };
+        if !confirmed {
+            self.notes.push(format!(
+                "{} unused dependencies were left installed",
+                preview.remove
+            ));
+            return Ok(0);
+        }
+
+        let (status, _) = runner
+            .execute_command("dnf5", &["autoremove", "-y"], true)
+            .await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("DNF5 autoremove failed"));
+        }
+
+        // A negative delta is the installed size freed by the removal
+        Ok(preview
+            .installed_size_delta
+            .filter(|delta| *delta < 0)
+            .map_or(0, i64::unsigned_abs))
+    }
+
+    /// Finds installed kernels beyond the keep count and offers to remove them
+    async fn prune_kernels(&mut self, runner: &mut CommandRunner) -> Result<()> {
+        let Some(output) = runner
+            .capture_command(
+                "rpm",
+                &["-q", "--queryformat", KERNEL_QUERY_FORMAT, "kernel-core"],
+            )
+            .await
+        else {
+            return Ok(());
+        };
+        let installed = String::from_utf8_lossy(&output.stdout).into_owned();
+        // Without knowing the running kernel, nothing can be removed safely
+        let Some(running) = runner.capture_command("uname", &["-r"]).await else {
+            return Ok(());
+        };
+        let running = String::from_utf8_lossy(&running.stdout).trim().to_string();
+
+        let removable = parse::removable_kernels(&installed, &running, self.config.keep_kernels);
+        if removable.is_empty() {
+            return Ok(());
+        }
+
+        let question = format!(
+            "Remove {} kernels beyond the newest {}: {}?",
+            removable.len(),
+            self.config.keep_kernels,
+            removable.join(", ")
+        );
+        if !self.confirm(runner.reporter(), &question, false)? {
+            self.notes.push(format!(
+                "{} kernels are installed beyond the keep count of {}: {}",
+                removable.len(),
+                self.config.keep_kernels,
+                removable.join(", ")
+            ));
+            return Ok(());
+        }
+
+        // Removing kernel-core also removes the kernel and module packages that need it
+        let packages: Vec<String> = removable
+            .iter()
+            .map(|version| format!("kernel-core-{}", version))
+            .collect();
+        let mut args = vec!["remove", "-y"];
+        args.extend(str_args(&packages));
+        let (status, _) = runner.execute_command("dnf5", &args, true).await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("Removing old kernels failed"));
+        }
+
+        self.notes
+            .push(format!("Removed old kernels: {}", removable.join(", ")));
+        Ok(())
+    }
+
+    /// Removes unused dependencies, cached packages and old kernels when configured
+    async fn clean(&mut self, runner: &mut CommandRunner) -> Result<()> {
+        if !self.config.cleanup {
+            return Ok(());
+        }
+        // A new transaction could invalidate the prepared offline update
+        if self.applied_mode == Some(UpdateMode::Offline) {
+            runner
+                .reporter()
+                .warn("Skipping DNF5 cleanup until the offline update has been applied.");
+            return Ok(());
+        }
+
+        runner.reporter().progress("Cleaning up after DNF5...");
+
+        let removed = self.autoremove(runner).await?;
+
+        let (status, output) = run_dnf5(runner, &["clean", "packages"]).await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("DNF5 clean packages failed"));
+        }
+        let cache = parse::parse_clean_total(&output).unwrap_or(0);
+
+        if removed + cache > 0 {
+            self.notes.push(format!(
+                "Reclaimed {} (unused dependencies {}, package cache {})",
+                size::format_size(removed + cache),
+                size::format_size(removed),
+                size::format_size(cache)
+            ));
+        }
+
+        self.prune_kernels(runner).await
+    }
+
+    /// Asks the user for the update mode in interactive runs
+    fn choose_mode(&self, reporter: &Reporter) -> Result<UpdateMode> {
+        if !self.interactive {
+            return Ok(self.config.mode);
+        }
+
+        let answer = reporter.ask(
+            "\nChoose update mode:\n\
+             1. Immediate update (type 'now')\n\
+             2. Offline update (press Enter)",
+        )?;
+
+        if answer == "now" {
+            Ok(UpdateMode::Immediate)
+        } else {
+            Ok(UpdateMode::Offline)
+        }
+    }
+}
+
+#[async_trait]
+impl UpdateBackend for Dnf5Backend {
+    fn name(&self) -> &'static str {
+        "DNF5"
+    }
+
+    fn required(&self) -> bool {
+        true
+    }
+
+    async fn detect(&mut self, runner: &mut CommandRunner) -> bool {
+        runner.is_available("dnf5").await
+    }
+
+    async fn check(&mut self, runner: &mut CommandRunner) -> Result<CheckStatus> {
+        runner.reporter().progress("Checking for DNF5 updates...");
+
+        // Check for updates - exit code 100 means updates are available
+        let args = self.with_excludes(&["--refresh", "check-upgrade"]);
+        let (status, output) = run_dnf5(runner, &str_args(&args)).await?;
+
+        match status.code() {
+            Some(0) => {
+                self.pending.clear();
+                self.advisories.clear();
+                runner.reporter().progress("No DNF5 updates available.");
+                return Ok(CheckStatus::UpToDate);
+            }
+            Some(100) => {}
+            _ => return Err(anyhow::anyhow!("DNF5 update check failed")),
+        }
+
+        self.pending = parse::parse_check_upgrade(&output);
+
+        runner.reporter().progress("DNF5 updates are available.");
+        let updates = self.pending_updates(runner).await;
+        if !updates.is_empty() {
+            show_updates(
+                runner.reporter(),
+                &format!("Pending DNF5 updates ({}):", updates.len()),
+                &updates,
+            );
+        }
+
+        // Advisories only add detail, so the updates can still be applied without them
+        self.advisories = match self.list_advisories(runner).await {
+            Ok(advisories) => advisories,
+            Err(e) => {
+                runner
+                    .reporter()
+                    .warn(&format!("Could not list DNF5 advisories: {:#}", e));
+                Vec::new()
+            }
+        };
+        if !self.advisories.is_empty() {
+            show_advisories(runner.reporter(), &self.advisories);
+        }
+
+        Ok(CheckStatus::Available(updates))
+    }
+
+    async fn preview(&mut self, runner: &mut CommandRunner) -> Result<TransactionSummary> {
+        runner
+            .reporter()
+            .progress("Resolving DNF5 transaction without applying it...");
+        self.resolve(runner, &["upgrade", "--assumeno"]).await
+    }
+
+    async fn required_space(
+        &mut self,
+        runner: &mut CommandRunner,
+        stage: &UpdateStage,
+    ) -> Result<Vec<SpaceRequirement>> {
+        runner
+            .reporter()
+            .progress("Estimating the disk space DNF5 needs...");
+
+        let (summary, kernels) = match stage {
+            UpdateStage::ApplyDownloaded(manifest) => {
+                let summary = self
+                    .resolve(runner, &["--cacheonly", "upgrade", "--assumeno"])
+                    .await?;
+                let kernels = manifest
+                    .packages(self.name())
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|update| update.name.starts_with("kernel-core."))
+                    .count();
+                (summary, kernels)
+            }
+            _ => {
+                let summary = self.resolve(runner, &["upgrade", "--assumeno"]).await?;
+                let kernels = self
+                    .pending
+                    .iter()
+                    .filter(|package| package.name == "kernel-core")
+                    .count();
+                (summary, kernels)
+            }
+        };
+
+        let mut requirements = Vec::new();
+        if !matches!(stage, UpdateStage::ApplyDownloaded(_)) {
+            requirements.push(SpaceRequirement::new(
+                CACHE_DIR,
+                summary.download_size.unwrap_or(0),
+                "package downloads",
+            ));
+        }
+        if !matches!(stage, UpdateStage::DownloadOnly) {
+            let installed = summary.installed_size_delta.unwrap_or(0).max(0) as u64;
+            requirements.push(SpaceRequirement::new("/", installed, "installed packages"));
+            requirements.push(SpaceRequirement::new(
+                "/boot",
+                kernels as u64 * KERNEL_BOOT_SPACE,
+                "new kernels",
+            ));
+        }
+        Ok(requirements)
+    }
+
+    async fn apply(&mut self, runner: &mut CommandRunner) -> Result<bool> {
+        let mode = self.choose_mode(runner.reporter())?;
+        self.snapshot_before(runner).await?;
+
+        match mode {
+            UpdateMode::Immediate => {
+                runner
+                    .reporter()
+                    .progress("Performing immediate DNF5 update...");
+                let before = latest_transaction(runner).await;
+                let args = self.upgrade_args(&["upgrade", "-y"]);
+                let (status, _) = runner
+                    .execute_command("dnf5", &str_args(&args), true)
+                    .await?;
+                if !status.success() {
+                    return Err(anyhow::anyhow!("DNF5 update failed"));
+                }
+                self.record_transaction(runner, before).await;
+            }
+            UpdateMode::Offline => {
+                runner
+                    .reporter()
+                    .progress("Preparing offline DNF5 update...");
+                let args = self.upgrade_args(&["upgrade", "--offline", "-y"]);
+                let (status, _) = runner
+                    .execute_command("dnf5", &str_args(&args), true)
+                    .await?;
+                if !status.success() {
+                    return Err(anyhow::anyhow!("DNF5 offline update preparation failed"));
+                }
+                runner
+                    .reporter()
+                    .warn("Offline update prepared. Changes will be applied on next reboot.");
+            }
+        }
+
+        self.applied_mode = Some(mode);
+        Ok(true)
+    }
+
+    fn supports_download(&self) -> bool {
+        true
+    }
+
+    async fn download(&mut self, runner: &mut CommandRunner) -> Result<()> {
+        runner.reporter().progress("Downloading DNF5 updates...");
+        let args = self.upgrade_args(&["upgrade", "--downloadonly", "-y"]);
+        let (status, _) = runner
+            .execute_command("dnf5", &str_args(&args), true)
+            .await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("DNF5 download failed"));
+        }
+        Ok(())
+    }
+
+    async fn apply_downloaded(
+        &mut self,
+        runner: &mut CommandRunner,
+        downloaded: &[PendingUpdate],
+    ) -> Result<bool> {
+        runner
+            .reporter()
+            .progress("Checking the downloaded DNF5 updates...");
+
+        // The cached metadata tells what an upgrade would need now
+        let args = self.with_excludes(&["--cacheonly", "check-upgrade"]);
+        let (status, output) = run_dnf5(runner, &str_args(&args)).await?;
+        match status.code() {
+            Some(0) => {
+                runner
+                    .reporter()
+                    .progress("The downloaded DNF5 updates are already installed.");
+                return Ok(false);
+            }
+            Some(100) => {}
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Could not read the cached DNF5 metadata. Run `update --download-only` again"
+                ));
+            }
+        }
+
+        let missing: Vec<String> = parse::parse_check_upgrade(&output)
+            .iter()
+            .map(PendingPackage::key)
+            .filter(|key| !downloaded.iter().any(|update| &update.name == key))
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow::anyhow!(
+                "The DNF5 download cache is incomplete, these updates were not downloaded: {}. Run `update --download-only` again",
+                missing.join(", ")
+            ));
+        }
+
+        self.snapshot_before(runner).await?;
+        runner
+            .reporter()
+            .progress("Applying downloaded DNF5 updates...");
+        let before = latest_transaction(runner).await;
+        let args = self.upgrade_args(&["--cacheonly", "upgrade", "-y"]);
+        let (status, _) = runner
+            .execute_command("dnf5", &str_args(&args), true)
+            .await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!(
+                "DNF5 could not apply the downloaded updates from the cache"
+            ));
+        }
+        self.record_transaction(runner, before).await;
+
+        self.applied_mode = Some(UpdateMode::Immediate);
+        Ok(true)
+    }
+
+    async fn post_apply(&mut self, runner: &mut CommandRunner) -> Result<RebootRequirement> {
+        if self.applied_mode == Some(UpdateMode::Offline) {
+            return Ok(RebootRequirement::Reboot);
+        }
+
+        // Check if reboot is needed - exit code 1 means a reboot is required
+        let status = match runner
+            .execute_command("dnf5", &["needs-restarting"], true)
+            .await
+        {
+            Ok((status, _)) => status,
+            Err(e) => {
+                let reporter = runner.reporter();
+                reporter.warn("Warning: Could not determine if restart is needed.");
+                reporter.error(&format!("Error checking restart status: {}", e));
+                return Ok(RebootRequirement::None);
+            }
+        };
+
+        match status.code() {
+            Some(1) => return Ok(RebootRequirement::Reboot),
+            Some(0) => {}
+            _ => {
+                runner
+                    .reporter()
+                    .warn("Warning: Could not determine if restart is needed.");
+                return Ok(RebootRequirement::None);
+            }
+        }
+
+        // No reboot needed, but updated libraries may still be in use by services
+        match run_dnf5(runner, &["needs-restarting", "-s"]).await {
+            Ok((_, output)) => {
+                let services = parse::parse_restart_services(&output);
+                if services.is_empty() {
+                    Ok(RebootRequirement::None)
+                } else {
+                    Ok(RebootRequirement::Services(services))
+                }
+            }
+            Err(e) => {
+                let reporter = runner.reporter();
+                reporter.warn("Warning: Could not determine which services need restarting.");
+                reporter.error(&format!("Error listing services to restart: {}", e));
+                Ok(RebootRequirement::None)
+            }
+        }
+    }
+
+    async fn cleanup(&mut self, runner: &mut CommandRunner) -> Result<()> {
+        let result = self.clean(runner).await;
+        // Taken last so that the pair also covers what the cleanup removed
+        self.snapshot_after(runner).await;
+        result
+    }
+
+    fn notes(&self) -> Vec<String> {
+        let mut notes = Vec::new();
+        if !self.advisories.is_empty() {
+            notes.push(format!(
+                "{} security advisories: {}",
+                if self.applied_mode.is_some() {
+                    "Applied"
+                } else {
+                    "Pending"
+                },
+                count_by_severity(&self.advisories)
+            ));
+        }
+        notes.extend(snapshot::describe(&self.snapshots));
+        notes.extend(self.notes.iter().cloned());
+        notes
+    }
+
+    fn snapshots(&self) -> Vec<Snapshot> {
+        self.snapshots.clone()
+    }
+
+    fn undo_steps(&self) -> Vec<UndoStep> {
+        self.undo.clone()
+    }
+}
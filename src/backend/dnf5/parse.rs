@@ -0,0 +1,475 @@
+//! Parsers for DNF5 and RPM command output
+
+use crate::backend::TransactionSummary;
+use crate::size::parse_size;
+use std::collections::HashMap;
+
+/// Architectures that may appear as the suffix of a `name.arch` column
+const KNOWN_ARCHES: [&str; 8] = [
+    "noarch", "x86_64", "i686", "aarch64", "ppc64le", "s390x", "armv7hl", "src",
+];
+
+/// Query format passed to `rpm -q` so versions match how DNF5 prints them
+pub(crate) const RPM_QUERY_FORMAT: &str =
+    "%{NAME}.%{ARCH} %|EPOCH?{%{EPOCH}:}:{}|%{VERSION}-%{RELEASE}\\n";
+
+/// Query format listing installed kernels by install time, in `uname -r` form
+pub(crate) const KERNEL_QUERY_FORMAT: &str = "%{INSTALLTIME} %{VERSION}-%{RELEASE}.%{ARCH}\\n";
+
+/// A package upgrade reported by `dnf5 check-upgrade`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PendingPackage {
+    pub(crate) name: String,
+    pub(crate) arch: String,
+    /// Epoch, version and release of the available package
+    pub(crate) evr: String,
+    pub(crate) repo: String,
+}
+
+impl PendingPackage {
+    /// Key in `name.arch` form, as used by the installed-version lookup
+    pub(crate) fn key(&self) -> String {
+        format!("{}.{}", self.name, self.arch)
+    }
+}
+
+/// Splits `name.arch` into its parts if the suffix is a known architecture
+fn split_name_arch(column: &str) -> Option<(&str, &str)> {
+    let (name, arch) = column.rsplit_once('.')?;
+    if name.is_empty() || !KNOWN_ARCHES.contains(&arch) {
+        return None;
+    }
+    Some((name, arch))
+}
+
+/// Parses the package listing printed by `dnf5 check-upgrade`
+///
+/// Header lines, repository progress and the indented packages that an
+/// obsoleting package replaces are ignored.
+pub(crate) fn parse_check_upgrade(output: &str) -> Vec<PendingPackage> {
+    let mut packages = Vec::new();
+
+    for line in output.lines() {
+        // Indented rows list installed packages being obsoleted
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+
+        let mut columns = line.split_whitespace();
+        let (Some(name_arch), Some(evr), Some(repo), None) = (
+            columns.next(),
+            columns.next(),
+            columns.next(),
+            columns.next(),
+        ) else {
+            continue;
+        };
+
+        let Some((name, arch)) = split_name_arch(name_arch) else {
+            continue;
+        };
+        if !evr.contains('-') {
+            continue;
+        }
+
+        packages.push(PendingPackage {
+            name: name.to_string(),
+            arch: arch.to_string(),
+            evr: evr.to_string(),
+            repo: repo.to_string(),
+        });
+    }
+
+    packages
+}
+
+/// Parses `rpm -q --queryformat RPM_QUERY_FORMAT` output into `name.arch -> evr`
+///
+/// When several versions are installed (kernels), the last one listed wins.
+/// Lines for packages that are not installed are skipped.
+pub(crate) fn parse_installed_versions(output: &str) -> HashMap<String, String> {
+    let mut versions = HashMap::new();
+
+    for line in output.lines() {
+        let mut columns = line.split_whitespace();
+        if let (Some(name_arch), Some(evr), None) = (columns.next(), columns.next(), columns.next())
+            && split_name_arch(name_arch).is_some()
+        {
+            versions.insert(name_arch.to_string(), evr.to_string());
+        }
+    }
+
+    versions
+}
+
+/// Parses `dnf5 needs-restarting -s` output into systemd unit names
+pub(crate) fn parse_restart_services(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.ends_with(".service"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parses the transaction summary DNF5 prints before asking for confirmation
+///
+/// Returns `None` if the output contains neither a summary nor "Nothing to do".
+pub(crate) fn parse_transaction_summary(output: &str) -> Option<TransactionSummary> {
+    let mut summary = TransactionSummary::default();
+    let mut found = false;
+    let mut in_summary = false;
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        if line == "Nothing to do." {
+            found = true;
+        } else if line == "Transaction Summary:" {
+            found = true;
+            in_summary = true;
+        } else if in_summary && let Some((label, rest)) = line.split_once(':') {
+            let count = rest
+                .split_whitespace()
+                .next()
+                .and_then(|count| count.parse().ok())
+                .unwrap_or(0);
+            match label {
+                "Installing" => summary.install = count,
+                "Upgrading" => summary.upgrade = count,
+                "Downgrading" => summary.downgrade = count,
+                "Removing" => summary.remove = count,
+                _ => {}
+            }
+        } else if let Some((_, rest)) = line.split_once("Need to download ") {
+            in_summary = false;
+            summary.download_size = parse_size(rest.trim_end_matches('.'));
+        } else if let Some(rest) = line.strip_prefix("After this operation, ") {
+            in_summary = false;
+            if let Some((size, _)) = rest.split_once(" extra will be used") {
+                summary.installed_size_delta = parse_size(size).map(|bytes| bytes as i64);
+            } else if let Some((size, _)) = rest.split_once(" will be freed") {
+                summary.installed_size_delta = parse_size(size).map(|bytes| -(bytes as i64));
+            }
+        }
+    }
+
+    found.then_some(summary)
+}
+
+/// Advisory types printed by `dnf5 advisory list`
+const ADVISORY_TYPES: [&str; 5] = [
+    "security",
+    "bugfix",
+    "enhancement",
+    "newpackage",
+    "unspecified",
+];
+
+/// An advisory that fixes installed packages, with the CVEs it addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Advisory {
+    pub(crate) name: String,
+    /// `security`, `bugfix`, `enhancement`, `newpackage` or `unspecified`
+    pub(crate) kind: String,
+    /// `Critical`, `Important`, `Moderate`, `Low` or `None`
+    pub(crate) severity: String,
+    /// NEVRAs of the packages that receive the fix
+    pub(crate) packages: Vec<String>,
+    pub(crate) cves: Vec<String>,
+}
+
+/// Rows of a `dnf5 advisory list` table as (name, type, severity, package)
+///
+/// `FEDORA-2025-1a2b3c4d5e security Critical firefox-134.0.1-1.fc41.x86_64 2025-01-15 01:23:45`
+fn advisory_rows(output: &str) -> impl Iterator<Item = (&str, &str, &str, &str)> {
+    output.lines().filter_map(|line| {
+        let mut columns = line.split_whitespace();
+        let (Some(name), Some(kind), Some(severity), Some(package)) = (
+            columns.next(),
+            columns.next(),
+            columns.next(),
+            columns.next(),
+        ) else {
+            return None;
+        };
+        ADVISORY_TYPES
+            .contains(&kind)
+            .then_some((name, kind, severity, package))
+    })
+}
+
+/// Parses `dnf5 advisory list --updates`, adding the CVE IDs from the same
+/// listing run with `--with-cve`, which names each row after a CVE instead
+pub(crate) fn parse_advisories(list: &str, cve_list: &str) -> Vec<Advisory> {
+    let mut advisories: Vec<Advisory> = Vec::new();
+
+    for (name, kind, severity, package) in advisory_rows(list) {
+        match advisories.iter_mut().find(|advisory| advisory.name == name) {
+            Some(advisory) => advisory.packages.push(package.to_string()),
+            None => advisories.push(Advisory {
+                name: name.to_string(),
+                kind: kind.to_string(),
+                severity: severity.to_string(),
+                packages: vec![package.to_string()],
+                cves: Vec::new(),
+            }),
+        }
+    }
+
+    // CVEs are matched to advisories through the packages they fix
+    for (cve, _, _, package) in advisory_rows(cve_list) {
+        for advisory in &mut advisories {
+            if advisory.packages.iter().any(|p| p == package)
+                && !advisory.cves.iter().any(|known| known == cve)
+            {
+                advisory.cves.push(cve.to_string());
+            }
+        }
+    }
+
+    advisories
+}
+
+/// Parses the total size from `dnf5 clean packages` output
+///
+/// `Removed 42 files, 3 directories (total of 512 MiB). 0 errors occurred.`
+pub(crate) fn parse_clean_total(output: &str) -> Option<u64> {
+    output.lines().find_map(|line| {
+        let (_, rest) = line.split_once("(total of ")?;
+        let (size, _) = rest.split_once(')')?;
+        parse_size(size)
+    })
+}
+
+/// Finds the newest transaction ID in `dnf5 history list` output
+///
+/// ```text
+/// ID Command line            Date and time       Action(s) Altered
+///  14 dnf5 upgrade -y        2025-01-15 10:22:01                41
+/// ```
+pub(crate) fn parse_latest_transaction(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().next()?.parse().ok())
+        .max()
+}
+
+/// Picks the installed kernels beyond the newest `keep`, oldest first
+///
+/// `output` is `rpm -q --queryformat KERNEL_QUERY_FORMAT kernel-core` output.
+/// The running kernel and the newest one, which may not have been booted yet, are never picked.
+pub(crate) fn removable_kernels(output: &str, running: &str, keep: usize) -> Vec<String> {
+    let mut kernels: Vec<(u64, &str)> = output
+        .lines()
+        .filter_map(|line| {
+            let (time, version) = line.trim().split_once(' ')?;
+            Some((time.parse().ok()?, version.trim()))
+        })
+        .collect();
+    kernels.sort_by_key(|&(time, _)| std::cmp::Reverse(time));
+
+    let mut removable: Vec<String> = kernels
+        .into_iter()
+        .skip(keep.max(1))
+        .filter(|(_, version)| *version != running)
+        .map(|(_, version)| version.to_string())
+        .collect();
+    removable.reverse();
+    removable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECK_UPGRADE: &str = include_str!("../../../tests/fixtures/dnf5/check-upgrade.txt");
+    const CHECK_UPGRADE_OBSOLETES: &str =
+        include_str!("../../../tests/fixtures/dnf5/check-upgrade-obsoletes.txt");
+    const RPM_QUERY: &str = include_str!("../../../tests/fixtures/dnf5/rpm-query.txt");
+    const UPGRADE_ASSUMENO: &str =
+        include_str!("../../../tests/fixtures/dnf5/upgrade-assumeno.txt");
+    const AUTOREMOVE_ASSUMENO: &str =
+        include_str!("../../../tests/fixtures/dnf5/autoremove-assumeno.txt");
+    const CLEAN_PACKAGES: &str = include_str!("../../../tests/fixtures/dnf5/clean-packages.txt");
+    const RPM_KERNELS: &str = include_str!("../../../tests/fixtures/dnf5/rpm-kernels.txt");
+    const ADVISORY_LIST: &str = include_str!("../../../tests/fixtures/dnf5/advisory-list.txt");
+    const ADVISORY_LIST_CVE: &str =
+        include_str!("../../../tests/fixtures/dnf5/advisory-list-cve.txt");
+    const HISTORY_LIST: &str = include_str!("../../../tests/fixtures/dnf5/history-list.txt");
+
+    #[test]
+    fn parses_check_upgrade_listing() {
+        let packages = parse_check_upgrade(CHECK_UPGRADE);
+
+        assert_eq!(packages.len(), 6);
+        assert_eq!(
+            packages[0],
+            PendingPackage {
+                name: "NetworkManager".to_string(),
+                arch: "x86_64".to_string(),
+                evr: "1:1.50.3-1.fc41".to_string(),
+                repo: "updates".to_string(),
+            }
+        );
+        assert_eq!(packages[5].name, "python3-dnf-plugins-core");
+        assert_eq!(packages[5].arch, "noarch");
+    }
+
+    #[test]
+    fn skips_progress_and_obsoleted_rows() {
+        let packages = parse_check_upgrade(CHECK_UPGRADE_OBSOLETES);
+        let names: Vec<_> = packages.iter().map(|p| p.name.as_str()).collect();
+
+        assert_eq!(
+            names,
+            [
+                "mesa-dri-drivers",
+                "mesa-libGL",
+                "mesa-va-drivers-freeworld"
+            ]
+        );
+        assert_eq!(packages[2].repo, "rpmfusion-free-updates");
+    }
+
+    #[test]
+    fn empty_output_has_no_packages() {
+        assert!(parse_check_upgrade("").is_empty());
+        assert!(parse_check_upgrade("Updating and loading repositories:\n").is_empty());
+    }
+
+    #[test]
+    fn parses_installed_versions() {
+        let versions = parse_installed_versions(RPM_QUERY);
+
+        assert_eq!(versions["NetworkManager.x86_64"], "1:1.50.0-1.fc41");
+        // The most recently listed kernel is taken as the installed one
+        assert_eq!(versions["kernel.x86_64"], "6.12.7-200.fc41");
+        assert!(!versions.contains_key("kernel-core.x86_64"));
+    }
+
+    #[test]
+    fn parses_services_needing_restart() {
+        let output = "Updating and loading repositories:\nRepositories loaded.\nNetworkManager.service\nsshd.service\n";
+        assert_eq!(
+            parse_restart_services(output),
+            ["NetworkManager.service", "sshd.service"]
+        );
+        assert!(parse_restart_services("").is_empty());
+    }
+
+    #[test]
+    fn ignores_packages_that_are_not_installed() {
+        let versions = parse_installed_versions("package kernel-core is not installed\n");
+        assert!(versions.is_empty());
+    }
+
+    #[test]
+    fn parses_transaction_summary() {
+        let summary = parse_transaction_summary(UPGRADE_ASSUMENO).unwrap();
+
+        assert_eq!(summary.install, 2);
+        assert_eq!(summary.upgrade, 2);
+        assert_eq!(summary.remove, 1);
+        assert_eq!(summary.downgrade, 0);
+        assert_eq!(summary.download_size, Some(118 * 1024 * 1024));
+        assert_eq!(summary.installed_size_delta, Some(1024 * 1024));
+    }
+
+    #[test]
+    fn parses_freed_space_and_empty_transactions() {
+        let output = "Transaction Summary:\n Removing: 2 packages\n\nAfter this operation, 15 MiB will be freed (install 0 B, remove 15 MiB).\n";
+        let summary = parse_transaction_summary(output).unwrap();
+        assert_eq!(summary.remove, 2);
+        assert_eq!(summary.installed_size_delta, Some(-15 * 1024 * 1024));
+
+        assert_eq!(
+            parse_transaction_summary("Nothing to do.\n"),
+            Some(TransactionSummary::default())
+        );
+        assert_eq!(parse_transaction_summary("Error: Failed to resolve"), None);
+    }
+
+    #[test]
+    fn parses_autoremove_preview() {
+        let summary = parse_transaction_summary(AUTOREMOVE_ASSUMENO).unwrap();
+
+        assert_eq!(summary.remove, 4);
+        assert_eq!(summary.installed_size_delta, Some(-193_986_560));
+    }
+
+    #[test]
+    fn parses_cleaned_cache_size() {
+        assert_eq!(parse_clean_total(CLEAN_PACKAGES), Some(536_870_912));
+        assert_eq!(
+            parse_clean_total("Removed 0 files, 0 directories. 0 errors occurred.\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn picks_oldest_kernels_beyond_keep_count() {
+        let running = "6.11.10-300.fc41.x86_64";
+
+        assert_eq!(
+            removable_kernels(RPM_KERNELS, running, 2),
+            ["6.11.9-300.fc41.x86_64", "6.12.4-200.fc41.x86_64"]
+        );
+        // The running kernel survives even when it is one of the oldest
+        assert_eq!(
+            removable_kernels(RPM_KERNELS, running, 1),
+            [
+                "6.11.9-300.fc41.x86_64",
+                "6.12.4-200.fc41.x86_64",
+                "6.12.5-200.fc41.x86_64"
+            ]
+        );
+        assert!(removable_kernels(RPM_KERNELS, running, 5).is_empty());
+    }
+
+    #[test]
+    fn never_picks_the_newest_kernel() {
+        // Running the oldest kernel right after installing a new one
+        let running = "6.11.9-300.fc41.x86_64";
+
+        assert_eq!(
+            removable_kernels(RPM_KERNELS, running, 0),
+            [
+                "6.11.10-300.fc41.x86_64",
+                "6.12.4-200.fc41.x86_64",
+                "6.12.5-200.fc41.x86_64"
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_advisories_with_their_cves() {
+        let advisories = parse_advisories(ADVISORY_LIST, ADVISORY_LIST_CVE);
+        let names: Vec<_> = advisories.iter().map(|a| a.name.as_str()).collect();
+
+        assert_eq!(
+            names,
+            [
+                "FEDORA-2025-1a2b3c4d5e",
+                "FEDORA-2025-6f7a8b9c0d",
+                "FEDORA-2025-aa11bb22cc",
+                "FEDORA-2025-dd33ee44ff"
+            ]
+        );
+        assert_eq!(advisories[0].severity, "Critical");
+        assert_eq!(advisories[0].packages.len(), 2);
+        assert_eq!(advisories[0].cves, ["CVE-2025-0237", "CVE-2025-0238"]);
+        assert_eq!(advisories[1].cves, ["CVE-2024-6387"]);
+        assert!(advisories[2].cves.is_empty());
+        assert_eq!(advisories[3].kind, "bugfix");
+    }
+
+    #[test]
+    fn finds_latest_transaction() {
+        assert_eq!(parse_latest_transaction(HISTORY_LIST), Some(14));
+        assert_eq!(
+            parse_latest_transaction("ID Command line Date and time\n"),
+            None
+        );
+    }
+}
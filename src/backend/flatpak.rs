@@ -0,0 +1,677 @@
+mod parse;
+
+use super::{
+    CheckStatus, ItemOutcome, ItemReport, PendingUpdate, TransactionSummary, UpdateBackend,
+    UpdateStage, show_updates, str_args,
+};
+use crate::config::FlatpakConfig;
+use crate::preflight::SpaceRequirement;
+use crate::rollback::UndoStep;
+use crate::runner::CommandRunner;
+use anyhow::Result;
+use async_trait::async_trait;
+use parse::{EndOfLife, ExtraInstallation, Operation, RefChange};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+/// Directory where administrators declare additional system-wide installations
+const INSTALLATIONS_DIR: &str = "/etc/flatpak/installations.d";
+
+/// Where the system installation stores its refs
+const SYSTEM_INSTALLATION_PATH: &str = "/var/lib/flatpak";
+
+/// Where the user installation stores its refs, following XDG_DATA_HOME
+fn user_installation_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+        })?;
+    Some(base.join("flatpak"))
+}
+
+/// Runs flatpak in the C locale, so the tables it prints parse the same for every user
+async fn run_flatpak(runner: &mut CommandRunner, args: &[&str]) -> Result<(ExitStatus, String)> {
+    let mut env_args = vec!["LC_ALL=C", "flatpak"];
+    env_args.extend_from_slice(args);
+    let (status, output) = runner.execute_command("env", &env_args, false).await?;
+    Ok((status, output.to_string()))
+}
+
+/// Describes what a transaction changed, such as `updated org.mozilla.firefox//stable`
+fn describe_changes(changes: &[RefChange]) -> Option<String> {
+    let groups: Vec<String> = [Operation::Update, Operation::Install, Operation::Uninstall]
+        .into_iter()
+        .filter_map(|operation| {
+            let names: Vec<String> = changes
+                .iter()
+                .filter(|change| change.operation == operation)
+                .map(RefChange::name)
+                .collect();
+            (!names.is_empty()).then(|| format!("{} {}", operation.past_tense(), names.join(", ")))
+        })
+        .collect();
+
+    (!groups.is_empty()).then(|| groups.join("; "))
+}
+
+/// Which part of `flatpak update` to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// Pull and deploy
+    Full,
+    /// Pull into the local repository without deploying (`--no-deploy`)
+    Pull,
+    /// Deploy what was pulled earlier without using the network (`--no-pull`)
+    Deploy,
+}
+
+impl Step {
+    fn flag(self) -> Option<&'static str> {
+        match self {
+            Self::Full => None,
+            Self::Pull => Some("--no-deploy"),
+            Self::Deploy => Some("--no-pull"),
+        }
+    }
+
+    /// Outcome reported for an installation where this step changed something
+    fn outcome(self) -> ItemOutcome {
+        match self {
+            Self::Pull => ItemOutcome::Downloaded,
+            Self::Full | Self::Deploy => ItemOutcome::Updated,
+        }
+    }
+
+    fn progress(self, installation: &str) -> String {
+        match self {
+            Self::Full => format!("Updating the {} Flatpak installation...", installation),
+            Self::Pull => format!(
+                "Downloading updates for the {} Flatpak installation...",
+                installation
+            ),
+            Self::Deploy => format!(
+                "Applying downloaded updates to the {} Flatpak installation...",
+                installation
+            ),
+        }
+    }
+}
+
+/// A Flatpak installation and what the last check found in it
+#[derive(Debug)]
+struct Installation {
+    /// `system`, `user`, or the ID of an extra installation
+    name: String,
+    /// Option selecting the installation on the flatpak command line
+    option: String,
+    /// Directory holding the installation's refs, if known
+    path: Option<PathBuf>,
+    pending: Vec<RefChange>,
+    // Refs changed by the last update, as listed in flatpak's transaction table
+    changes: Vec<RefChange>,
+    // End-of-life refs flatpak reported during the last update
+    end_of_life: Vec<EndOfLife>,
+    // Unused refs removed by the cleanup
+    removed: Vec<RefChange>,
+    // Commits the updated refs had before the last update
+    undo: Vec<UndoStep>,
+    // Result reported in the run summary
+    outcome: ItemOutcome,
+    error: Option<String>,
+}
+
+impl Installation {
+    fn new(name: &str, option: String, path: Option<PathBuf>) -> Self {
+        Self {
+            name: name.to_string(),
+            option,
+            path,
+            pending: Vec::new(),
+            changes: Vec::new(),
+            end_of_life: Vec::new(),
+            removed: Vec::new(),
+            undo: Vec::new(),
+            outcome: ItemOutcome::UpToDate,
+            error: None,
+        }
+    }
+
+    /// Lists the refs with pending updates in this installation
+    async fn check(&mut self, runner: &mut CommandRunner) -> Result<()> {
+        let (status, output) = run_flatpak(
+            runner,
+            &[
+                "remote-ls",
+                &self.option,
+                "--updates",
+                parse::REMOTE_LS_COLUMNS,
+            ],
+        )
+        .await?;
+
+        if !status.success() {
+            return Err(anyhow::anyhow!("Flatpak update check failed"));
+        }
+
+        self.pending = parse::parse_remote_ls(&output);
+        Ok(())
+    }
+
+    /// Reads the commit each installed ref has deployed
+    async fn active_commits(&self, runner: &mut CommandRunner) -> HashMap<String, String> {
+        match runner
+            .capture_command("flatpak", &["list", &self.option, parse::LIST_COLUMNS])
+            .await
+        {
+            Some(output) => parse::parse_active_commits(&String::from_utf8_lossy(&output.stdout)),
+            None => HashMap::new(),
+        }
+    }
+
+    /// Updates every ref in this installation, returning whether anything changed
+    async fn update(
+        &mut self,
+        runner: &mut CommandRunner,
+        step: Step,
+        extra_args: &[String],
+    ) -> Result<bool> {
+        // Pulling leaves the deployed commits alone, so there is nothing to roll back
+        let previous = match step {
+            Step::Pull => HashMap::new(),
+            _ => self.active_commits(runner).await,
+        };
+
+        let mut args = vec!["update", self.option.as_str(), "-y"];
+        args.extend(step.flag());
+        args.extend(str_args(extra_args));
+        let (status, output) = run_flatpak(runner, &args).await?;
+
+        // Rows that did complete are kept even if another ref failed
+        self.changes = parse::parse_transaction(&output);
+        self.end_of_life = parse::parse_end_of_life(&output);
+        self.undo = self
+            .changes
+            .iter()
+            .filter(|change| change.operation == Operation::Update)
+            .filter_map(|change| {
+                Some(UndoStep::FlatpakCommit {
+                    installation: self.option.clone(),
+                    reference: change.name(),
+                    commit: previous.get(&change.name())?.clone(),
+                })
+            })
+            .collect();
+        if !status.success() {
+            return Err(anyhow::anyhow!("Flatpak update failed"));
+        }
+
+        Ok(!self.changes.is_empty())
+    }
+
+    /// Uninstalls runtimes and extensions no installed application uses
+    async fn remove_unused(&mut self, runner: &mut CommandRunner) -> Result<()> {
+        let (status, output) =
+            run_flatpak(runner, &["uninstall", &self.option, "--unused", "-y"]).await?;
+
+        self.removed = parse::parse_transaction(&output);
+        if !status.success() {
+            return Err(anyhow::anyhow!(
+                "Removing unused refs from the {} installation failed",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Bytes to download for the pending refs
+    fn download_size(&self) -> u64 {
+        self.pending
+            .iter()
+            .filter_map(|pending| pending.download_size)
+            .sum()
+    }
+
+    fn fail(&mut self, error: anyhow::Error) {
+        self.outcome = ItemOutcome::Failed;
+        self.error = Some(format!("{:#}", error));
+    }
+}
+
+/// Describes an end-of-life ref and what still depends on it
+fn describe_end_of_life(eol: &EndOfLife) -> String {
+    let mut note = format!("{} is end-of-life", eol.name);
+    if let Some(target) = &eol.rebased_to {
+        note.push_str(&format!(" and was replaced by {}", target));
+    }
+    if !eol.apps.is_empty() {
+        note.push_str(&format!(", still used by {}", eol.apps.join(", ")));
+    }
+    if let Some(reason) = &eol.reason {
+        note.push_str(&format!(" ({})", reason));
+    }
+    note
+}
+
+/// Reads the extra installations declared in `dir`, sorted by file name
+fn extra_installations(dir: &Path) -> Vec<ExtraInstallation> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+        .collect();
+    files.sort();
+
+    files
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|text| parse::parse_installations(&text))
+        .collect()
+}
+
+/// Updates Flatpak applications and runtimes without elevated privileges
+///
+/// The system installation, extra system-wide installations and the user
+/// installation are checked and updated separately, so a broken remote in one
+/// does not hide the result of the others.
+#[derive(Debug)]
+pub(crate) struct FlatpakBackend {
+    config: FlatpakConfig,
+    installations_dir: PathBuf,
+    // Installations found by the last check
+    installations: Vec<Installation>,
+}
+
+impl FlatpakBackend {
+    pub(crate) fn new(config: FlatpakConfig) -> Self {
+        Self::with_installations_dir(config, PathBuf::from(INSTALLATIONS_DIR))
+    }
+
+    /// Creates a backend that reads extra installations from `dir`
+    pub(crate) fn with_installations_dir(config: FlatpakConfig, dir: PathBuf) -> Self {
+        Self {
+            config,
+            installations_dir: dir,
+            installations: Vec::new(),
+        }
+    }
+
+    /// Fails with the names of failed installations, if any
+    fn failures(&self) -> Result<()> {
+        let failed: Vec<&str> = self
+            .installations
+            .iter()
+            .filter(|installation| installation.outcome == ItemOutcome::Failed)
+            .map(|installation| installation.name.as_str())
+            .collect();
+
+        if failed.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow::anyhow!(
+            "Flatpak installations failed: {}",
+            failed.join(", ")
+        ))
+    }
+
+    /// Lists the system, extra and user installations, in that order
+    fn load_installations(&mut self) {
+        self.installations = vec![Installation::new(
+            "system",
+            "--system".to_string(),
+            Some(PathBuf::from(SYSTEM_INSTALLATION_PATH)),
+        )];
+        self.installations.extend(
+            extra_installations(&self.installations_dir)
+                .into_iter()
+                .map(|extra| {
+                    let option = format!("--installation={}", extra.id);
+                    Installation::new(&extra.id, option, extra.path)
+                }),
+        );
+        self.installations.push(Installation::new(
+            "user",
+            "--user".to_string(),
+            user_installation_path(),
+        ));
+    }
+
+    /// Runs a step of `flatpak update` in each installation, returning whether anything changed
+    ///
+    /// Installations without pending refs are skipped unless `all` is set.
+    async fn update_installations(
+        &mut self,
+        runner: &mut CommandRunner,
+        step: Step,
+        all: bool,
+    ) -> Result<bool> {
+        let mut any_changed = false;
+
+        for installation in &mut self.installations {
+            if installation.pending.is_empty() && !all {
+                continue;
+            }
+
+            runner
+                .reporter()
+                .progress(&step.progress(&installation.name));
+            match installation
+                .update(runner, step, &self.config.extra_args)
+                .await
+            {
+                Ok(true) => {
+                    installation.outcome = step.outcome();
+                    any_changed = true;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    runner.reporter().error(&format!(
+                        "The {} Flatpak installation failed: {:#}",
+                        installation.name, e
+                    ));
+                    installation.fail(e);
+                }
+            }
+        }
+
+        self.failures()?;
+        Ok(any_changed)
+    }
+}
+
+#[async_trait]
+impl UpdateBackend for FlatpakBackend {
+    fn name(&self) -> &'static str {
+        "Flatpak"
+    }
+
+    async fn detect(&mut self, runner: &mut CommandRunner) -> bool {
+        runner.is_available("flatpak").await
+    }
+
+    async fn check(&mut self, runner: &mut CommandRunner) -> Result<CheckStatus> {
+        runner
+            .reporter()
+            .progress("Checking for Flatpak updates...");
+
+        self.load_installations();
+
+        let mut pending: Vec<PendingUpdate> = Vec::new();
+        for installation in &mut self.installations {
+            match installation.check(runner).await {
+                Ok(()) if installation.pending.is_empty() => {}
+                Ok(()) => {
+                    let active = installation.active_commits(runner).await;
+                    let updates: Vec<PendingUpdate> = installation
+                        .pending
+                        .iter()
+                        .map(|change| change.to_pending_update(active.get(&change.name())))
+                        .collect();
+                    show_updates(
+                        runner.reporter(),
+                        &format!(
+                            "Pending Flatpak updates in the {} installation ({}):",
+                            installation.name,
+                            updates.len()
+                        ),
+                        &updates,
+                    );
+                    pending.extend(updates);
+                }
+                Err(e) => {
+                    runner.reporter().warn(&format!(
+                        "Could not check the {} Flatpak installation: {:#}",
+                        installation.name, e
+                    ));
+                    installation.fail(e);
+                }
+            }
+        }
+
+        if pending.is_empty() {
+            self.failures()?;
+            runner.reporter().progress("No Flatpak updates available.");
+            return Ok(CheckStatus::UpToDate);
+        }
+
+        runner.reporter().progress("Flatpak updates are available.");
+        Ok(CheckStatus::Available(pending))
+    }
+
+    async fn preview(&mut self, runner: &mut CommandRunner) -> Result<TransactionSummary> {
+        // The check already listed every pending ref with its download size
+        runner
+            .reporter()
+            .progress("Flatpak dry run: no changes will be made.");
+
+        let mut transaction = TransactionSummary {
+            download_size: Some(0),
+            ..TransactionSummary::default()
+        };
+        for installation in &mut self.installations {
+            if installation.pending.is_empty() {
+                continue;
+            }
+            installation.outcome = ItemOutcome::Previewed;
+            transaction.upgrade += installation.pending.len() as u32;
+            transaction.download_size = transaction
+                .download_size
+                .map(|total| total + installation.download_size());
+        }
+
+        self.failures()?;
+        Ok(transaction)
+    }
+
+    async fn required_space(
+        &mut self,
+        _runner: &mut CommandRunner,
+        stage: &UpdateStage,
+    ) -> Result<Vec<SpaceRequirement>> {
+        // Deploying pulled refs checks out objects already in the local repository
+        if matches!(stage, UpdateStage::ApplyDownloaded(_)) {
+            return Ok(Vec::new());
+        }
+
+        Ok(self
+            .installations
+            .iter()
+            .filter_map(|installation| {
+                Some(SpaceRequirement::new(
+                    installation.path.clone()?,
+                    installation.download_size(),
+                    &format!("{} installation downloads", installation.name),
+                ))
+            })
+            .collect())
+    }
+
+    async fn apply(&mut self, runner: &mut CommandRunner) -> Result<bool> {
+        self.update_installations(runner, Step::Full, false).await
+    }
+
+    fn supports_download(&self) -> bool {
+        true
+    }
+
+    async fn download(&mut self, runner: &mut CommandRunner) -> Result<()> {
+        self.update_installations(runner, Step::Pull, false)
+            .await
+            .map(|_| ())
+    }
+
+    async fn apply_downloaded(
+        &mut self,
+        runner: &mut CommandRunner,
+        downloaded: &[PendingUpdate],
+    ) -> Result<bool> {
+        // Nothing was checked in this run, so every installation deploys what it has pulled
+        self.load_installations();
+        let any_updated = self
+            .update_installations(runner, Step::Deploy, true)
+            .await?;
+
+        let deployed: Vec<String> = self
+            .installations
+            .iter()
+            .flat_map(|installation| &installation.changes)
+            .map(RefChange::name)
+            .collect();
+        let missing: Vec<&PendingUpdate> = downloaded
+            .iter()
+            .filter(|update| !deployed.contains(&update.name))
+            .collect();
+        if missing.is_empty() {
+            return Ok(any_updated);
+        }
+
+        // Refs updated since the download, such as by GNOME Software, no longer
+        // have the commit they had then and count as applied
+        let mut active = Vec::new();
+        for installation in &self.installations {
+            active.push(installation.active_commits(runner).await);
+        }
+        let outdated: Vec<&str> = missing
+            .iter()
+            .filter(|update| {
+                active
+                    .iter()
+                    .any(|commits| match commits.get(&update.name) {
+                        Some(commit) => update
+                            .current_version
+                            .as_ref()
+                            .is_none_or(|before| commit.starts_with(before.as_str())),
+                        None => false,
+                    })
+            })
+            .map(|update| update.name.as_str())
+            .collect();
+        if !outdated.is_empty() {
+            return Err(anyhow::anyhow!(
+                "These Flatpak refs were not in the local repository: {}. Run `update --download-only` again",
+                outdated.join(", ")
+            ));
+        }
+
+        Ok(any_updated)
+    }
+
+    async fn cleanup(&mut self, runner: &mut CommandRunner) -> Result<()> {
+        if !self.config.cleanup {
+            return Ok(());
+        }
+
+        runner
+            .reporter()
+            .progress("Removing unused Flatpak runtimes...");
+
+        // Installations that could not be checked are left alone
+        let mut result = Ok(());
+        for installation in &mut self.installations {
+            if installation.outcome == ItemOutcome::Failed {
+                continue;
+            }
+            if let Err(e) = installation.remove_unused(runner).await {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn items(&self) -> Vec<ItemReport> {
+        self.installations
+            .iter()
+            .map(|installation| ItemReport {
+                name: installation.name.clone(),
+                outcome: installation.outcome,
+                error: installation.error.clone(),
+                // Updated installations list exactly which refs changed
+                detail: describe_changes(&installation.changes),
+            })
+            .collect()
+    }
+
+    fn notes(&self) -> Vec<String> {
+        let mut notes = Vec::new();
+
+        // The same runtime may be reported by several installations
+        let mut reported: Vec<&str> = Vec::new();
+        let end_of_life = self
+            .installations
+            .iter()
+            .flat_map(|installation| &installation.end_of_life);
+        for eol in end_of_life {
+            if reported.contains(&eol.name.as_str()) {
+                continue;
+            }
+            reported.push(&eol.name);
+            notes.push(describe_end_of_life(eol));
+        }
+
+        for installation in &self.installations {
+            if installation.removed.is_empty() {
+                continue;
+            }
+            let names: Vec<String> = installation.removed.iter().map(RefChange::name).collect();
+            notes.push(format!(
+                "Removed {} unused refs from the {} installation: {}",
+                names.len(),
+                installation.name,
+                names.join(", ")
+            ));
+        }
+
+        notes
+    }
+
+    fn undo_steps(&self) -> Vec<UndoStep> {
+        self.installations
+            .iter()
+            .flat_map(|installation| installation.undo.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_changes_by_operation() {
+        let change = |id: &str, operation| RefChange {
+            id: id.to_string(),
+            branch: "stable".to_string(),
+            operation,
+            remote: "flathub".to_string(),
+            download_size: None,
+        };
+        let changes = [
+            change("org.mozilla.firefox", Operation::Update),
+            change("org.example.Locale", Operation::Install),
+            change("org.gimp.GIMP", Operation::Update),
+        ];
+
+        assert_eq!(
+            describe_changes(&changes).as_deref(),
+            Some(
+                "updated org.mozilla.firefox//stable, org.gimp.GIMP//stable; \
+                 installed org.example.Locale//stable"
+            )
+        );
+        assert_eq!(describe_changes(&[]), None);
+    }
+
+    #[test]
+    fn reads_only_conf_files() {
+        let extra = extra_installations(Path::new("tests/fixtures/flatpak/installations.d"));
+
+        let ids: Vec<_> = extra.iter().map(|extra| extra.id.as_str()).collect();
+        assert_eq!(ids, ["extra", "sdcard"]);
+        assert!(extra_installations(Path::new("tests/fixtures/flatpak/missing")).is_empty());
+    }
+}
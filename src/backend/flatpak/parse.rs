@@ -0,0 +1,402 @@
+//! Parsers for Flatpak ref listings, transaction tables and installation files
+
+use crate::backend::PendingUpdate;
+use crate::size::parse_size;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Columns requested from `flatpak remote-ls`, in the order `parse_remote_ls` reads them
+pub(crate) const REMOTE_LS_COLUMNS: &str = "--columns=application,branch,origin,download-size";
+
+/// Change a Flatpak transaction makes to a ref
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Install,
+    Update,
+    Uninstall,
+}
+
+impl Operation {
+    /// Maps the one-letter code of the `Op` column
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "i" => Some(Self::Install),
+            "u" => Some(Self::Update),
+            "r" => Some(Self::Uninstall),
+            _ => None,
+        }
+    }
+
+    /// Verb used when reporting what a transaction did
+    pub(crate) fn past_tense(self) -> &'static str {
+        match self {
+            Self::Install => "installed",
+            Self::Update => "updated",
+            Self::Uninstall => "uninstalled",
+        }
+    }
+}
+
+/// An application or runtime listed as pending or changed by a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RefChange {
+    /// Application or runtime ID, such as `org.mozilla.firefox`
+    pub(crate) id: String,
+    pub(crate) branch: String,
+    pub(crate) operation: Operation,
+    pub(crate) remote: String,
+    /// Bytes to download; flatpak prints an upper bound
+    pub(crate) download_size: Option<u64>,
+}
+
+impl RefChange {
+    /// Ref in the `ID//BRANCH` form accepted by the flatpak command line
+    pub(crate) fn name(&self) -> String {
+        format!("{}//{}", self.id, self.branch)
+    }
+
+    /// Describes this pending ref, with the abbreviated commit it has deployed as its version
+    pub(crate) fn to_pending_update(&self, active: Option<&String>) -> PendingUpdate {
+        PendingUpdate {
+            name: self.name(),
+            current_version: active.map(|commit| short_commit(commit).to_string()),
+            new_version: None,
+            origin: Some(self.remote.clone()),
+        }
+    }
+}
+
+/// Parses `flatpak remote-ls --updates` output with `REMOTE_LS_COLUMNS`
+///
+/// Rows are tab-separated application ID, branch, remote and download size.
+pub(crate) fn parse_remote_ls(output: &str) -> Vec<RefChange> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split('\t').map(str::trim);
+            let id = columns.next().filter(|id| !id.is_empty())?;
+            Some(RefChange {
+                id: id.to_string(),
+                branch: columns.next().unwrap_or_default().to_string(),
+                operation: Operation::Update,
+                remote: columns.next().unwrap_or_default().to_string(),
+                download_size: columns.next().and_then(parse_size),
+            })
+        })
+        .collect()
+}
+
+/// Parses the transaction table printed by `flatpak update`, skipping failed rows
+///
+/// ```text
+///         ID                         Branch    Op    Remote     Download
+///  1. [✓] org.mozilla.firefox        stable    u     flathub    < 92.4 MB (partial)
+/// ```
+///
+/// The status column is only printed on interactive terminals.
+pub(crate) fn parse_transaction(output: &str) -> Vec<RefChange> {
+    output.lines().filter_map(parse_transaction_row).collect()
+}
+
+fn parse_transaction_row(line: &str) -> Option<RefChange> {
+    let (number, mut rest) = line.trim().split_once('.')?;
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    rest = rest.trim_start();
+    if rest.starts_with('[') {
+        let (status, after) = rest.split_once(']')?;
+        if status.contains('✗') {
+            return None;
+        }
+        rest = after;
+    }
+
+    let mut columns = rest.split_whitespace();
+    let id = columns.next()?.to_string();
+    let branch = columns.next()?.to_string();
+    let operation = Operation::from_code(columns.next()?)?;
+    // Uninstall tables have no remote or download columns
+    let remote = columns.next().unwrap_or_default().to_string();
+
+    // The size may be followed by a note such as "(partial)"
+    let download = columns.collect::<Vec<_>>().join(" ");
+    let download = download.split('(').next().unwrap_or_default();
+
+    Some(RefChange {
+        id,
+        branch,
+        operation,
+        remote,
+        download_size: parse_size(download),
+    })
+}
+
+/// Columns requested from `flatpak list`, in the order `parse_active_commits` reads them
+pub(crate) const LIST_COLUMNS: &str = "--columns=application,branch,active";
+
+/// Abbreviates a commit checksum the way flatpak prints it
+pub(crate) fn short_commit(commit: &str) -> &str {
+    commit.get(..12).unwrap_or(commit)
+}
+
+/// Maps each installed ref in `ID//BRANCH` form to its deployed commit
+pub(crate) fn parse_active_commits(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split('\t').map(str::trim);
+            let id = columns.next().filter(|id| !id.is_empty())?;
+            let branch = columns.next()?;
+            let commit = columns.next().filter(|commit| !commit.is_empty())?;
+            Some((format!("{}//{}", id, branch), commit.to_string()))
+        })
+        .collect()
+}
+
+/// An end-of-life ref reported by flatpak during a transaction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct EndOfLife {
+    /// Ref in `ID//BRANCH` form
+    pub(crate) name: String,
+    pub(crate) reason: Option<String>,
+    /// Ref that replaces this one, when flatpak rebased it
+    pub(crate) rebased_to: Option<String>,
+    /// Installed applications that still use this runtime
+    pub(crate) apps: Vec<String>,
+}
+
+/// Parses a `runtime ID branch BRANCH` phrase into `ID//BRANCH`
+fn parse_ref_phrase(text: &str) -> Option<String> {
+    let mut words = text.split_whitespace();
+    if !matches!(words.next()?, "runtime" | "app") {
+        return None;
+    }
+    let id = words.next()?;
+    if words.next()? != "branch" {
+        return None;
+    }
+    Some(format!("{}//{}", id, words.next()?))
+}
+
+/// Parses the end-of-life notices flatpak prints during an update
+///
+/// ```text
+/// Info: runtime org.gnome.Platform branch 45 is end-of-life, with reason:
+///    The GNOME 45 runtime is no longer supported as of April 2, 2024.
+/// Info: applications using this runtime:
+///    org.gnome.Boxes, org.example.Editor
+/// Info: app org.example.Old branch stable is end-of-life, in favor of org.example.New branch stable
+/// ```
+pub(crate) fn parse_end_of_life(output: &str) -> Vec<EndOfLife> {
+    // Which field the indented line after an `Info:` line belongs to
+    enum Expect {
+        Nothing,
+        Reason,
+        Apps,
+    }
+
+    let mut refs: Vec<EndOfLife> = Vec::new();
+    let mut expect = Expect::Nothing;
+
+    for line in output.lines() {
+        if line.starts_with(char::is_whitespace) {
+            let text = line.trim();
+            match (&expect, refs.last_mut()) {
+                (Expect::Reason, Some(eol)) if !text.is_empty() => {
+                    eol.reason = Some(text.to_string())
+                }
+                (Expect::Apps, Some(eol)) => eol.apps.extend(
+                    text.split(',')
+                        .map(str::trim)
+                        .filter(|app| !app.is_empty())
+                        .map(str::to_string),
+                ),
+                _ => {}
+            }
+            expect = Expect::Nothing;
+            continue;
+        }
+
+        expect = Expect::Nothing;
+        let Some(info) = line.strip_prefix("Info: ") else {
+            continue;
+        };
+        if info.trim() == "applications using this runtime:" {
+            expect = Expect::Apps;
+            continue;
+        }
+
+        let info = info.strip_prefix("(pinned) ").unwrap_or(info);
+        let Some((subject, details)) = info.split_once(" is end-of-life") else {
+            continue;
+        };
+        let Some(name) = parse_ref_phrase(subject) else {
+            continue;
+        };
+
+        let rebased_to = details
+            .split_once("in favor of ")
+            .and_then(|(_, target)| parse_ref_phrase(&format!("runtime {}", target)));
+        if details.contains("with reason:") {
+            expect = Expect::Reason;
+        }
+        refs.push(EndOfLife {
+            name,
+            rebased_to,
+            ..EndOfLife::default()
+        });
+    }
+
+    refs
+}
+
+/// An installation declared in an installations.d configuration file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExtraInstallation {
+    pub(crate) id: String,
+    /// Directory holding the installation's refs, from its `Path=` key
+    pub(crate) path: Option<PathBuf>,
+}
+
+/// Parses the installations declared in an installations.d configuration file
+///
+/// Each installation is a `[Installation "id"]` group with a `Path=` key.
+pub(crate) fn parse_installations(text: &str) -> Vec<ExtraInstallation> {
+    let mut installations: Vec<ExtraInstallation> = Vec::new();
+    let mut in_installation = false;
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            let id = line
+                .strip_prefix("[Installation \"")
+                .and_then(|rest| rest.strip_suffix("\"]"));
+            in_installation = id.is_some();
+            if let Some(id) = id {
+                installations.push(ExtraInstallation {
+                    id: id.to_string(),
+                    path: None,
+                });
+            }
+        } else if in_installation
+            && let Some(path) = line.strip_prefix("Path=")
+            && let Some(installation) = installations.last_mut()
+        {
+            installation.path = Some(PathBuf::from(path.trim()));
+        }
+    }
+
+    installations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REMOTE_LS: &str = include_str!("../../../tests/fixtures/flatpak/remote-ls-updates.txt");
+    const UPDATE: &str = include_str!("../../../tests/fixtures/flatpak/update.txt");
+    const UPDATE_FAILED: &str = include_str!("../../../tests/fixtures/flatpak/update-failed.txt");
+    const UPDATE_EOL: &str = include_str!("../../../tests/fixtures/flatpak/update-eol.txt");
+    const UNINSTALL_UNUSED: &str =
+        include_str!("../../../tests/fixtures/flatpak/uninstall-unused.txt");
+    const LIST_ACTIVE: &str = include_str!("../../../tests/fixtures/flatpak/list-active.txt");
+    const EXTRA_CONF: &str =
+        include_str!("../../../tests/fixtures/flatpak/installations.d/extra.conf");
+
+    #[test]
+    fn parses_pending_refs() {
+        let refs = parse_remote_ls(REMOTE_LS);
+
+        assert_eq!(refs.len(), 3);
+        assert_eq!(refs[0].name(), "org.freedesktop.Platform.GL.default//24.08");
+        assert_eq!(refs[0].remote, "flathub");
+        assert_eq!(refs[0].download_size, Some(167_900_000));
+        assert_eq!(refs[2].id, "org.mozilla.firefox");
+        assert!(refs.iter().all(|r| r.operation == Operation::Update));
+    }
+
+    #[test]
+    fn parses_transaction_table() {
+        let changes = parse_transaction(UPDATE);
+        let summary: Vec<_> = changes.iter().map(|c| (c.name(), c.operation)).collect();
+
+        assert_eq!(
+            summary,
+            [
+                (
+                    "org.freedesktop.Platform.GL.default//24.08".to_string(),
+                    Operation::Update
+                ),
+                (
+                    "org.freedesktop.Platform.Locale//24.08".to_string(),
+                    Operation::Install
+                ),
+                ("org.mozilla.firefox//stable".to_string(), Operation::Update),
+            ]
+        );
+        assert_eq!(changes[2].download_size, Some(92_400_000));
+        assert!(parse_transaction("Looking for updates…\nNothing to do.\n").is_empty());
+    }
+
+    #[test]
+    fn skips_failed_rows_of_interactive_table() {
+        let changes = parse_transaction(UPDATE_FAILED);
+        let names: Vec<_> = changes.iter().map(RefChange::name).collect();
+
+        assert_eq!(names, ["org.gnome.Platform//47"]);
+    }
+
+    #[test]
+    fn parses_end_of_life_runtimes_and_their_apps() {
+        let eol = parse_end_of_life(UPDATE_EOL);
+
+        assert_eq!(eol.len(), 2);
+        assert_eq!(eol[0].name, "org.gnome.Platform//45");
+        assert_eq!(
+            eol[0].reason.as_deref(),
+            Some("The GNOME 45 runtime is no longer supported as of April 2, 2024.")
+        );
+        assert_eq!(eol[0].apps, ["org.gnome.Boxes", "org.example.Editor"]);
+        assert_eq!(eol[1].name, "org.example.Old//stable");
+        assert_eq!(
+            eol[1].rebased_to.as_deref(),
+            Some("org.example.New//stable")
+        );
+        assert!(eol[1].apps.is_empty());
+    }
+
+    #[test]
+    fn parses_uninstall_table() {
+        let removed = parse_transaction(UNINSTALL_UNUSED);
+        let names: Vec<_> = removed.iter().map(RefChange::name).collect();
+
+        assert_eq!(
+            names,
+            ["org.freedesktop.Platform//22.08", "org.gnome.Platform//44"]
+        );
+        assert!(removed.iter().all(|r| r.operation == Operation::Uninstall));
+    }
+
+    #[test]
+    fn reads_deployed_commits() {
+        let commits = parse_active_commits(LIST_ACTIVE);
+
+        assert_eq!(commits.len(), 2);
+        assert!(commits["org.mozilla.firefox//stable"].starts_with("9f3c1e7a"));
+    }
+
+    #[test]
+    fn parses_extra_installations() {
+        let installations = parse_installations(EXTRA_CONF);
+
+        let ids: Vec<_> = installations.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, ["extra", "sdcard"]);
+        assert_eq!(
+            installations[1].path.as_deref(),
+            Some(std::path::Path::new("/var/mnt/sdcard/flatpak"))
+        );
+        assert!(parse_installations("[Core]\nPath=/x\n").is_empty());
+    }
+}
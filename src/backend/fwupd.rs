@@ -0,0 +1,268 @@
+use super::{CheckStatus, PendingUpdate, RebootRequirement, TransactionSummary, UpdateBackend};
+use crate::config::FwupdConfig;
+use crate::output::Reporter;
+use crate::runner::CommandRunner;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+
+/// Exit status fwupdmgr uses when there is nothing to do
+const EXIT_NOTHING_TO_DO: i32 = 2;
+
+/// Device flags meaning the new firmware is only applied by a reboot or shutdown
+const REBOOT_FLAGS: [&str; 2] = ["needs-reboot", "needs-shutdown"];
+
+/// Output of `fwupdmgr get-updates --json`
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct UpdateList {
+    #[serde(default)]
+    devices: Vec<Device>,
+}
+
+/// A device with firmware releases newer than the installed version
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Device {
+    name: String,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    flags: Vec<String>,
+    /// Available releases, newest first
+    #[serde(default)]
+    releases: Vec<Release>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Release {
+    version: String,
+    #[serde(default)]
+    remote_id: Option<String>,
+    /// Size of the firmware archive in bytes
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+impl Device {
+    /// Whether updating this device only takes effect after a reboot
+    fn needs_reboot(&self) -> bool {
+        self.flags
+            .iter()
+            .any(|flag| REBOOT_FLAGS.contains(&flag.as_str()))
+    }
+}
+
+/// Parses `fwupdmgr get-updates --json`, keeping devices that have a release
+fn parse_updates(output: &str) -> Result<Vec<Device>> {
+    let list: UpdateList =
+        serde_json::from_str(output).context("Invalid JSON from fwupdmgr get-updates")?;
+    Ok(list
+        .devices
+        .into_iter()
+        .filter(|device| !device.releases.is_empty())
+        .collect())
+}
+
+/// Updates device firmware such as BIOS, docks and SSDs through fwupd
+///
+/// Flashing firmware is only done after confirmation in interactive runs,
+/// or unattended when `auto_apply` is set.
+#[derive(Debug, Default)]
+pub(crate) struct FwupdBackend {
+    interactive: bool,
+    config: FwupdConfig,
+    // Devices with pending firmware, found by the last check
+    devices: Vec<Device>,
+    // Set when the last apply was not confirmed, so the devices are still pending
+    declined: bool,
+}
+
+impl FwupdBackend {
+    pub(crate) fn new(interactive: bool, config: FwupdConfig) -> Self {
+        Self {
+            interactive,
+            config,
+            devices: Vec::new(),
+            declined: false,
+        }
+    }
+
+    /// Decides whether firmware may be flashed in this run
+    fn confirm(&self, reporter: &Reporter) -> Result<bool> {
+        if self.config.auto_apply {
+            return Ok(true);
+        }
+        if !self.interactive {
+            reporter.warn(
+                "Firmware updates are available but were not applied. \
+                 Run with -i to confirm them, or set auto_apply in the [fwupd] configuration.",
+            );
+            return Ok(false);
+        }
+
+        reporter.confirm(
+            &format!("\nApply {} firmware updates now?", self.devices.len()),
+            false,
+        )
+    }
+}
+
+#[async_trait]
+impl UpdateBackend for FwupdBackend {
+    fn name(&self) -> &'static str {
+        "fwupd"
+    }
+
+    async fn detect(&mut self, runner: &mut CommandRunner) -> bool {
+        runner.is_available("fwupdmgr").await
+    }
+
+    async fn check(&mut self, runner: &mut CommandRunner) -> Result<CheckStatus> {
+        runner
+            .reporter()
+            .progress("Checking for firmware updates...");
+
+        // Stale metadata still lists known updates, so a failed refresh is not fatal.
+        // Dry runs leave the metadata alone and use what is cached
+        if !runner.dry_run() {
+            let (status, _) = runner
+                .execute_command("fwupdmgr", &["refresh"], false)
+                .await?;
+            if !status.success() && status.code() != Some(EXIT_NOTHING_TO_DO) {
+                runner
+                    .reporter()
+                    .warn("Could not refresh firmware metadata. Using cached metadata.");
+            }
+        }
+
+        let output = runner
+            .capture_command("fwupdmgr", &["get-updates", "--json"])
+            .await
+            .context("Failed to run fwupdmgr get-updates")?;
+
+        self.devices = match output.status.code() {
+            Some(EXIT_NOTHING_TO_DO) => Vec::new(),
+            Some(0) => parse_updates(&String::from_utf8_lossy(&output.stdout))?,
+            _ => return Err(anyhow::anyhow!("Firmware update check failed")),
+        };
+
+        if self.devices.is_empty() {
+            runner.reporter().progress("No firmware updates available.");
+            return Ok(CheckStatus::UpToDate);
+        }
+
+        runner
+            .reporter()
+            .progress("Firmware updates are available.");
+        Ok(CheckStatus::Available(
+            self.devices
+                .iter()
+                .map(|device| PendingUpdate {
+                    name: device.name.clone(),
+                    current_version: device.version.clone(),
+                    new_version: Some(device.releases[0].version.clone()),
+                    origin: device.releases[0].remote_id.clone(),
+                })
+                .collect(),
+        ))
+    }
+
+    async fn preview(&mut self, runner: &mut CommandRunner) -> Result<TransactionSummary> {
+        runner
+            .reporter()
+            .progress("Firmware dry run: no devices will be flashed.");
+        Ok(TransactionSummary {
+            upgrade: self.devices.len() as u32,
+            download_size: Some(
+                self.devices
+                    .iter()
+                    .filter_map(|device| device.releases[0].size)
+                    .sum(),
+            ),
+            ..TransactionSummary::default()
+        })
+    }
+
+    async fn apply(&mut self, runner: &mut CommandRunner) -> Result<bool> {
+        self.declined = !self.confirm(runner.reporter())?;
+        if self.declined {
+            return Ok(false);
+        }
+
+        runner.reporter().progress("Updating device firmware...");
+
+        // The final summary reports reboots, so fwupdmgr must not prompt for one
+        let (status, _) = runner
+            .execute_command(
+                "fwupdmgr",
+                &["update", "--assume-yes", "--no-reboot-check"],
+                true,
+            )
+            .await?;
+
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(EXIT_NOTHING_TO_DO) => Ok(false),
+            _ => Err(anyhow::anyhow!("Firmware update failed")),
+        }
+    }
+
+    fn left_pending(&self) -> bool {
+        self.declined
+    }
+
+    async fn post_apply(&mut self, runner: &mut CommandRunner) -> Result<RebootRequirement> {
+        let pending: Vec<&str> = self
+            .devices
+            .iter()
+            .filter(|device| device.needs_reboot())
+            .map(|device| device.name.as_str())
+            .collect();
+
+        if pending.is_empty() {
+            return Ok(RebootRequirement::None);
+        }
+
+        runner.reporter().warn(&format!(
+            "Firmware will be installed on the next reboot: {}",
+            pending.join(", ")
+        ));
+        Ok(RebootRequirement::Reboot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GET_UPDATES: &str = include_str!("../../tests/fixtures/fwupd/get-updates.json");
+
+    #[test]
+    fn parses_devices_and_newest_release() {
+        let devices = parse_updates(GET_UPDATES).unwrap();
+
+        assert_eq!(devices.len(), 3);
+        assert_eq!(devices[0].name, "System Firmware");
+        assert_eq!(devices[0].version.as_deref(), Some("0.1.41"));
+        assert_eq!(devices[0].releases[0].version, "0.1.43");
+        assert_eq!(devices[0].releases[0].size, Some(26394016));
+        assert_eq!(devices[0].releases[0].remote_id.as_deref(), Some("lvfs"));
+    }
+
+    #[test]
+    fn detects_devices_that_need_a_reboot() {
+        let devices = parse_updates(GET_UPDATES).unwrap();
+        let flags: Vec<_> = devices.iter().map(Device::needs_reboot).collect();
+
+        assert_eq!(flags, [true, true, false]);
+    }
+
+    #[test]
+    fn accepts_empty_device_list() {
+        assert!(parse_updates(r#"{ "Devices" : [] }"#).unwrap().is_empty());
+        assert!(parse_updates("{}").unwrap().is_empty());
+        assert!(parse_updates("No updatable devices").is_err());
+    }
+}
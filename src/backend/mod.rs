@@ -0,0 +1,1655 @@
+mod containers;
+mod dnf5;
+mod flatpak;
+mod fwupd;
+mod rpm_ostree;
+
+use crate::config::{BackendKind, Config};
+use crate::download::DownloadManifest;
+use crate::output::{Event, Reporter};
+use crate::preflight::{self, SpaceRequirement};
+use crate::rollback::UndoStep;
+use crate::runner::CommandRunner;
+use crate::size;
+use crate::snapshot::Snapshot;
+use anyhow::Result;
+use async_trait::async_trait;
+use colored::*;
+use serde::{Deserialize, Serialize};
+
+pub(crate) use containers::ContainersBackend;
+pub(crate) use dnf5::{
+    AdvisorySeverity, Autoremove, Dnf5Backend, UpdateMode, resolve_undo, run_dnf5,
+};
+pub(crate) use flatpak::FlatpakBackend;
+pub(crate) use fwupd::FwupdBackend;
+pub(crate) use rpm_ostree::RpmOstreeBackend;
+
+/// Marker file present on systems booted from an ostree deployment
+const OSTREE_BOOTED_PATH: &str = "/run/ostree-booted";
+
+/// A single pending update reported by a backend's check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PendingUpdate {
+    pub(crate) name: String,
+    pub(crate) current_version: Option<String>,
+    pub(crate) new_version: Option<String>,
+    /// Repository or remote the update comes from
+    pub(crate) origin: Option<String>,
+}
+
+/// Package counts and sizes of a transaction previewed in a dry run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct TransactionSummary {
+    pub(crate) install: u32,
+    pub(crate) upgrade: u32,
+    pub(crate) downgrade: u32,
+    pub(crate) remove: u32,
+    /// Bytes that still have to be downloaded
+    pub(crate) download_size: Option<u64>,
+    /// Change in installed size in bytes; negative when space is freed
+    pub(crate) installed_size_delta: Option<i64>,
+}
+
+impl TransactionSummary {
+    /// One-line human-readable description of the transaction
+    pub(crate) fn describe(&self) -> String {
+        let mut text = format!(
+            "install {}, upgrade {}, downgrade {}, remove {}",
+            self.install, self.upgrade, self.downgrade, self.remove
+        );
+        if let Some(download) = self.download_size {
+            text.push_str(&format!("; download {}", size::format_size(download)));
+        }
+        match self.installed_size_delta {
+            Some(delta) if delta >= 0 => text.push_str(&format!(
+                "; {} extra disk space",
+                size::format_size(delta.unsigned_abs())
+            )),
+            Some(delta) => text.push_str(&format!(
+                "; {} freed",
+                size::format_size(delta.unsigned_abs())
+            )),
+            None => {}
+        }
+        text
+    }
+}
+
+/// What happened to one of several items a backend updates independently
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ItemOutcome {
+    /// Nothing was pending
+    #[default]
+    UpToDate,
+    /// The item cannot be updated, such as a container of an unknown distribution
+    Skipped,
+    /// A dry run found pending updates
+    Previewed,
+    /// Updates were downloaded to be applied later
+    Downloaded,
+    /// Pending updates were applied
+    Updated,
+    /// Checking or updating the item returned an error
+    Failed,
+    /// The item's changes were undone by `rollback last`
+    RolledBack,
+}
+
+impl ItemOutcome {
+    /// Short machine-readable name of the outcome
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Self::UpToDate => "up_to_date",
+            Self::Skipped => "skipped",
+            Self::Previewed => "previewed",
+            Self::Downloaded => "downloaded",
+            Self::Updated => "updated",
+            Self::Failed => "failed",
+            Self::RolledBack => "rolled_back",
+        }
+    }
+}
+
+/// Result for one of several items a backend updates independently, such as a container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ItemReport {
+    pub(crate) name: String,
+    pub(crate) outcome: ItemOutcome,
+    /// Why the item failed; set only for failures
+    pub(crate) error: Option<String>,
+    /// What changed or why the item was skipped, such as the refs that were updated
+    #[serde(default)]
+    pub(crate) detail: Option<String>,
+}
+
+/// Result of looking for pending updates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CheckStatus {
+    /// Nothing to apply
+    UpToDate,
+    /// Updates are pending and should be applied
+    Available(Vec<PendingUpdate>),
+}
+
+impl CheckStatus {
+    /// Short machine-readable name of the status
+    fn label(&self) -> &'static str {
+        match self {
+            Self::UpToDate => "up_to_date",
+            Self::Available(_) => "available",
+        }
+    }
+}
+
+/// What the system needs after updates were applied, ordered by severity
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) enum RebootRequirement {
+    /// Nothing further is required
+    #[default]
+    None,
+    /// The listed systemd services should be restarted
+    Services(Vec<String>),
+    /// A full reboot is required to finish applying updates
+    Reboot,
+}
+
+impl RebootRequirement {
+    /// Short machine-readable name of the requirement
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Services(_) => "services",
+            Self::Reboot => "reboot",
+        }
+    }
+
+    /// Combines two requirements, keeping the more severe one
+    ///
+    /// Service lists are merged when neither side needs a full reboot.
+    pub(crate) fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Reboot, _) | (_, Self::Reboot) => Self::Reboot,
+            (Self::Services(mut services), Self::Services(more)) => {
+                for service in more {
+                    if !services.contains(&service) {
+                        services.push(service);
+                    }
+                }
+                Self::Services(services)
+            }
+            (Self::Services(services), Self::None) | (Self::None, Self::Services(services)) => {
+                Self::Services(services)
+            }
+            (Self::None, Self::None) => Self::None,
+        }
+    }
+}
+
+/// Which part of an update a run performs
+#[derive(Debug, Clone, Default)]
+pub(crate) enum UpdateStage {
+    /// Check, download and apply in one go
+    #[default]
+    Full,
+    /// Download pending updates into the package caches and stop
+    DownloadOnly,
+    /// Apply what an earlier download-only run fetched, without using the network
+    ApplyDownloaded(DownloadManifest),
+}
+
+/// A source of system updates such as Flatpak or DNF5
+#[async_trait]
+pub(crate) trait UpdateBackend: Send {
+    /// Display name used in progress and summary messages
+    fn name(&self) -> &'static str;
+
+    /// Whether a missing backend is an error rather than a skipped step
+    fn required(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the backend's tooling is installed
+    async fn detect(&mut self, runner: &mut CommandRunner) -> bool;
+
+    /// Looks for pending updates without changing the system
+    async fn check(&mut self, runner: &mut CommandRunner) -> Result<CheckStatus>;
+
+    /// Resolves what applying would do without changing the system
+    async fn preview(&mut self, runner: &mut CommandRunner) -> Result<TransactionSummary>;
+
+    /// Applies pending updates, returning whether anything changed
+    async fn apply(&mut self, runner: &mut CommandRunner) -> Result<bool>;
+
+    /// Whether the last apply deliberately left the pending updates alone,
+    /// such as firmware that was not confirmed
+    fn left_pending(&self) -> bool {
+        false
+    }
+
+    /// Whether updates can be downloaded in one run and applied in a later one
+    fn supports_download(&self) -> bool {
+        false
+    }
+
+    /// Downloads pending updates into the cache without applying them
+    async fn download(&mut self, _runner: &mut CommandRunner) -> Result<()> {
+        Ok(())
+    }
+
+    /// Applies updates from the cache only, returning whether anything changed
+    ///
+    /// `downloaded` lists what the download-only run fetched; updates the
+    /// cache does not hold are an error rather than a silent download.
+    async fn apply_downloaded(
+        &mut self,
+        _runner: &mut CommandRunner,
+        _downloaded: &[PendingUpdate],
+    ) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Disk space the next download or apply needs, by filesystem path
+    async fn required_space(
+        &mut self,
+        _runner: &mut CommandRunner,
+        _stage: &UpdateStage,
+    ) -> Result<Vec<SpaceRequirement>> {
+        Ok(Vec::new())
+    }
+
+    /// Reports what the system needs after updates were applied
+    async fn post_apply(&mut self, _runner: &mut CommandRunner) -> Result<RebootRequirement> {
+        Ok(RebootRequirement::None)
+    }
+
+    /// Removes what updates left behind, such as unused runtimes
+    ///
+    /// Runs after a successful check or apply, but never in dry runs.
+    /// Backends decide from their configuration whether anything is done.
+    async fn cleanup(&mut self, _runner: &mut CommandRunner) -> Result<()> {
+        Ok(())
+    }
+
+    /// Per-item results of the last run, reported in the summary
+    fn items(&self) -> Vec<ItemReport> {
+        Vec::new()
+    }
+
+    /// Things the user should know after the run, such as end-of-life runtimes
+    fn notes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Snapshots taken around the last transaction, recorded in the history
+    fn snapshots(&self) -> Vec<Snapshot> {
+        Vec::new()
+    }
+
+    /// How to revert the changes of the last apply, recorded in the history
+    fn undo_steps(&self) -> Vec<UndoStep> {
+        Vec::new()
+    }
+}
+
+/// What happened to a single backend during a run
+#[derive(Debug)]
+pub(crate) enum BackendOutcome {
+    /// The backend's tooling is not installed
+    Skipped,
+    /// No updates were pending
+    UpToDate,
+    /// Updates were applied
+    Updated {
+        reboot: RebootRequirement,
+        /// Updates reported by the check that preceded applying them
+        packages: Vec<PendingUpdate>,
+    },
+    /// A dry run resolved the transaction without applying it
+    Previewed(TransactionSummary),
+    /// Updates were downloaded to be applied by a later run
+    Downloaded(Vec<PendingUpdate>),
+    /// Updates are available but were not applied without confirmation
+    Pending(Vec<PendingUpdate>),
+}
+
+impl BackendOutcome {
+    /// Kind of the outcome, without its details
+    pub(crate) fn kind(&self) -> OutcomeKind {
+        match self {
+            Self::Skipped => OutcomeKind::Skipped,
+            Self::UpToDate => OutcomeKind::UpToDate,
+            Self::Updated { .. } => OutcomeKind::Updated,
+            Self::Previewed(_) => OutcomeKind::Previewed,
+            Self::Downloaded(_) => OutcomeKind::Downloaded,
+            Self::Pending(_) => OutcomeKind::Pending,
+        }
+    }
+}
+
+/// Kind of a backend's result, as recorded in the journal and JSON output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OutcomeKind {
+    /// The backend's tooling is not installed
+    Skipped,
+    /// No updates were pending
+    UpToDate,
+    /// Updates were applied
+    Updated,
+    /// A dry run resolved the transaction without applying it
+    Previewed,
+    /// Updates were downloaded to be applied by a later run
+    Downloaded,
+    /// Updates are available but were not applied without confirmation
+    Pending,
+    /// The backend returned an error
+    Failed,
+    /// The backend's changes were undone by `rollback last`
+    RolledBack,
+}
+
+impl OutcomeKind {
+    /// Kind of a backend's result, counting an error as a failure
+    pub(crate) fn of(outcome: &Result<BackendOutcome>) -> Self {
+        outcome.as_ref().map_or(Self::Failed, BackendOutcome::kind)
+    }
+
+    /// Short machine-readable name of the outcome
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Self::Skipped => "skipped",
+            Self::UpToDate => "up_to_date",
+            Self::Updated => "updated",
+            Self::Previewed => "previewed",
+            Self::Downloaded => "downloaded",
+            Self::Pending => "pending",
+            Self::Failed => "failed",
+            Self::RolledBack => "rolled_back",
+        }
+    }
+}
+
+/// Per-backend result collected by the driver
+#[derive(Debug)]
+pub(crate) struct BackendReport {
+    pub(crate) name: &'static str,
+    pub(crate) outcome: Result<BackendOutcome>,
+    /// Results of the items the backend updated independently
+    pub(crate) items: Vec<ItemReport>,
+    /// Notes printed with the backend's result in the summary
+    pub(crate) notes: Vec<String>,
+    /// Snapshots taken around the backend's transaction
+    pub(crate) snapshots: Vec<Snapshot>,
+    /// How to revert what the backend changed
+    pub(crate) undo: Vec<UndoStep>,
+}
+
+/// Result of checking a single backend for pending updates
+#[derive(Debug)]
+pub(crate) struct CheckReport {
+    pub(crate) name: &'static str,
+    /// `None` when the backend's tooling is not installed
+    pub(crate) status: Result<Option<CheckStatus>>,
+    /// Notes about the pending updates, such as advisory counts
+    pub(crate) notes: Vec<String>,
+}
+
+/// Borrows owned arguments for `CommandRunner::execute_command`
+pub(crate) fn str_args(args: &[String]) -> Vec<&str> {
+    args.iter().map(String::as_str).collect()
+}
+
+/// Prints a table of updates with their installed and new versions
+pub(crate) fn show_updates(reporter: &Reporter, heading: &str, updates: &[PendingUpdate]) {
+    if !reporter.is_text() {
+        return;
+    }
+
+    println!("\n{}", heading.blue().bold());
+
+    let width = updates.iter().map(|u| u.name.len()).max().unwrap_or(0) + 1;
+    for update in updates {
+        let origin = match &update.origin {
+            Some(origin) => format!("[{}]", origin).dimmed().to_string(),
+            None => String::new(),
+        };
+        println!(
+            "  {:width$} {} -> {} {}",
+            format!("{}:", update.name),
+            update
+                .current_version
+                .as_deref()
+                .unwrap_or("(not installed)"),
+            update.new_version.as_deref().unwrap_or_default().green(),
+            origin,
+            width = width
+        );
+    }
+    println!();
+}
+
+/// Builds the enabled backends in the configured order
+///
+/// On ostree-booted systems the base image is managed by rpm-ostree, so it
+/// takes the place of DNF5.
+pub(crate) fn registry(config: &Config, interactive: bool) -> Vec<Box<dyn UpdateBackend>> {
+    let ostree_booted = std::path::Path::new(OSTREE_BOOTED_PATH).exists();
+
+    let mut kinds: Vec<BackendKind> = Vec::with_capacity(config.backends.len());
+    for &kind in &config.backends {
+        let kind = match kind {
+            BackendKind::Dnf5 if ostree_booted => BackendKind::RpmOstree,
+            kind => kind,
+        };
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+
+    kinds
+        .into_iter()
+        .map(|kind| -> Box<dyn UpdateBackend> {
+            match kind {
+                BackendKind::Flatpak => Box::new(FlatpakBackend::new(config.flatpak.clone())),
+                BackendKind::Dnf5 => Box::new(
+                    Dnf5Backend::new(interactive, config.dnf5.clone())
+                        .with_snapshots(config.snapshot.clone()),
+                ),
+                BackendKind::RpmOstree => Box::new(RpmOstreeBackend::new()),
+                BackendKind::Fwupd => {
+                    Box::new(FwupdBackend::new(interactive, config.fwupd.clone()))
+                }
+                BackendKind::Containers => Box::new(ContainersBackend::new()),
+            }
+        })
+        .collect()
+}
+
+/// Runs every backend in turn, collecting a report for each
+pub(crate) async fn run_backends(
+    runner: &mut CommandRunner,
+    backends: &mut [Box<dyn UpdateBackend>],
+    stage: &UpdateStage,
+) -> Vec<BackendReport> {
+    let mut reports = Vec::with_capacity(backends.len());
+
+    for backend in backends.iter_mut() {
+        let outcome = run_backend(runner, backend.as_mut(), stage).await;
+        let items = backend.items();
+        let notes = backend.notes();
+        runner.reporter().emit(Event::Outcome {
+            backend: backend.name().to_string(),
+            outcome: OutcomeKind::of(&outcome),
+            error: outcome.as_ref().err().map(|e| format!("{:#}", e)),
+            items: items.clone(),
+            notes: notes.clone(),
+        });
+        reports.push(BackendReport {
+            name: backend.name(),
+            outcome,
+            items,
+            notes,
+            snapshots: backend.snapshots(),
+            undo: backend.undo_steps(),
+        });
+    }
+
+    reports
+}
+
+/// Checks every backend for pending updates without applying anything
+pub(crate) async fn check_backends(
+    runner: &mut CommandRunner,
+    backends: &mut [Box<dyn UpdateBackend>],
+) -> Vec<CheckReport> {
+    let mut reports = Vec::with_capacity(backends.len());
+
+    for backend in backends.iter_mut() {
+        let status = match detect_backend(runner, backend.as_mut()).await {
+            Ok(true) => backend.check(runner).await.map(Some),
+            Ok(false) => Ok(None),
+            Err(e) => Err(e),
+        };
+        let notes = backend.notes();
+        emit_check(
+            runner.reporter(),
+            backend.name(),
+            status.as_ref().map(Option::as_ref),
+            &notes,
+        );
+        reports.push(CheckReport {
+            name: backend.name(),
+            status,
+            notes,
+        });
+    }
+
+    reports
+}
+
+/// Emits the machine-readable result of checking a backend
+fn emit_check(
+    reporter: &Reporter,
+    name: &str,
+    status: Result<Option<&CheckStatus>, &anyhow::Error>,
+    notes: &[String],
+) {
+    let (label, pending, error) = match status {
+        Ok(Some(CheckStatus::Available(pending))) => ("available", pending.clone(), None),
+        Ok(Some(status)) => (status.label(), Vec::new(), None),
+        Ok(None) => ("not_installed", Vec::new(), None),
+        Err(e) => ("failed", Vec::new(), Some(format!("{:#}", e))),
+    };
+
+    reporter.emit(Event::Check {
+        backend: name.to_string(),
+        status: label,
+        pending,
+        error,
+        notes: notes.to_vec(),
+    });
+}
+
+/// Detects a backend, failing if it is required but missing
+async fn detect_backend(
+    runner: &mut CommandRunner,
+    backend: &mut dyn UpdateBackend,
+) -> Result<bool> {
+    let name = backend.name();
+
+    if backend.detect(runner).await {
+        return Ok(true);
+    }
+
+    let reporter = runner.reporter();
+    if backend.required() {
+        reporter.error(&format!(
+            "{} is not installed. Please install it first.",
+            name
+        ));
+        return Err(anyhow::anyhow!("{} not found", name));
+    }
+
+    reporter.warn(&format!(
+        "{} is not installed. Skipping {} updates.",
+        name, name
+    ));
+    Ok(false)
+}
+
+/// Drives a single backend through detect, check, apply and post-apply
+async fn run_backend(
+    runner: &mut CommandRunner,
+    backend: &mut dyn UpdateBackend,
+    stage: &UpdateStage,
+) -> Result<BackendOutcome> {
+    if !detect_backend(runner, backend).await? {
+        return Ok(BackendOutcome::Skipped);
+    }
+
+    if !matches!(stage, UpdateStage::Full) && !backend.supports_download() {
+        let name = backend.name();
+        runner.reporter().warn(&format!(
+            "{} cannot download updates ahead of time. Skipping {} updates.",
+            name, name
+        ));
+        return Ok(BackendOutcome::Skipped);
+    }
+    if let UpdateStage::ApplyDownloaded(manifest) = stage {
+        return apply_downloaded(runner, backend, stage, manifest).await;
+    }
+
+    let status = backend.check(runner).await;
+    emit_check(
+        runner.reporter(),
+        backend.name(),
+        status.as_ref().map(Some),
+        &backend.notes(),
+    );
+    let packages = match status? {
+        CheckStatus::UpToDate => {
+            // Cleaning the package cache would throw away earlier downloads
+            if !runner.dry_run() && matches!(stage, UpdateStage::Full) {
+                run_cleanup(runner, backend).await;
+            }
+            return Ok(BackendOutcome::UpToDate);
+        }
+        CheckStatus::Available(packages) => packages,
+    };
+
+    if runner.dry_run() {
+        let transaction = backend.preview(runner).await?;
+        runner.reporter().emit(Event::Transaction {
+            backend: backend.name().to_string(),
+            summary: transaction.clone(),
+        });
+        return Ok(BackendOutcome::Previewed(transaction));
+    }
+
+    ensure_space(runner, backend, stage).await?;
+    if let UpdateStage::DownloadOnly = stage {
+        backend.download(runner).await?;
+        return Ok(BackendOutcome::Downloaded(packages));
+    }
+
+    if !backend.apply(runner).await? {
+        run_cleanup(runner, backend).await;
+        if backend.left_pending() {
+            return Ok(BackendOutcome::Pending(packages));
+        }
+        return Ok(BackendOutcome::UpToDate);
+    }
+
+    let reboot = backend.post_apply(runner).await?;
+    run_cleanup(runner, backend).await;
+    Ok(BackendOutcome::Updated { reboot, packages })
+}
+
+/// Applies the updates a backend fetched in an earlier download-only run
+async fn apply_downloaded(
+    runner: &mut CommandRunner,
+    backend: &mut dyn UpdateBackend,
+    stage: &UpdateStage,
+    manifest: &DownloadManifest,
+) -> Result<BackendOutcome> {
+    let Some(packages) = manifest.packages(backend.name()) else {
+        runner
+            .reporter()
+            .progress(&format!("No {} updates were downloaded.", backend.name()));
+        return Ok(BackendOutcome::UpToDate);
+    };
+
+    ensure_space(runner, backend, stage).await?;
+    if !backend.apply_downloaded(runner, packages).await? {
+        run_cleanup(runner, backend).await;
+        return Ok(BackendOutcome::UpToDate);
+    }
+
+    let reboot = backend.post_apply(runner).await?;
+    run_cleanup(runner, backend).await;
+    Ok(BackendOutcome::Updated {
+        reboot,
+        packages: packages.to_vec(),
+    })
+}
+
+/// Fails the backend when a filesystem lacks room for its transaction
+///
+/// An estimate that cannot be made only warns, so it never blocks updates.
+async fn ensure_space(
+    runner: &mut CommandRunner,
+    backend: &mut dyn UpdateBackend,
+    stage: &UpdateStage,
+) -> Result<()> {
+    match backend.required_space(runner, stage).await {
+        Ok(requirements) => preflight::check_space(runner, backend.name(), &requirements).await,
+        Err(e) => {
+            runner.reporter().warn(&format!(
+                "Could not estimate the disk space {} needs: {:#}",
+                backend.name(),
+                e
+            ));
+            Ok(())
+        }
+    }
+}
+
+/// Runs a backend's cleanup; the updates already succeeded, so failures only warn
+async fn run_cleanup(runner: &mut CommandRunner, backend: &mut dyn UpdateBackend) {
+    if let Err(e) = backend.cleanup(runner).await {
+        runner
+            .reporter()
+            .warn(&format!("{} cleanup failed: {:#}", backend.name(), e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputFormat;
+    use crate::process::fake::ScriptedExecutor;
+    use std::sync::Arc;
+
+    const CHECK_UPGRADE: &str = include_str!("../../tests/fixtures/dnf5/check-upgrade.txt");
+    const UPGRADE_ASSUMENO: &str = include_str!("../../tests/fixtures/dnf5/upgrade-assumeno.txt");
+    const RPM_QUERY: &str = include_str!("../../tests/fixtures/dnf5/rpm-query.txt");
+    const ADVISORY_LIST: &str = include_str!("../../tests/fixtures/dnf5/advisory-list.txt");
+    const ADVISORY_LIST_CVE: &str = include_str!("../../tests/fixtures/dnf5/advisory-list-cve.txt");
+    const HISTORY_LIST: &str = include_str!("../../tests/fixtures/dnf5/history-list.txt");
+    const FLATPAK_UPDATE: &str = include_str!("../../tests/fixtures/flatpak/update.txt");
+    const FLATPAK_LIST: &str = include_str!("../../tests/fixtures/flatpak/list-active.txt");
+
+    /// DNF5 history before the upgrade, whose transaction is 14 in `HISTORY_LIST`
+    const HISTORY_BEFORE: &str = "ID Command line Date and time Action(s) Altered\n\
+                                  13 dnf5 install htop 2025-01-10 09:00:00 1\n";
+
+    /// Directory without extra Flatpak installations, so the host's are ignored
+    const NO_INSTALLATIONS_DIR: &str = "tests/fixtures/flatpak/missing";
+
+    fn flatpak_backend(installations_dir: &str) -> FlatpakBackend {
+        FlatpakBackend::with_installations_dir(Default::default(), installations_dir.into())
+    }
+
+    /// Runs the default backends against a scripted executor
+    async fn run(executor: ScriptedExecutor) -> (Vec<BackendReport>, Arc<ScriptedExecutor>) {
+        let backends = vec![
+            Box::new(flatpak_backend(NO_INSTALLATIONS_DIR)) as Box<dyn UpdateBackend>,
+            Box::new(Dnf5Backend::new(false, Default::default())),
+        ];
+        run_with(executor, backends).await
+    }
+
+    /// Runs the given backends against a scripted executor
+    async fn run_with(
+        executor: ScriptedExecutor,
+        backends: Vec<Box<dyn UpdateBackend>>,
+    ) -> (Vec<BackendReport>, Arc<ScriptedExecutor>) {
+        run_stage(executor, backends, UpdateStage::Full).await
+    }
+
+    /// Runs one stage of an update with the given backends against a scripted executor
+    async fn run_stage(
+        executor: ScriptedExecutor,
+        mut backends: Vec<Box<dyn UpdateBackend>>,
+        stage: UpdateStage,
+    ) -> (Vec<BackendReport>, Arc<ScriptedExecutor>) {
+        let executor = Arc::new(executor);
+        // JSON mode collects events silently instead of printing them
+        let mut runner =
+            CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone());
+
+        let reports = run_backends(&mut runner, &mut backends, &stage).await;
+        executor.assert_done();
+        (reports, executor)
+    }
+
+    /// Scripts a DNF5 check that finds the fixture's pending upgrades and
+    /// advisories, followed by the disk space estimate
+    fn dnf5_updates_available(executor: ScriptedExecutor) -> ScriptedExecutor {
+        executor
+            .expect(
+                "sudo env LC_ALL=C dnf5 --refresh check-upgrade",
+                100,
+                CHECK_UPGRADE,
+            )
+            .expect("rpm -q --queryformat", 0, RPM_QUERY)
+            .expect(
+                "sudo env LC_ALL=C dnf5 advisory list --updates",
+                0,
+                ADVISORY_LIST,
+            )
+            .expect(
+                "sudo env LC_ALL=C dnf5 advisory list --updates --with-cve",
+                0,
+                ADVISORY_LIST_CVE,
+            )
+            .expect(
+                "sudo env LC_ALL=C dnf5 upgrade --assumeno",
+                1,
+                UPGRADE_ASSUMENO,
+            )
+    }
+
+    #[tokio::test]
+    async fn dry_run_resolves_dnf5_in_the_c_locale() {
+        let executor = Arc::new(dnf5_updates_available(ScriptedExecutor::new(&[
+            "dnf5", "rpm",
+        ])));
+        let mut runner =
+            CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone());
+        runner.set_dry_run(true);
+        let mut backends: Vec<Box<dyn UpdateBackend>> =
+            vec![Box::new(Dnf5Backend::new(false, Default::default()))];
+
+        let reports = run_backends(&mut runner, &mut backends, &UpdateStage::Full).await;
+
+        executor.assert_done();
+        assert!(matches!(
+            reports[0].outcome,
+            Ok(BackendOutcome::Previewed(_))
+        ));
+        // Translated summaries would not parse, so dnf5 always runs in the C locale
+        assert_eq!(
+            executor.calls().last().map(String::as_str),
+            Some("sudo env LC_ALL=C dnf5 upgrade --assumeno")
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_missing_flatpak_and_finds_no_dnf5_updates() {
+        let executor = ScriptedExecutor::new(&["dnf5", "rpm"]).expect(
+            "sudo env LC_ALL=C dnf5 --refresh check-upgrade",
+            0,
+            "Repositories loaded.\n",
+        );
+
+        let (reports, executor) = run(executor).await;
+
+        assert!(matches!(reports[0].outcome, Ok(BackendOutcome::Skipped)));
+        assert!(matches!(reports[1].outcome, Ok(BackendOutcome::UpToDate)));
+        assert_eq!(executor.calls().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn missing_dnf5_is_an_error() {
+        let executor = ScriptedExecutor::new(&["flatpak"])
+            .expect("env LC_ALL=C flatpak remote-ls --system --updates", 0, "")
+            .expect("env LC_ALL=C flatpak remote-ls --user --updates", 0, "");
+
+        let (reports, _) = run(executor).await;
+
+        assert!(matches!(reports[0].outcome, Ok(BackendOutcome::UpToDate)));
+        assert_eq!(
+            reports[1].outcome.as_ref().unwrap_err().to_string(),
+            "DNF5 not found"
+        );
+    }
+
+    #[tokio::test]
+    async fn applies_available_updates_and_reports_reboot() {
+        let executor = dnf5_updates_available(ScriptedExecutor::new(&["dnf5", "rpm"]))
+            .expect("dnf5 history list", 0, HISTORY_BEFORE)
+            .expect("sudo dnf5 upgrade -y", 0, "Complete!\n")
+            .expect("dnf5 history list", 0, HISTORY_LIST)
+            .expect("sudo dnf5 needs-restarting", 1, "Reboot is required\n");
+
+        let (reports, _) = run(executor).await;
+
+        let Ok(BackendOutcome::Updated { reboot, packages }) = &reports[1].outcome else {
+            panic!("unexpected outcome: {:?}", reports[1].outcome);
+        };
+        assert_eq!(*reboot, RebootRequirement::Reboot);
+        assert_eq!(packages.len(), 6);
+        assert_eq!(packages[0].name, "NetworkManager.x86_64");
+        assert_eq!(
+            packages[0].current_version.as_deref(),
+            Some("1:1.50.0-1.fc41")
+        );
+        assert_eq!(reports[1].undo, [UndoStep::Dnf5Transaction { id: 14 }]);
+    }
+
+    #[tokio::test]
+    async fn security_mode_applies_only_security_advisories() {
+        let executor = ScriptedExecutor::new(&["dnf5", "rpm"])
+            .expect(
+                "sudo env LC_ALL=C dnf5 --refresh check-upgrade --security --advisory-severities=critical,important",
+                100,
+                CHECK_UPGRADE,
+            )
+            .expect("rpm -q --queryformat", 0, RPM_QUERY)
+            .expect(
+                "sudo env LC_ALL=C dnf5 advisory list --updates --security --advisory-severities=critical,important",
+                0,
+                "FEDORA-2025-1a2b3c4d5e security Critical firefox-134.0.1-1.fc41.x86_64 2025-01-15 01:23:45\n\
+                 FEDORA-2025-6f7a8b9c0d security Important openssh-9.9p1-2.fc41.x86_64 2025-01-14 02:10:11\n",
+            )
+            .expect("sudo env LC_ALL=C dnf5 advisory list", 0, ADVISORY_LIST_CVE)
+            .expect(
+                "sudo env LC_ALL=C dnf5 upgrade --assumeno --security --advisory-severities=critical,important",
+                1,
+                UPGRADE_ASSUMENO,
+            )
+            .expect("dnf5 history list", 0, HISTORY_BEFORE)
+            .expect(
+                "sudo dnf5 upgrade -y --security --advisory-severities=critical,important",
+                0,
+                "Complete!\n",
+            )
+            .expect("dnf5 history list", 0, HISTORY_LIST)
+            .expect("sudo dnf5 needs-restarting", 0, "")
+            .expect("sudo env LC_ALL=C dnf5 needs-restarting -s", 0, "");
+        let config = crate::config::Dnf5Config {
+            security: true,
+            advisory_severity: Some(AdvisorySeverity::Important),
+            ..Default::default()
+        };
+
+        let (reports, _) =
+            run_with(executor, vec![Box::new(Dnf5Backend::new(false, config))]).await;
+
+        assert!(matches!(
+            reports[0].outcome,
+            Ok(BackendOutcome::Updated { .. })
+        ));
+        assert_eq!(
+            reports[0].notes,
+            ["Applied security advisories: 1 critical, 1 important"]
+        );
+    }
+
+    #[tokio::test]
+    async fn download_only_fetches_updates_without_applying_them() {
+        let executor = ScriptedExecutor::new(&["flatpak", "dnf5", "rpm"])
+            .expect(
+                "env LC_ALL=C flatpak remote-ls --system --updates",
+                0,
+                "org.mozilla.firefox\tstable\tflathub\t92.4 MB\n",
+            )
+            .expect("flatpak list --system", 0, FLATPAK_LIST)
+            .expect("env LC_ALL=C flatpak remote-ls --user --updates", 0, "")
+            .expect(
+                "env LC_ALL=C flatpak update --system -y --no-deploy",
+                0,
+                FLATPAK_UPDATE,
+            );
+        let executor = dnf5_updates_available(executor).expect(
+            "sudo dnf5 upgrade --downloadonly -y",
+            0,
+            "Complete!\n",
+        );
+        let backends = vec![
+            Box::new(flatpak_backend(NO_INSTALLATIONS_DIR)) as Box<dyn UpdateBackend>,
+            Box::new(Dnf5Backend::new(false, Default::default())),
+        ];
+
+        let (reports, _) = run_stage(executor, backends, UpdateStage::DownloadOnly).await;
+
+        let Ok(BackendOutcome::Downloaded(refs)) = &reports[0].outcome else {
+            panic!("unexpected outcome: {:?}", reports[0].outcome);
+        };
+        assert_eq!(refs[0].name, "org.mozilla.firefox//stable");
+        assert_eq!(reports[0].items[0].outcome, ItemOutcome::Downloaded);
+        let Ok(BackendOutcome::Downloaded(packages)) = &reports[1].outcome else {
+            panic!("unexpected outcome: {:?}", reports[1].outcome);
+        };
+        assert_eq!(packages.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn apply_downloaded_accepts_flatpak_refs_updated_since_the_download() {
+        let update = |name: &str, commit: &str| PendingUpdate {
+            name: name.to_string(),
+            current_version: Some(commit.to_string()),
+            new_version: None,
+            origin: None,
+        };
+        let manifest = DownloadManifest {
+            downloaded_at: chrono::Local::now(),
+            backends: vec![crate::download::DownloadedUpdates {
+                backend: "Flatpak".to_string(),
+                packages: vec![
+                    update("org.mozilla.firefox//stable", "0c1d2e3f4a5b"),
+                    update("org.gimp.GIMP//stable", "aaaaaaaaaaaa"),
+                    update("org.gnome.Builder//stable", "cccccccccccc"),
+                ],
+            }],
+        };
+        let user_list = "org.gimp.GIMP\tstable\tbbbbbbbbbbbbbbbb\n\
+                         org.gnome.Builder\tstable\tcccccccccccccccc\n";
+        let executor = ScriptedExecutor::new(&["flatpak"])
+            .expect("flatpak list --system", 0, FLATPAK_LIST)
+            .expect(
+                "env LC_ALL=C flatpak update --system -y --no-pull",
+                0,
+                FLATPAK_UPDATE,
+            )
+            .expect("flatpak list --user", 0, user_list)
+            .expect(
+                "env LC_ALL=C flatpak update --user -y --no-pull",
+                0,
+                "Nothing to do.\n",
+            )
+            .expect("flatpak list --system", 0, FLATPAK_LIST)
+            .expect("flatpak list --user", 0, user_list);
+        let backends =
+            vec![Box::new(flatpak_backend(NO_INSTALLATIONS_DIR)) as Box<dyn UpdateBackend>];
+
+        let (reports, _) =
+            run_stage(executor, backends, UpdateStage::ApplyDownloaded(manifest)).await;
+
+        // GIMP moved on to another commit, while Builder still has the one it had
+        assert_eq!(
+            reports[0].outcome.as_ref().unwrap_err().to_string(),
+            "These Flatpak refs were not in the local repository: org.gnome.Builder//stable. \
+             Run `update --download-only` again"
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_downloaded_uses_only_the_cache() {
+        let update = |name: &str| PendingUpdate {
+            name: name.to_string(),
+            current_version: None,
+            new_version: None,
+            origin: None,
+        };
+        let manifest = DownloadManifest {
+            downloaded_at: chrono::Local::now(),
+            backends: vec![
+                crate::download::DownloadedUpdates {
+                    backend: "Flatpak".to_string(),
+                    packages: vec![update("org.mozilla.firefox//stable")],
+                },
+                crate::download::DownloadedUpdates {
+                    backend: "DNF5".to_string(),
+                    packages: vec![update("firefox.x86_64")],
+                },
+            ],
+        };
+        let executor = ScriptedExecutor::new(&["flatpak", "dnf5", "rpm"])
+            .expect("flatpak list --system", 0, FLATPAK_LIST)
+            .expect(
+                "env LC_ALL=C flatpak update --system -y --no-pull",
+                0,
+                FLATPAK_UPDATE,
+            )
+            .expect("flatpak list --user", 0, FLATPAK_LIST)
+            .expect(
+                "env LC_ALL=C flatpak update --user -y --no-pull",
+                0,
+                "Nothing to do.\n",
+            )
+            .expect(
+                "sudo env LC_ALL=C dnf5 --cacheonly upgrade --assumeno",
+                1,
+                UPGRADE_ASSUMENO,
+            )
+            .expect(
+                "sudo env LC_ALL=C dnf5 --cacheonly check-upgrade",
+                100,
+                CHECK_UPGRADE,
+            );
+        let backends = vec![
+            Box::new(flatpak_backend(NO_INSTALLATIONS_DIR)) as Box<dyn UpdateBackend>,
+            Box::new(Dnf5Backend::new(false, Default::default())),
+        ];
+
+        let (reports, _) =
+            run_stage(executor, backends, UpdateStage::ApplyDownloaded(manifest)).await;
+
+        assert!(matches!(
+            reports[0].outcome,
+            Ok(BackendOutcome::Updated { .. })
+        ));
+        // The cached metadata lists upgrades that were never downloaded
+        let error = reports[1].outcome.as_ref().unwrap_err().to_string();
+        assert!(error.starts_with("The DNF5 download cache is incomplete"));
+        assert!(error.contains("NetworkManager.x86_64"));
+    }
+
+    #[tokio::test]
+    async fn aborts_dnf5_without_enough_free_space() {
+        let executor = dnf5_updates_available(ScriptedExecutor::new(&["dnf5", "rpm", "df"]))
+            .expect(
+                "df --output=target,avail -B1",
+                0,
+                "Mounted on       Avail\n/     157286400\n",
+            )
+            .expect(
+                "df --output=target,avail -B1",
+                0,
+                "Mounted on       Avail\n/     157286400\n",
+            )
+            .expect(
+                "df --output=target,avail -B1",
+                0,
+                "Mounted on       Avail\n/boot 104857600\n",
+            );
+
+        let (reports, _) = run_with(
+            executor,
+            vec![Box::new(Dnf5Backend::new(false, Default::default()))],
+        )
+        .await;
+
+        // The upgrade itself never runs
+        let error = reports[0].outcome.as_ref().unwrap_err().to_string();
+        assert!(error.starts_with("Not enough disk space for DNF5 updates"));
+        assert!(error.contains(
+            "/ needs 119.0 MiB (package downloads 118.0 MiB, installed packages 1.0 MiB) \
+             but has 150.0 MiB free"
+        ));
+        assert!(error.contains("/boot needs 128.0 MiB (new kernels 128.0 MiB)"));
+    }
+
+    #[tokio::test]
+    async fn reports_services_when_no_reboot_is_needed() {
+        let executor = dnf5_updates_available(ScriptedExecutor::new(&["dnf5", "rpm"]))
+            .expect("dnf5 history list", 0, HISTORY_BEFORE)
+            .expect("sudo dnf5 upgrade -y", 0, "Complete!\n")
+            .expect("dnf5 history list", 0, HISTORY_LIST)
+            .expect("sudo dnf5 needs-restarting", 0, "")
+            .expect(
+                "sudo env LC_ALL=C dnf5 needs-restarting -s",
+                0,
+                "NetworkManager.service\n",
+            );
+
+        let (reports, _) = run(executor).await;
+
+        assert!(matches!(
+            &reports[1].outcome,
+            Ok(BackendOutcome::Updated { reboot: RebootRequirement::Services(services), .. })
+                if services == &["NetworkManager.service"]
+        ));
+    }
+
+    #[tokio::test]
+    async fn brackets_dnf5_upgrade_with_btrfs_snapshots() {
+        const SNAPSHOT: &str = "sudo btrfs subvolume snapshot -r / \
+                                /var/lib/fedora-updater/snapshots/fedora-updater-";
+        let executor = dnf5_updates_available(ScriptedExecutor::new(&["dnf5", "rpm"]))
+            .expect("sudo mkdir -p /var/lib/fedora-updater/snapshots", 0, "")
+            .expect(SNAPSHOT, 0, "")
+            .expect("dnf5 history list", 0, HISTORY_BEFORE)
+            .expect("sudo dnf5 upgrade -y", 0, "Complete!\n")
+            .expect("dnf5 history list", 0, HISTORY_LIST)
+            .expect("sudo dnf5 needs-restarting", 0, "")
+            .expect("sudo env LC_ALL=C dnf5 needs-restarting -s", 0, "")
+            // The post snapshot follows the cleanup phase
+            .expect("sudo mkdir -p /var/lib/fedora-updater/snapshots", 0, "")
+            .expect(SNAPSHOT, 1, "ERROR: Could not create subvolume\n");
+        let snapshots = crate::config::SnapshotConfig {
+            enabled: true,
+            method: crate::snapshot::SnapshotMethod::Btrfs,
+            ..Default::default()
+        };
+        let backend = Dnf5Backend::new(false, Default::default()).with_snapshots(snapshots);
+
+        let (reports, _) = run_with(executor, vec![Box::new(backend)]).await;
+
+        // A failed post snapshot does not fail the update that already succeeded
+        assert!(matches!(
+            reports[0].outcome,
+            Ok(BackendOutcome::Updated { .. })
+        ));
+        assert_eq!(reports[0].snapshots.len(), 1);
+        assert!(reports[0].snapshots[0].id.ends_with("-pre"));
+        assert!(
+            reports[0]
+                .notes
+                .iter()
+                .any(|note| note.starts_with("Created btrfs snapshot /var/lib/fedora-updater"))
+        );
+    }
+
+    #[tokio::test]
+    async fn stages_rpm_ostree_deployment_and_requires_reboot() {
+        let executor = ScriptedExecutor::new(&["rpm-ostree"])
+            .expect(
+                "env LC_ALL=C rpm-ostree upgrade --check",
+                0,
+                include_str!("../../tests/fixtures/rpm-ostree/upgrade-check.txt"),
+            )
+            .expect(
+                "rpm-ostree status --booted",
+                0,
+                include_str!("../../tests/fixtures/rpm-ostree/status-booted.txt"),
+            )
+            .expect(
+                "env LC_ALL=C rpm-ostree upgrade",
+                0,
+                "Staging deployment...done\n",
+            )
+            .expect(
+                "rpm-ostree db diff",
+                0,
+                include_str!("../../tests/fixtures/rpm-ostree/db-diff.txt"),
+            );
+
+        let (reports, _) = run_with(executor, vec![Box::new(RpmOstreeBackend::new())]).await;
+
+        let Ok(BackendOutcome::Updated { reboot, packages }) = &reports[0].outcome else {
+            panic!("unexpected outcome: {:?}", reports[0].outcome);
+        };
+        assert_eq!(*reboot, RebootRequirement::Reboot);
+        assert_eq!(packages[0].name, "fedora:fedora/41/x86_64/silverblue");
+        assert_eq!(packages[0].new_version.as_deref(), Some("41.20250115.0"));
+    }
+
+    #[tokio::test]
+    async fn rpm_ostree_exit_77_means_up_to_date() {
+        let executor = ScriptedExecutor::new(&["rpm-ostree"]).expect(
+            "env LC_ALL=C rpm-ostree upgrade --check",
+            77,
+            "No updates available.\n",
+        );
+
+        let (reports, _) = run_with(executor, vec![Box::new(RpmOstreeBackend::new())]).await;
+
+        assert!(matches!(reports[0].outcome, Ok(BackendOutcome::UpToDate)));
+    }
+
+    #[tokio::test]
+    async fn flashes_firmware_and_requires_reboot_for_capsule_devices() {
+        let executor = ScriptedExecutor::new(&["fwupdmgr"])
+            .expect("fwupdmgr refresh", 2, "Metadata is up to date\n")
+            .expect(
+                "fwupdmgr get-updates --json",
+                0,
+                include_str!("../../tests/fixtures/fwupd/get-updates.json"),
+            )
+            .expect(
+                "sudo fwupdmgr update --assume-yes",
+                0,
+                "Successfully installed firmware\n",
+            );
+        let config = crate::config::FwupdConfig { auto_apply: true };
+
+        let (reports, _) =
+            run_with(executor, vec![Box::new(FwupdBackend::new(false, config))]).await;
+
+        let Ok(BackendOutcome::Updated { reboot, packages }) = &reports[0].outcome else {
+            panic!("unexpected outcome: {:?}", reports[0].outcome);
+        };
+        assert_eq!(*reboot, RebootRequirement::Reboot);
+        assert_eq!(packages.len(), 3);
+        assert_eq!(packages[2].new_version.as_deref(), Some("23.00"));
+    }
+
+    #[tokio::test]
+    async fn leaves_firmware_alone_without_confirmation() {
+        let executor = ScriptedExecutor::new(&["fwupdmgr"])
+            .expect("fwupdmgr refresh", 0, "")
+            .expect(
+                "fwupdmgr get-updates --json",
+                0,
+                include_str!("../../tests/fixtures/fwupd/get-updates.json"),
+            );
+
+        let (reports, _) = run_with(
+            executor,
+            vec![Box::new(FwupdBackend::new(false, Default::default()))],
+        )
+        .await;
+
+        // The devices stay listed as pending instead of being reported as up to date
+        let Ok(BackendOutcome::Pending(devices)) = &reports[0].outcome else {
+            panic!("unexpected outcome: {:?}", reports[0].outcome);
+        };
+        assert_eq!(devices.len(), 3);
+        assert_eq!(devices[0].name, "System Firmware");
+    }
+
+    #[tokio::test]
+    async fn container_failure_does_not_stop_other_containers() {
+        let executor = ScriptedExecutor::new(&["toolbox", "distrobox"])
+            .expect(
+                "toolbox list --containers",
+                0,
+                "CONTAINER ID  CONTAINER NAME     CREATED      STATUS   IMAGE NAME\n\
+                 5a3f9c1d2e4b  fedora-toolbox-41  3 weeks ago  exited   fedora-toolbox:41\n",
+            )
+            .expect(
+                "distrobox list --no-color",
+                0,
+                "ID           | NAME       | STATUS     | IMAGE\n\
+                 1e5c4d2a8b7f | ubuntu-dev | Up 2 hours | ubuntu:24.04\n",
+            )
+            .expect(
+                "toolbox run --container fedora-toolbox-41 cat /etc/os-release",
+                0,
+                include_str!("../../tests/fixtures/containers/os-release-fedora.txt"),
+            )
+            .expect(
+                "toolbox run --container fedora-toolbox-41 dnf5 check-upgrade",
+                100,
+                CHECK_UPGRADE,
+            )
+            .expect(
+                "distrobox enter ubuntu-dev -- cat /etc/os-release",
+                0,
+                include_str!("../../tests/fixtures/containers/os-release-ubuntu.txt"),
+            )
+            .expect(
+                "distrobox enter ubuntu-dev -- apt list --upgradable",
+                0,
+                include_str!("../../tests/fixtures/containers/apt-list-upgradable.txt"),
+            )
+            .expect(
+                "toolbox run --container fedora-toolbox-41 sudo dnf5 upgrade -y",
+                0,
+                "Complete!\n",
+            )
+            .expect("distrobox enter ubuntu-dev -- sudo apt-get update", 0, "")
+            .expect(
+                "distrobox enter ubuntu-dev -- sudo env DEBIAN_FRONTEND=noninteractive apt-get upgrade",
+                100,
+                "E: Could not get lock /var/lib/dpkg/lock-frontend\n",
+            );
+
+        let (reports, _) = run_with(executor, vec![Box::new(ContainersBackend::new())]).await;
+
+        assert_eq!(
+            reports[0].outcome.as_ref().unwrap_err().to_string(),
+            "Updating containers failed: ubuntu-dev"
+        );
+        let outcomes: Vec<_> = reports[0]
+            .items
+            .iter()
+            .map(|item| (item.name.as_str(), item.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("fedora-toolbox-41", ItemOutcome::Updated),
+                ("ubuntu-dev", ItemOutcome::Failed)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn container_checks_leave_packages_alone() {
+        let executor = Arc::new(
+            ScriptedExecutor::new(&["distrobox"])
+                .expect(
+                    "distrobox list --no-color",
+                    0,
+                    "ID           | NAME     | STATUS     | IMAGE\n\
+                     9c2b7e4f1a3d | arch-dev | Up 2 hours | archlinux:latest\n",
+                )
+                .expect(
+                    "distrobox enter arch-dev -- cat /etc/os-release",
+                    0,
+                    "NAME=\"Arch Linux\"\nID=arch\n",
+                )
+                // Without pacman-contrib the sync database is read as it is
+                .expect("distrobox enter arch-dev -- checkupdates", 127, "")
+                .expect(
+                    "distrobox enter arch-dev -- pacman -Qu",
+                    0,
+                    include_str!("../../tests/fixtures/containers/pacman-qu.txt"),
+                ),
+        );
+        let mut runner =
+            CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone());
+        let mut backends: Vec<Box<dyn UpdateBackend>> = vec![Box::new(ContainersBackend::new())];
+
+        let reports = check_backends(&mut runner, &mut backends).await;
+
+        executor.assert_done();
+        assert!(matches!(
+            reports[0].status,
+            Ok(Some(CheckStatus::Available(_)))
+        ));
+        assert!(!executor.calls().iter().any(|call| call.contains("sudo")));
+    }
+
+    #[tokio::test]
+    async fn broken_user_remote_does_not_hide_system_updates() {
+        let executor = ScriptedExecutor::new(&["flatpak"])
+            .expect(
+                "env LC_ALL=C flatpak remote-ls --system --updates",
+                0,
+                "org.mozilla.firefox\tstable\tflathub\t< 92.4 MB\n",
+            )
+            .expect("flatpak list --system", 0, FLATPAK_LIST)
+            .expect(
+                "env LC_ALL=C flatpak remote-ls --installation=extra --updates",
+                0,
+                "org.gimp.GIMP\tstable\tflathub\t< 150.2 MB\n",
+            )
+            .expect("flatpak list --installation=extra", 0, FLATPAK_LIST)
+            .expect(
+                "env LC_ALL=C flatpak remote-ls --installation=sdcard --updates",
+                0,
+                "",
+            )
+            .expect("env LC_ALL=C flatpak remote-ls --user --updates", 1, "")
+            .expect("flatpak list --system", 0, FLATPAK_LIST)
+            .expect("env LC_ALL=C flatpak update --system -y", 0, FLATPAK_UPDATE)
+            .expect("flatpak list --installation=extra", 0, FLATPAK_LIST)
+            .expect(
+                "env LC_ALL=C flatpak update --installation=extra -y",
+                0,
+                " 1.     org.gimp.GIMP     stable     u     flathub     < 150.2 MB\n",
+            );
+
+        let backend = flatpak_backend("tests/fixtures/flatpak/installations.d");
+        let (reports, _) = run_with(executor, vec![Box::new(backend)]).await;
+
+        assert_eq!(
+            reports[0].outcome.as_ref().unwrap_err().to_string(),
+            "Flatpak installations failed: user"
+        );
+        let outcomes: Vec<_> = reports[0]
+            .items
+            .iter()
+            .map(|item| (item.name.as_str(), item.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("system", ItemOutcome::Updated),
+                ("extra", ItemOutcome::Updated),
+                ("sdcard", ItemOutcome::UpToDate),
+                ("user", ItemOutcome::Failed)
+            ]
+        );
+        assert_eq!(reports[0].items[1].error, None);
+        assert_eq!(
+            reports[0].items[1].detail.as_deref(),
+            Some("updated org.gimp.GIMP//stable")
+        );
+        assert!(reports[0].items[3].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn cleanup_removes_unused_runtimes_and_reports_end_of_life() {
+        let executor = ScriptedExecutor::new(&["flatpak"])
+            .expect(
+                "env LC_ALL=C flatpak remote-ls --system --updates",
+                0,
+                "org.mozilla.firefox\tstable\tflathub\t< 92.4 MB\n",
+            )
+            .expect("flatpak list --system", 0, FLATPAK_LIST)
+            .expect("env LC_ALL=C flatpak remote-ls --user --updates", 0, "")
+            .expect("flatpak list --system", 0, FLATPAK_LIST)
+            .expect(
+                "env LC_ALL=C flatpak update --system -y",
+                0,
+                include_str!("../../tests/fixtures/flatpak/update-eol.txt"),
+            )
+            .expect(
+                "env LC_ALL=C flatpak uninstall --system --unused -y",
+                0,
+                include_str!("../../tests/fixtures/flatpak/uninstall-unused.txt"),
+            )
+            .expect(
+                "env LC_ALL=C flatpak uninstall --user --unused -y",
+                0,
+                "Nothing unused to uninstall\n",
+            );
+        let config = crate::config::FlatpakConfig {
+            cleanup: true,
+            ..Default::default()
+        };
+        let backend = FlatpakBackend::with_installations_dir(config, NO_INSTALLATIONS_DIR.into());
+
+        let (reports, _) = run_with(executor, vec![Box::new(backend)]).await;
+
+        assert!(matches!(
+            reports[0].outcome,
+            Ok(BackendOutcome::Updated { .. })
+        ));
+        assert_eq!(reports[0].notes.len(), 3);
+        assert!(
+            reports[0].notes[0].starts_with(
+                "org.gnome.Platform//45 is end-of-life, still used by org.gnome.Boxes"
+            )
+        );
+        assert_eq!(
+            reports[0].notes[2],
+            "Removed 2 unused refs from the system installation: \
+             org.freedesktop.Platform//22.08, org.gnome.Platform//44"
+        );
+    }
+
+    #[tokio::test]
+    async fn dnf5_cleanup_reports_reclaimed_space_and_extra_kernels() {
+        let executor = ScriptedExecutor::new(&["dnf5", "rpm", "uname"])
+            .expect("sudo env LC_ALL=C dnf5 --refresh check-upgrade", 0, "")
+            .expect(
+                "sudo env LC_ALL=C dnf5 autoremove --assumeno",
+                1,
+                include_str!("../../tests/fixtures/dnf5/autoremove-assumeno.txt"),
+            )
+            .expect("sudo dnf5 autoremove -y", 0, "Complete!\n")
+            .expect(
+                "sudo env LC_ALL=C dnf5 clean packages",
+                0,
+                include_str!("../../tests/fixtures/dnf5/clean-packages.txt"),
+            )
+            .expect(
+                "rpm -q --queryformat",
+                0,
+                include_str!("../../tests/fixtures/dnf5/rpm-kernels.txt"),
+            )
+            .expect("uname -r", 0, "6.12.6-200.fc41.x86_64\n");
+        let config = crate::config::Dnf5Config {
+            cleanup: true,
+            autoremove: Autoremove::Always,
+            ..Default::default()
+        };
+
+        let (reports, _) =
+            run_with(executor, vec![Box::new(Dnf5Backend::new(false, config))]).await;
+
+        assert!(matches!(reports[0].outcome, Ok(BackendOutcome::UpToDate)));
+        assert_eq!(
+            reports[0].notes,
+            [
+                "Reclaimed 697.0 MiB (unused dependencies 185.0 MiB, package cache 512.0 MiB)",
+                "2 kernels are installed beyond the keep count of 3: \
+                 6.11.9-300.fc41.x86_64, 6.11.10-300.fc41.x86_64"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_dnf5_check_is_not_up_to_date() {
+        let executor = ScriptedExecutor::new(&["dnf5"]).expect(
+            "sudo env LC_ALL=C dnf5 --refresh check-upgrade",
+            1,
+            "Curl error (6): Couldn't resolve host name\n",
+        );
+        let config = crate::config::Dnf5Config {
+            cleanup: true,
+            ..Default::default()
+        };
+
+        let (reports, executor) =
+            run_with(executor, vec![Box::new(Dnf5Backend::new(false, config))]).await;
+
+        assert_eq!(
+            reports[0].outcome.as_ref().unwrap_err().to_string(),
+            "DNF5 update check failed"
+        );
+        // Nothing is cleaned up after a check that did not succeed
+        assert_eq!(executor.calls().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unattended_cleanup_leaves_unused_dependencies() {
+        let executor = ScriptedExecutor::new(&["dnf5"])
+            .expect("sudo env LC_ALL=C dnf5 --refresh check-upgrade", 0, "")
+            .expect(
+                "sudo env LC_ALL=C dnf5 autoremove --assumeno",
+                1,
+                include_str!("../../tests/fixtures/dnf5/autoremove-assumeno.txt"),
+            )
+            .expect(
+                "sudo env LC_ALL=C dnf5 clean packages",
+                0,
+                include_str!("../../tests/fixtures/dnf5/clean-packages.txt"),
+            );
+        let config = crate::config::Dnf5Config {
+            cleanup: true,
+            ..Default::default()
+        };
+
+        let (reports, executor) =
+            run_with(executor, vec![Box::new(Dnf5Backend::new(false, config))]).await;
+
+        assert_eq!(
+            reports[0].notes,
+            [
+                "4 unused dependencies were left installed",
+                "Reclaimed 512.0 MiB (unused dependencies 0 B, package cache 512.0 MiB)"
+            ]
+        );
+        assert!(
+            !executor
+                .calls()
+                .iter()
+                .any(|call| call.starts_with("sudo dnf5 autoremove -y"))
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_upgrade_skips_restart_check() {
+        let executor = ScriptedExecutor::new(&["flatpak", "dnf5", "rpm"])
+            .expect(
+                "env LC_ALL=C flatpak remote-ls --system --updates",
+                0,
+                "org.mozilla.firefox\tstable\tflathub\t< 92.4 MB\n",
+            )
+            .expect("flatpak list --system", 0, FLATPAK_LIST)
+            .expect("env LC_ALL=C flatpak remote-ls --user --updates", 0, "")
+            .expect("flatpak list --system", 0, FLATPAK_LIST)
+            .expect("env LC_ALL=C flatpak update --system -y", 0, FLATPAK_UPDATE);
+        let executor = dnf5_updates_available(executor)
+            .expect("dnf5 history list", 0, HISTORY_BEFORE)
+            .expect("sudo dnf5 upgrade -y", 1, "Error: Transaction failed\n");
+
+        let (reports, executor) = run(executor).await;
+
+        assert!(matches!(
+            reports[0].outcome,
+            Ok(BackendOutcome::Updated { .. })
+        ));
+        assert_eq!(
+            reports[1].outcome.as_ref().unwrap_err().to_string(),
+            "DNF5 update failed"
+        );
+        // Updated refs can return to the commits they had; the installed locale cannot
+        let references: Vec<_> = reports[0]
+            .undo
+            .iter()
+            .map(|step| match step {
+                UndoStep::FlatpakCommit { reference, .. } => reference.as_str(),
+                UndoStep::Dnf5Transaction { .. } => "dnf5",
+            })
+            .collect();
+        assert_eq!(
+            references,
+            [
+                "org.freedesktop.Platform.GL.default//24.08",
+                "org.mozilla.firefox//stable"
+            ]
+        );
+        assert!(reports[1].undo.is_empty());
+        assert!(
+            !executor
+                .calls()
+                .iter()
+                .any(|call| call.contains("needs-restarting"))
+        );
+    }
+
+    #[test]
+    fn reboot_requirement_keeps_most_severe() {
+        let services = RebootRequirement::Services(vec!["sshd.service".to_string()]);
+
+        assert_eq!(
+            RebootRequirement::None.merge(services.clone()),
+            services.clone()
+        );
+        assert_eq!(
+            services.clone().merge(RebootRequirement::Reboot),
+            RebootRequirement::Reboot
+        );
+        assert_eq!(
+            services.merge(RebootRequirement::Services(vec![
+                "sshd.service".to_string(),
+                "dbus-broker.service".to_string(),
+            ])),
+            RebootRequirement::Services(vec![
+                "sshd.service".to_string(),
+                "dbus-broker.service".to_string(),
+            ])
+        );
+    }
+}
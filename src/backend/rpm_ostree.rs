@@ -0,0 +1,260 @@
+use super::{
+    CheckStatus, PendingUpdate, RebootRequirement, TransactionSummary, UpdateBackend, show_updates,
+};
+use crate::runner::CommandRunner;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::process::ExitStatus;
+
+/// Exit status of `rpm-ostree upgrade --check` when no update is available
+const EXIT_NO_UPDATE: i32 = 77;
+
+/// Updates the base image of Atomic desktops such as Silverblue and Kinoite
+///
+/// Upgrades are staged as a new deployment that becomes active on the next boot.
+#[derive(Debug, Default)]
+pub(crate) struct RpmOstreeBackend {
+    // Package counts of the available update, found by the last check
+    transaction: TransactionSummary,
+}
+
+impl RpmOstreeBackend {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Runs rpm-ostree in the C locale, so the messages it prints parse the same for every user
+async fn run_rpm_ostree(runner: &mut CommandRunner, args: &[&str]) -> Result<(ExitStatus, String)> {
+    let mut env_args = vec!["LC_ALL=C", "rpm-ostree"];
+    env_args.extend_from_slice(args);
+    let (status, output) = runner.execute_command("env", &env_args, false).await?;
+    Ok((status, output.to_string()))
+}
+
+/// Reads the value of a `Key: value` line from rpm-ostree's status-like output
+fn field<'a>(output: &'a str, key: &str) -> Option<&'a str> {
+    output.lines().find_map(|line| {
+        let (name, value) = line.trim().split_once(':')?;
+        (name == key).then(|| value.trim())
+    })
+}
+
+/// Strips the timestamp from a `41.20250115.0 (2025-01-15T00:43:48Z)` version field
+fn version(value: &str) -> Option<String> {
+    value.split_whitespace().next().map(str::to_string)
+}
+
+/// Parses `rpm-ostree upgrade --check` output into the new version and package counts
+fn parse_upgrade_check(output: &str) -> (Option<String>, TransactionSummary) {
+    let mut summary = TransactionSummary::default();
+
+    // Diff: 41 upgraded, 1 downgraded, 2 removed, 3 added
+    for change in field(output, "Diff").unwrap_or_default().split(',') {
+        let mut words = change.split_whitespace();
+        let (Some(count), Some(kind)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let count = count.parse().unwrap_or(0);
+        match kind {
+            "upgraded" => summary.upgrade = count,
+            "downgraded" => summary.downgrade = count,
+            "removed" => summary.remove = count,
+            "added" => summary.install = count,
+            _ => {}
+        }
+    }
+
+    (field(output, "Version").and_then(version), summary)
+}
+
+/// Parses `rpm-ostree status --booted` output into the booted refspec and version
+fn parse_booted_status(output: &str) -> (Option<String>, Option<String>) {
+    let refspec = output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix('●'))
+        .map(|refspec| refspec.trim().to_string());
+
+    (refspec, field(output, "Version").and_then(version))
+}
+
+/// Parses the upgraded and downgraded packages listed by `rpm-ostree db diff`
+fn parse_db_diff(output: &str) -> Vec<PendingUpdate> {
+    let mut updates = Vec::new();
+    let mut in_changes = false;
+
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            // Added and removed packages are listed as bare NEVRAs
+            in_changes = matches!(line.trim(), "Upgraded:" | "Downgraded:");
+            continue;
+        }
+        if !in_changes {
+            continue;
+        }
+
+        // firefox 133.0.3-1.fc41 -> 134.0.1-1.fc41
+        let mut columns = line.split_whitespace();
+        if let (Some(name), Some(from), Some("->"), Some(to), None) = (
+            columns.next(),
+            columns.next(),
+            columns.next(),
+            columns.next(),
+            columns.next(),
+        ) {
+            updates.push(PendingUpdate {
+                name: name.to_string(),
+                current_version: Some(from.to_string()),
+                new_version: Some(to.to_string()),
+                origin: None,
+            });
+        }
+    }
+
+    updates
+}
+
+#[async_trait]
+impl UpdateBackend for RpmOstreeBackend {
+    fn name(&self) -> &'static str {
+        "rpm-ostree"
+    }
+
+    fn required(&self) -> bool {
+        true
+    }
+
+    async fn detect(&mut self, runner: &mut CommandRunner) -> bool {
+        runner.is_available("rpm-ostree").await
+    }
+
+    async fn check(&mut self, runner: &mut CommandRunner) -> Result<CheckStatus> {
+        runner
+            .reporter()
+            .progress("Checking for rpm-ostree updates...");
+
+        let (status, output) = run_rpm_ostree(runner, &["upgrade", "--check"]).await?;
+
+        match status.code() {
+            Some(EXIT_NO_UPDATE) => {
+                self.transaction = TransactionSummary::default();
+                runner
+                    .reporter()
+                    .progress("No rpm-ostree updates available.");
+                return Ok(CheckStatus::UpToDate);
+            }
+            Some(0) => {}
+            _ => return Err(anyhow::anyhow!("rpm-ostree update check failed")),
+        }
+
+        let (new_version, transaction) = parse_upgrade_check(&output);
+        self.transaction = transaction;
+
+        // The booted deployment tells which image and version are being replaced
+        let (refspec, current_version) = match runner
+            .capture_command("rpm-ostree", &["status", "--booted"])
+            .await
+        {
+            Some(output) => parse_booted_status(&String::from_utf8_lossy(&output.stdout)),
+            None => (None, None),
+        };
+
+        runner
+            .reporter()
+            .progress("rpm-ostree updates are available.");
+        Ok(CheckStatus::Available(vec![PendingUpdate {
+            name: refspec.unwrap_or_else(|| "deployment".to_string()),
+            current_version,
+            new_version,
+            origin: None,
+        }]))
+    }
+
+    async fn preview(&mut self, runner: &mut CommandRunner) -> Result<TransactionSummary> {
+        // The check already reported the package counts of the new deployment
+        runner
+            .reporter()
+            .progress("rpm-ostree dry run: no deployment will be staged.");
+        Ok(self.transaction.clone())
+    }
+
+    async fn apply(&mut self, runner: &mut CommandRunner) -> Result<bool> {
+        runner
+            .reporter()
+            .progress("Staging rpm-ostree deployment...");
+
+        let (status, output) = run_rpm_ostree(runner, &["upgrade"]).await?;
+
+        if !status.success() {
+            return Err(anyhow::anyhow!("rpm-ostree upgrade failed"));
+        }
+
+        Ok(!output.contains("No upgrade available"))
+    }
+
+    async fn post_apply(&mut self, runner: &mut CommandRunner) -> Result<RebootRequirement> {
+        // Compare the booted deployment with the one that was just staged
+        if let Some(output) = runner.capture_command("rpm-ostree", &["db", "diff"]).await {
+            let changes = parse_db_diff(&String::from_utf8_lossy(&output.stdout));
+            if !changes.is_empty() {
+                show_updates(
+                    runner.reporter(),
+                    &format!("Staged deployment changes ({}):", changes.len()),
+                    &changes,
+                );
+            }
+        }
+
+        runner
+            .reporter()
+            .warn("New deployment staged. Reboot to start using it.");
+        Ok(RebootRequirement::Reboot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPGRADE_CHECK: &str = include_str!("../../tests/fixtures/rpm-ostree/upgrade-check.txt");
+    const STATUS_BOOTED: &str = include_str!("../../tests/fixtures/rpm-ostree/status-booted.txt");
+    const DB_DIFF: &str = include_str!("../../tests/fixtures/rpm-ostree/db-diff.txt");
+
+    #[test]
+    fn parses_available_update() {
+        let (version, summary) = parse_upgrade_check(UPGRADE_CHECK);
+
+        assert_eq!(version.as_deref(), Some("41.20250115.0"));
+        assert_eq!(summary.upgrade, 41);
+        assert_eq!(summary.downgrade, 1);
+        assert_eq!(summary.remove, 2);
+        assert_eq!(summary.install, 3);
+    }
+
+    #[test]
+    fn parses_booted_deployment() {
+        let (refspec, version) = parse_booted_status(STATUS_BOOTED);
+
+        assert_eq!(
+            refspec.as_deref(),
+            Some("fedora:fedora/41/x86_64/silverblue")
+        );
+        assert_eq!(version.as_deref(), Some("41.20250110.0"));
+    }
+
+    #[test]
+    fn parses_staged_package_changes() {
+        let changes = parse_db_diff(DB_DIFF);
+        let names: Vec<_> = changes.iter().map(|c| c.name.as_str()).collect();
+
+        assert_eq!(
+            names,
+            ["firefox", "kernel", "kernel-core", "mesa-dri-drivers"]
+        );
+        assert_eq!(
+            changes[0].current_version.as_deref(),
+            Some("133.0.3-1.fc41")
+        );
+        assert_eq!(changes[0].new_version.as_deref(), Some("134.0.1-1.fc41"));
+    }
+}
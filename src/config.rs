@@ -0,0 +1,464 @@
+//! Layered TOML configuration: system file, user file, then CLI overrides
+
+use crate::backend::{AdvisorySeverity, Autoremove, UpdateMode};
+use crate::output::OutputFormat;
+use crate::preflight::PreflightAction;
+use crate::snapshot::SnapshotMethod;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// System-wide configuration, typically shipped by an administrator
+const SYSTEM_CONFIG_PATH: &str = "/etc/fedora-updater/config.toml";
+
+/// Update sources that can be enabled in the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BackendKind {
+    Flatpak,
+    Dnf5,
+    /// Image-based updates on Atomic desktops, used instead of DNF5 when ostree-booted
+    #[serde(rename = "rpm-ostree")]
+    RpmOstree,
+    /// Device firmware through fwupd; never enabled by default
+    Fwupd,
+    /// Packages inside toolbox and distrobox containers; never enabled by default
+    Containers,
+}
+
+/// Fully resolved configuration used by a run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Config {
+    /// Backends to run, in order
+    pub(crate) backends: Vec<BackendKind>,
+    pub(crate) output: OutputConfig,
+    pub(crate) dnf5: Dnf5Config,
+    pub(crate) flatpak: FlatpakConfig,
+    pub(crate) fwupd: FwupdConfig,
+    pub(crate) preflight: PreflightConfig,
+    pub(crate) snapshot: SnapshotConfig,
+}
+
+/// Output preferences
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OutputConfig {
+    pub(crate) format: OutputFormat,
+    /// Whether text output is color-coded
+    pub(crate) color: bool,
+}
+
+/// Kernels kept by the DNF5 cleanup, matching DNF's default `installonly_limit`
+const DEFAULT_KEEP_KERNELS: usize = 3;
+
+/// DNF5 backend settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Dnf5Config {
+    /// Update mode used when not running interactively
+    pub(crate) mode: UpdateMode,
+    /// Extra arguments appended to `dnf5 upgrade`
+    pub(crate) extra_args: Vec<String>,
+    /// Package name globs excluded from checks and upgrades
+    pub(crate) exclude: Vec<String>,
+    /// Remove unused dependencies and cached packages after updating
+    pub(crate) cleanup: bool,
+    /// Whether the cleanup removes unused dependencies
+    pub(crate) autoremove: Autoremove,
+    /// Installed kernels kept by the cleanup; older ones are offered for removal
+    pub(crate) keep_kernels: usize,
+    /// Only apply updates that fix security advisories
+    pub(crate) security: bool,
+    /// Only apply updates from advisories of this severity or higher
+    pub(crate) advisory_severity: Option<AdvisorySeverity>,
+}
+
+impl Default for Dnf5Config {
+    fn default() -> Self {
+        Self {
+            mode: UpdateMode::default(),
+            extra_args: Vec::new(),
+            exclude: Vec::new(),
+            cleanup: false,
+            autoremove: Autoremove::default(),
+            keep_kernels: DEFAULT_KEEP_KERNELS,
+            security: false,
+            advisory_severity: None,
+        }
+    }
+}
+
+/// Flatpak backend settings
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct FlatpakConfig {
+    /// Extra arguments appended to `flatpak update`
+    pub(crate) extra_args: Vec<String>,
+    /// Uninstall unused runtimes and extensions after updating
+    pub(crate) cleanup: bool,
+}
+
+/// Firmware backend settings
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct FwupdConfig {
+    /// Apply firmware updates without asking; otherwise they are only applied
+    /// after confirming in interactive runs
+    pub(crate) auto_apply: bool,
+}
+
+/// Battery charge below which updates wait for AC power
+const DEFAULT_MIN_BATTERY: u8 = 30;
+
+/// Conditions checked before an update run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PreflightConfig {
+    /// Lowest battery charge in percent at which updates run without AC power
+    pub(crate) min_battery: u8,
+    /// Download updates over metered connections
+    pub(crate) allow_metered: bool,
+    /// What to do when a condition is not met
+    pub(crate) action: PreflightAction,
+}
+
+impl Default for PreflightConfig {
+    fn default() -> Self {
+        Self {
+            min_battery: DEFAULT_MIN_BATTERY,
+            allow_metered: false,
+            action: PreflightAction::default(),
+        }
+    }
+}
+
+/// Snapper configuration used for the root filesystem
+const DEFAULT_SNAPPER_CONFIG: &str = "root";
+
+/// Where btrfs snapshots are kept when snapper is not used
+const DEFAULT_BTRFS_DIR: &str = "/var/lib/fedora-updater/snapshots";
+
+/// Snapshots of the root filesystem taken around DNF5 transactions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SnapshotConfig {
+    /// Take a snapshot before and after each DNF5 transaction
+    pub(crate) enabled: bool,
+    pub(crate) method: SnapshotMethod,
+    /// Snapper configuration the snapshots are created in
+    pub(crate) snapper_config: String,
+    /// Directory holding read-only btrfs snapshots
+    pub(crate) btrfs_dir: PathBuf,
+    /// Whether a failed snapshot blocks the transaction
+    pub(crate) required: bool,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            method: SnapshotMethod::default(),
+            snapper_config: DEFAULT_SNAPPER_CONFIG.to_string(),
+            btrfs_dir: PathBuf::from(DEFAULT_BTRFS_DIR),
+            required: true,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backends: vec![BackendKind::Flatpak, BackendKind::Dnf5],
+            output: OutputConfig {
+                format: OutputFormat::Text,
+                color: true,
+            },
+            dnf5: Dnf5Config::default(),
+            flatpak: FlatpakConfig::default(),
+            fwupd: FwupdConfig::default(),
+            preflight: PreflightConfig::default(),
+            snapshot: SnapshotConfig::default(),
+        }
+    }
+}
+
+/// One configuration file; unset keys fall through to lower layers
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    backends: Option<Vec<BackendKind>>,
+    output: OutputSection,
+    dnf5: Dnf5Section,
+    flatpak: FlatpakSection,
+    fwupd: FwupdSection,
+    preflight: PreflightSection,
+    snapshot: SnapshotSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OutputSection {
+    format: Option<OutputFormat>,
+    color: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Dnf5Section {
+    mode: Option<UpdateMode>,
+    extra_args: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    cleanup: Option<bool>,
+    autoremove: Option<Autoremove>,
+    keep_kernels: Option<usize>,
+    security: Option<bool>,
+    advisory_severity: Option<AdvisorySeverity>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FlatpakSection {
+    extra_args: Option<Vec<String>>,
+    cleanup: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FwupdSection {
+    auto_apply: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PreflightSection {
+    min_battery: Option<u8>,
+    allow_metered: Option<bool>,
+    action: Option<PreflightAction>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SnapshotSection {
+    enabled: Option<bool>,
+    method: Option<SnapshotMethod>,
+    snapper_config: Option<String>,
+    btrfs_dir: Option<PathBuf>,
+    required: Option<bool>,
+}
+
+impl ConfigFile {
+    /// Rejects values that parse but would be unsafe to act on
+    fn validate(&self) -> Result<()> {
+        if self.dnf5.keep_kernels == Some(0) {
+            return Err(anyhow::anyhow!(
+                "keep_kernels in the [dnf5] section must be at least 1"
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Applies the keys set in a configuration file on top of this one
+    ///
+    /// Lists replace the lower layer's value instead of extending it.
+    fn apply(&mut self, file: ConfigFile) {
+        if let Some(backends) = file.backends {
+            self.backends = backends;
+        }
+        if let Some(format) = file.output.format {
+            self.output.format = format;
+        }
+        if let Some(color) = file.output.color {
+            self.output.color = color;
+        }
+        if let Some(mode) = file.dnf5.mode {
+            self.dnf5.mode = mode;
+        }
+        if let Some(extra_args) = file.dnf5.extra_args {
+            self.dnf5.extra_args = extra_args;
+        }
+        if let Some(exclude) = file.dnf5.exclude {
+            self.dnf5.exclude = exclude;
+        }
+        if let Some(cleanup) = file.dnf5.cleanup {
+            self.dnf5.cleanup = cleanup;
+        }
+        if let Some(autoremove) = file.dnf5.autoremove {
+            self.dnf5.autoremove = autoremove;
+        }
+        if let Some(keep_kernels) = file.dnf5.keep_kernels {
+            self.dnf5.keep_kernels = keep_kernels;
+        }
+        if let Some(security) = file.dnf5.security {
+            self.dnf5.security = security;
+        }
+        if let Some(severity) = file.dnf5.advisory_severity {
+            self.dnf5.advisory_severity = Some(severity);
+        }
+        if let Some(extra_args) = file.flatpak.extra_args {
+            self.flatpak.extra_args = extra_args;
+        }
+        if let Some(cleanup) = file.flatpak.cleanup {
+            self.flatpak.cleanup = cleanup;
+        }
+        if let Some(auto_apply) = file.fwupd.auto_apply {
+            self.fwupd.auto_apply = auto_apply;
+        }
+        if let Some(min_battery) = file.preflight.min_battery {
+            self.preflight.min_battery = min_battery;
+        }
+        if let Some(allow_metered) = file.preflight.allow_metered {
+            self.preflight.allow_metered = allow_metered;
+        }
+        if let Some(action) = file.preflight.action {
+            self.preflight.action = action;
+        }
+        if let Some(enabled) = file.snapshot.enabled {
+            self.snapshot.enabled = enabled;
+        }
+        if let Some(method) = file.snapshot.method {
+            self.snapshot.method = method;
+        }
+        if let Some(snapper_config) = file.snapshot.snapper_config {
+            self.snapshot.snapper_config = snapper_config;
+        }
+        if let Some(btrfs_dir) = file.snapshot.btrfs_dir {
+            self.snapshot.btrfs_dir = btrfs_dir;
+        }
+        if let Some(required) = file.snapshot.required {
+            self.snapshot.required = required;
+        }
+    }
+
+    /// Loads the system configuration and then the user configuration
+    ///
+    /// `user_path` replaces the default per-user file when given, and must exist.
+    pub(crate) fn load(user_path: Option<&Path>) -> Result<Self> {
+        let mut config = Self::default();
+
+        if let Some(file) = read_file(Path::new(SYSTEM_CONFIG_PATH), false)? {
+            config.apply(file);
+        }
+
+        let user_file = match user_path {
+            Some(path) => read_file(path, true)?,
+            None => match user_config_path() {
+                Some(path) => read_file(&path, false)?,
+                None => None,
+            },
+        };
+        if let Some(file) = user_file {
+            config.apply(file);
+        }
+
+        Ok(config)
+    }
+}
+
+/// Location of the per-user configuration file, honoring `XDG_CONFIG_HOME`
+fn user_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("fedora-updater").join("config.toml"))
+}
+
+/// Reads and parses a configuration file, returning None if it is absent
+fn read_file(path: &Path, required: bool) -> Result<Option<ConfigFile>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+
+    let file: ConfigFile = toml::from_str(&text)
+        .with_context(|| format!("Invalid configuration in {}", path.display()))?;
+    file.validate()
+        .with_context(|| format!("Invalid configuration in {}", path.display()))?;
+    Ok(Some(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> ConfigFile {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn empty_file_keeps_defaults() {
+        let mut config = Config::default();
+        config.apply(parse(""));
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn user_file_overrides_system_file() {
+        let mut config = Config::default();
+        config.apply(parse(
+            r#"
+            backends = ["dnf5"]
+
+            [dnf5]
+            mode = "offline"
+            exclude = ["kernel*"]
+            advisory_severity = "important"
+            "#,
+        ));
+        config.apply(parse(
+            r#"
+            [dnf5]
+            exclude = ["firefox"]
+            keep_kernels = 2
+            autoremove = "always"
+
+            [output]
+            color = false
+
+            [fwupd]
+            auto_apply = true
+
+            [flatpak]
+            cleanup = true
+
+            [preflight]
+            action = "check"
+
+            [snapshot]
+            enabled = true
+            method = "snapper"
+            "#,
+        ));
+
+        assert_eq!(config.backends, [BackendKind::Dnf5]);
+        assert_eq!(config.dnf5.mode, UpdateMode::Offline);
+        assert_eq!(config.dnf5.exclude, ["firefox"]);
+        assert_eq!(config.dnf5.keep_kernels, 2);
+        assert_eq!(config.dnf5.autoremove, Autoremove::Always);
+        assert!(!config.dnf5.cleanup);
+        assert_eq!(
+            config.dnf5.advisory_severity,
+            Some(AdvisorySeverity::Important)
+        );
+        assert!(!config.output.color);
+        assert!(config.fwupd.auto_apply);
+        assert!(config.flatpak.cleanup);
+        assert_eq!(config.preflight.action, PreflightAction::Check);
+        assert_eq!(config.preflight.min_battery, 30);
+        assert!(config.snapshot.enabled && config.snapshot.required);
+        assert_eq!(config.snapshot.method, SnapshotMethod::Snapper);
+        assert_eq!(config.output.format, OutputFormat::Text);
+    }
+
+    #[test]
+    fn rejects_keeping_no_kernels() {
+        assert!(parse("[dnf5]\nkeep_kernels = 0").validate().is_err());
+        assert!(parse("[dnf5]\nkeep_kernels = 1").validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<ConfigFile>("[dnf5]\nexclude_packages = []").is_err());
+        assert!(toml::from_str::<ConfigFile>("backends = [\"snap\"]").is_err());
+    }
+}
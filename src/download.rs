@@ -0,0 +1,184 @@
+//! Record of the updates fetched by `update --download-only`, applied later by `--apply-downloaded`
+
+use crate::backend::{BackendOutcome, BackendReport, PendingUpdate};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// File inside the state directory that lists the downloaded updates
+const MANIFEST_FILE: &str = "downloaded.json";
+
+/// Updates one backend downloaded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DownloadedUpdates {
+    pub(crate) backend: String,
+    pub(crate) packages: Vec<PendingUpdate>,
+}
+
+/// Updates waiting in the package caches to be applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DownloadManifest {
+    pub(crate) downloaded_at: DateTime<Local>,
+    pub(crate) backends: Vec<DownloadedUpdates>,
+}
+
+impl DownloadManifest {
+    /// Collects the updates of every backend that downloaded something
+    pub(crate) fn from_reports(downloaded_at: DateTime<Local>, reports: &[BackendReport]) -> Self {
+        let backends = reports
+            .iter()
+            .filter_map(|report| match &report.outcome {
+                Ok(BackendOutcome::Downloaded(packages)) => Some(DownloadedUpdates {
+                    backend: report.name.to_string(),
+                    packages: packages.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            downloaded_at,
+            backends,
+        }
+    }
+
+    /// Updates downloaded for a backend, or None if it had nothing to download
+    pub(crate) fn packages(&self, backend: &str) -> Option<&[PendingUpdate]> {
+        self.backends
+            .iter()
+            .find(|downloaded| downloaded.backend == backend)
+            .map(|downloaded| downloaded.packages.as_slice())
+    }
+
+    /// Keeps only the backends that failed to apply their downloaded updates
+    pub(crate) fn retain_failed(&mut self, reports: &[BackendReport]) {
+        self.backends.retain(|downloaded| {
+            reports
+                .iter()
+                .any(|report| report.name == downloaded.backend && report.outcome.is_err())
+        });
+    }
+}
+
+/// Path of the manifest file
+fn manifest_path() -> Result<PathBuf> {
+    crate::history::state_dir()
+        .map(|dir| dir.join(MANIFEST_FILE))
+        .context("Cannot locate the state directory: neither XDG_STATE_HOME nor HOME is set")
+}
+
+/// Reads the manifest written by the last download-only run
+pub(crate) fn load() -> Result<DownloadManifest> {
+    read_manifest(&manifest_path()?)
+}
+
+/// Stores the manifest, removing it once no downloaded updates are left
+pub(crate) fn save(manifest: &DownloadManifest) -> Result<()> {
+    write_manifest(&manifest_path()?, manifest)
+}
+
+fn read_manifest(path: &Path) -> Result<DownloadManifest> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow::anyhow!(
+                "No downloaded updates are recorded. Run `fedora-updater update --download-only` first"
+            ));
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    serde_json::from_str(&text)
+        .with_context(|| format!("Invalid download manifest in {}", path.display()))
+}
+
+fn write_manifest(path: &Path, manifest: &DownloadManifest) -> Result<()> {
+    if manifest.backends.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let text = serde_json::to_string_pretty(manifest)?;
+    std::fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(name: &str) -> PendingUpdate {
+        PendingUpdate {
+            name: name.to_string(),
+            current_version: None,
+            new_version: Some("2.0-1.fc41".to_string()),
+            origin: Some("updates".to_string()),
+        }
+    }
+
+    fn report(name: &'static str, outcome: Result<BackendOutcome>) -> BackendReport {
+        BackendReport {
+            name,
+            outcome,
+            items: Vec::new(),
+            notes: Vec::new(),
+            snapshots: Vec::new(),
+            undo: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn keeps_updates_until_they_are_applied() {
+        let path = std::env::temp_dir()
+            .join(format!("fedora-updater-download-{}", std::process::id()))
+            .join(MANIFEST_FILE);
+        let downloaded = [
+            report(
+                "Flatpak",
+                Ok(BackendOutcome::Downloaded(vec![update(
+                    "org.mozilla.firefox//stable",
+                )])),
+            ),
+            report(
+                "DNF5",
+                Ok(BackendOutcome::Downloaded(vec![update("bash.x86_64")])),
+            ),
+            report("Containers", Ok(BackendOutcome::Skipped)),
+        ];
+
+        let mut manifest = DownloadManifest::from_reports(Local::now(), &downloaded);
+        write_manifest(&path, &manifest).unwrap();
+        assert_eq!(read_manifest(&path).unwrap(), manifest);
+        assert_eq!(manifest.packages("DNF5").unwrap()[0].name, "bash.x86_64");
+        assert!(manifest.packages("Containers").is_none());
+
+        // Only the backend that failed to apply keeps its downloads
+        let applied = [
+            report(
+                "Flatpak",
+                Err(anyhow::anyhow!("not in the local repository")),
+            ),
+            report("DNF5", Ok(BackendOutcome::UpToDate)),
+        ];
+        manifest.retain_failed(&applied);
+        assert!(manifest.packages("DNF5").is_none());
+        assert!(manifest.packages("Flatpak").is_some());
+
+        manifest.retain_failed(&[]);
+        write_manifest(&path, &manifest).unwrap();
+        assert!(
+            read_manifest(&path)
+                .unwrap_err()
+                .to_string()
+                .starts_with("No downloaded updates are recorded")
+        );
+    }
+}
@@ -0,0 +1,493 @@
+//! Journal of past update runs, stored as JSON lines in the user's state directory
+
+use crate::backend::{
+    BackendOutcome, BackendReport, ItemReport, OutcomeKind, PendingUpdate, RebootRequirement,
+};
+use crate::output::{Event, Reporter};
+use crate::rollback::UndoStep;
+use crate::snapshot::Snapshot;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// File inside the state directory that holds one run per line
+const JOURNAL_FILE: &str = "history.jsonl";
+
+/// A command executed during a run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CommandRecord {
+    pub(crate) program: String,
+    pub(crate) args: Vec<String>,
+    pub(crate) sudo: bool,
+    /// Absent when the command was killed by a signal or failed to spawn
+    pub(crate) exit_code: Option<i32>,
+}
+
+/// What a single backend did during a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BackendRecord {
+    pub(crate) name: String,
+    pub(crate) outcome: OutcomeKind,
+    pub(crate) error: Option<String>,
+    /// Packages, applications or runtimes that were updated, downloaded by a download-only run,
+    /// or left pending without confirmation
+    pub(crate) packages: Vec<PendingUpdate>,
+    /// Results of items updated independently, such as containers
+    #[serde(default)]
+    pub(crate) items: Vec<ItemReport>,
+    /// Notes shown with the backend's result, such as end-of-life runtimes
+    #[serde(default)]
+    pub(crate) notes: Vec<String>,
+    /// Snapshots taken before and after the backend's transaction
+    #[serde(default)]
+    pub(crate) snapshots: Vec<Snapshot>,
+    /// How to revert what the backend changed, used by `rollback last`
+    #[serde(default)]
+    pub(crate) undo: Vec<UndoStep>,
+}
+
+/// Overall result of a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RunStatus {
+    /// Every backend succeeded
+    Success,
+    /// Some backends failed while others succeeded
+    Partial,
+    /// No backend succeeded
+    Failed,
+    /// Power or network conditions kept the run from applying updates
+    Deferred,
+    /// Power or network conditions limited the run to checking for updates
+    Checked,
+}
+
+impl RunStatus {
+    /// Short machine-readable name of the status
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Partial => "partial",
+            Self::Failed => "failed",
+            Self::Deferred => "deferred",
+            Self::Checked => "checked",
+        }
+    }
+
+    /// The status colored the same way the final summary prints it
+    fn colored(self) -> ColoredString {
+        match self {
+            Self::Success => self.label().green(),
+            Self::Partial | Self::Deferred | Self::Checked => self.label().yellow(),
+            Self::Failed => self.label().red(),
+        }
+    }
+}
+
+/// A complete update run as stored in the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RunRecord {
+    pub(crate) id: u64,
+    pub(crate) started_at: DateTime<Local>,
+    pub(crate) finished_at: DateTime<Local>,
+    pub(crate) dry_run: bool,
+    pub(crate) status: RunStatus,
+    pub(crate) backends: Vec<BackendRecord>,
+    pub(crate) commands: Vec<CommandRecord>,
+    /// `none`, `services` or `reboot`
+    pub(crate) reboot: String,
+    pub(crate) restart_services: Vec<String>,
+    /// Why the run was deferred or only checked for updates
+    #[serde(default)]
+    pub(crate) preflight: Option<String>,
+    /// ID of the run whose updates this run rolled back
+    #[serde(default)]
+    pub(crate) rolled_back: Option<u64>,
+}
+
+impl RunRecord {
+    /// Builds the record of a finished run from the driver's reports
+    pub(crate) fn new(
+        started_at: DateTime<Local>,
+        dry_run: bool,
+        status: RunStatus,
+        reports: &[BackendReport],
+        commands: Vec<CommandRecord>,
+        reboot: &RebootRequirement,
+    ) -> Self {
+        let backends = reports
+            .iter()
+            .map(|report| BackendRecord {
+                name: report.name.to_string(),
+                outcome: OutcomeKind::of(&report.outcome),
+                error: report.outcome.as_ref().err().map(|e| format!("{:#}", e)),
+                packages: match &report.outcome {
+                    Ok(BackendOutcome::Updated { packages, .. })
+                    | Ok(BackendOutcome::Downloaded(packages))
+                    | Ok(BackendOutcome::Pending(packages)) => packages.clone(),
+                    _ => Vec::new(),
+                },
+                items: report.items.clone(),
+                notes: report.notes.clone(),
+                snapshots: report.snapshots.clone(),
+                undo: report.undo.clone(),
+            })
+            .collect();
+
+        Self {
+            // Assigned when the record is appended to the journal
+            id: 0,
+            started_at,
+            finished_at: Local::now(),
+            dry_run,
+            status,
+            backends,
+            commands,
+            reboot: reboot.label().to_string(),
+            restart_services: match reboot {
+                RebootRequirement::Services(services) => services.clone(),
+                _ => Vec::new(),
+            },
+            preflight: None,
+            rolled_back: None,
+        }
+    }
+
+    /// Names of the backends that applied updates
+    fn updated_backends(&self) -> Vec<&str> {
+        self.backends
+            .iter()
+            .filter(|backend| backend.outcome == OutcomeKind::Updated)
+            .map(|backend| backend.name.as_str())
+            .collect()
+    }
+}
+
+/// Directory holding the journal and other state, honoring `XDG_STATE_HOME`
+pub(crate) fn state_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(base.join("fedora-updater"))
+}
+
+/// Path of the journal file
+fn journal_path() -> Result<PathBuf> {
+    state_dir()
+        .map(|dir| dir.join(JOURNAL_FILE))
+        .context("Cannot locate the state directory: neither XDG_STATE_HOME nor HOME is set")
+}
+
+/// Reads every run from the journal, oldest first
+///
+/// Lines that cannot be parsed are skipped so one bad entry does not hide the rest.
+pub(crate) fn load() -> Result<Vec<RunRecord>> {
+    read_journal(&journal_path()?)
+}
+
+/// Appends a run to the journal, assigning it the next ID
+pub(crate) fn append(record: RunRecord) -> Result<u64> {
+    append_to_journal(&journal_path()?, record)
+}
+
+fn read_journal(path: &Path) -> Result<Vec<RunRecord>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+fn append_to_journal(path: &Path, mut record: RunRecord) -> Result<u64> {
+    record.id = read_journal(path)?
+        .iter()
+        .map(|run| run.id)
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let line = serde_json::to_string(&record)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}", line).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(record.id)
+}
+
+/// Lists the most recent runs, newest first
+pub(crate) fn list(reporter: &Reporter, limit: usize) -> Result<()> {
+    let runs = load()?;
+    let recent = runs.iter().rev().take(limit);
+
+    if !reporter.is_text() {
+        for run in recent {
+            reporter.emit(Event::HistoryEntry { run: run.clone() });
+        }
+        return Ok(());
+    }
+
+    if runs.is_empty() {
+        println!("No update runs recorded yet.");
+        return Ok(());
+    }
+
+    println!(
+        "{}",
+        format!(
+            "{:>4}  {:<19}  {:>8}  {:<8}  {:<7}  Updated",
+            "ID", "Started", "Duration", "Status", "Reboot"
+        )
+        .blue()
+        .bold()
+    );
+    for run in recent {
+        let duration = (run.finished_at - run.started_at).num_seconds();
+        let updated = run.updated_backends();
+        println!(
+            "{:>4}  {:<19}  {:>7}s  {:<8}  {:<7}  {}{}{}",
+            run.id,
+            run.started_at.format("%Y-%m-%d %H:%M:%S"),
+            duration,
+            run.status.colored(),
+            run.reboot,
+            if updated.is_empty() {
+                "-".to_string()
+            } else {
+                updated.join(", ")
+            },
+            if run.dry_run { " (dry run)" } else { "" },
+            run.rolled_back
+                .map(|id| format!(" (rollback of run {})", id))
+                .unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+/// Shows everything recorded about one run
+pub(crate) fn show(reporter: &Reporter, id: u64) -> Result<()> {
+    let run = load()?
+        .into_iter()
+        .find(|run| run.id == id)
+        .with_context(|| format!("No update run with ID {}", id))?;
+
+    if !reporter.is_text() {
+        reporter.emit(Event::HistoryEntry { run });
+        return Ok(());
+    }
+
+    println!("{}", format!("Update run {}", run.id).blue().bold());
+    println!(
+        "Started:  {}",
+        run.started_at.format("%Y-%m-%d %H:%M:%S %Z")
+    );
+    println!(
+        "Finished: {}",
+        run.finished_at.format("%Y-%m-%d %H:%M:%S %Z")
+    );
+    println!(
+        "Status:   {}{}",
+        run.status.colored(),
+        if run.dry_run { " (dry run)" } else { "" }
+    );
+    if run.restart_services.is_empty() {
+        println!("Reboot:   {}", run.reboot);
+    } else {
+        println!(
+            "Reboot:   {} ({})",
+            run.reboot,
+            run.restart_services.join(", ")
+        );
+    }
+    if let Some(reason) = &run.preflight {
+        println!("Reason:   {}", reason.yellow());
+    }
+    if let Some(id) = run.rolled_back {
+        println!("Rollback: of update run {}", id);
+    }
+
+    for backend in &run.backends {
+        println!(
+            "\n{} {}",
+            format!("{}:", backend.name).bold(),
+            backend.outcome.label()
+        );
+        if let Some(error) = &backend.error {
+            println!("  {} {}", "error:".red(), error);
+        }
+        for item in &backend.items {
+            match (&item.error, &item.detail) {
+                (Some(error), _) => println!("  {}: {} {}", item.name, "failed:".red(), error),
+                (None, Some(detail)) => {
+                    println!("  {}: {} ({})", item.name, item.outcome.label(), detail)
+                }
+                (None, None) => println!("  {}: {}", item.name, item.outcome.label()),
+            }
+        }
+        for note in &backend.notes {
+            println!("  {}", note.yellow());
+        }
+        for package in &backend.packages {
+            println!(
+                "  {} {} -> {}",
+                package.name,
+                package.current_version.as_deref().unwrap_or("?"),
+                package.new_version.as_deref().unwrap_or("?")
+            );
+        }
+    }
+
+    if !run.commands.is_empty() {
+        println!("\n{}", "Commands:".bold());
+    }
+    for command in &run.commands {
+        let exit_code = command
+            .exit_code
+            .map_or_else(|| "-".to_string(), |code| code.to_string());
+        println!(
+            "  [{}] {}{} {}",
+            exit_code,
+            if command.sudo { "sudo " } else { "" },
+            command.program,
+            command.args.join(" ")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::BackendReport;
+
+    fn update(name: &str, from: &str, to: &str) -> PendingUpdate {
+        PendingUpdate {
+            name: name.to_string(),
+            current_version: Some(from.to_string()),
+            new_version: Some(to.to_string()),
+            origin: Some("updates".to_string()),
+        }
+    }
+
+    fn record() -> RunRecord {
+        let reports = [
+            BackendReport {
+                name: "Flatpak",
+                outcome: Err(anyhow::anyhow!("remote unreachable")),
+                items: Vec::new(),
+                notes: Vec::new(),
+                snapshots: Vec::new(),
+                undo: Vec::new(),
+            },
+            BackendReport {
+                name: "DNF5",
+                outcome: Ok(BackendOutcome::Updated {
+                    reboot: RebootRequirement::Services(vec!["sshd.service".to_string()]),
+                    packages: vec![update("openssh.x86_64", "9.8p1-1.fc41", "9.9p1-2.fc41")],
+                }),
+                items: Vec::new(),
+                notes: Vec::new(),
+                snapshots: Vec::new(),
+                undo: Vec::new(),
+            },
+        ];
+        let commands = vec![CommandRecord {
+            program: "dnf5".to_string(),
+            args: vec!["upgrade".to_string(), "-y".to_string()],
+            sudo: true,
+            exit_code: Some(0),
+        }];
+
+        RunRecord::new(
+            Local::now(),
+            false,
+            RunStatus::Partial,
+            &reports,
+            commands,
+            &RebootRequirement::Services(vec!["sshd.service".to_string()]),
+        )
+    }
+
+    /// A journal path unique to one test
+    fn journal(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "fedora-updater-history-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join(JOURNAL_FILE)
+    }
+
+    #[test]
+    fn records_outcomes_and_packages() {
+        let run = record();
+
+        assert_eq!(run.backends[0].outcome, OutcomeKind::Failed);
+        assert_eq!(run.backends[0].error.as_deref(), Some("remote unreachable"));
+        assert!(run.backends[0].packages.is_empty());
+        assert_eq!(run.backends[1].outcome, OutcomeKind::Updated);
+        assert_eq!(run.backends[1].packages[0].name, "openssh.x86_64");
+        assert_eq!(run.updated_backends(), ["DNF5"]);
+        assert_eq!(run.reboot, "services");
+        assert_eq!(run.restart_services, ["sshd.service"]);
+    }
+
+    #[test]
+    fn appends_runs_with_increasing_ids() {
+        let path = journal("append");
+
+        assert!(read_journal(&path).unwrap().is_empty());
+        assert_eq!(append_to_journal(&path, record()).unwrap(), 1);
+        assert_eq!(append_to_journal(&path, record()).unwrap(), 2);
+
+        let runs = read_journal(&path).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1].id, 2);
+        assert_eq!(runs[1].commands, record().commands);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn skips_unreadable_lines() {
+        let path = journal("corrupt");
+        append_to_journal(&path, record()).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        writeln!(file, "{{\"id\": truncated").unwrap();
+        append_to_journal(&path, record()).unwrap();
+
+        let ids: Vec<_> = read_journal(&path)
+            .unwrap()
+            .iter()
+            .map(|run| run.id)
+            .collect();
+        assert_eq!(ids, [1, 2]);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
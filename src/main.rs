@@ -1,20 +1,47 @@
 #![forbid(unsafe_code)]
 #![deny(warnings)]
 
+mod backend;
+mod config;
+mod download;
+mod history;
+mod offline;
+mod output;
+mod preflight;
+mod process;
+mod release;
+mod rollback;
+mod runner;
+mod size;
+mod snapshot;
+mod timer;
+
 use anyhow::{Context, Result};
-use clap::Parser;
+use backend::{
+    AdvisorySeverity, BackendOutcome, BackendReport, CheckReport, CheckStatus, RebootRequirement,
+    UpdateMode, UpdateStage,
+};
+use chrono::Local;
+use clap::{Args, Parser, Subcommand};
 use colored::*;
-use std::process::Stdio;
-use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
-use tokio::sync::{Mutex, mpsc};
-
-/// Pre-allocated buffer capacity for command output strings
-const DEFAULT_OUTPUT_CAPACITY: usize = 16384;
-const DEFAULT_CHANNEL_CAPACITY: usize = 200;
-const DEFAULT_LINE_CAPACITY: usize = 256;
-const STRING_POOL_SIZE: usize = 32;
+use config::{BackendKind, Config};
+use history::RunStatus;
+use output::{Event, OutputFormat, Reporter};
+use preflight::PreflightAction;
+use runner::CommandRunner;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+// Clap exits with 2 on usage errors, so these statuses stay clear of it
+
+/// Exit status when updates were applied and a reboot is required
+const EXIT_REBOOT_REQUIRED: u8 = 10;
+/// Exit status when updates were applied and services should be restarted
+const EXIT_SERVICES_RESTART: u8 = 11;
+/// Exit status when power or network conditions kept updates from being applied
+const EXIT_DEFERRED: u8 = 12;
+/// Number of runs `history` lists when no limit is given
+const DEFAULT_HISTORY_LIMIT: usize = 20;
 
 /// Fedora system updater that handles both Flatpak and DNF5 updates
 #[derive(Parser, Debug)]
@@ -23,652 +50,823 @@ const STRING_POOL_SIZE: usize = 32;
     version,
     about = "A command-line utility to update Fedora systems through Flatpak and DNF5",
     after_help = concat!("Repository: https://github.com/khs-kks/fedora-updater\nBuild Date: ", env!("CARGO_BUILD_DATE")),
-    help_template = "{about}\n\nUsage: {name} [OPTIONS]\n\nOptions:\n{options}\n\nAuthor: {author}{after-help}"
+    help_template = "{about}\n\nUsage: {usage}\n\nCommands:\n{subcommands}\n\nOptions:\n{options}\n\nAuthor: {author}{after-help}"
 )]
 struct Cli {
-    /// Enable interactive mode for choosing update type
-    #[arg(short, long)]
-    interactive: bool,
-}
-
-/// Struct to manage command availability caching
-#[derive(Debug)]
-struct CommandCache {
-    // Use a static array of known commands to avoid heap allocations
-    // This acts as a simple string interning mechanism
-    known_commands: [&'static str; 4],
-    // Store availability as a fixed-size array matching known_commands
-    availability: [Option<bool>; 4],
-}
-
-impl CommandCache {
-    fn new() -> Self {
-        Self {
-            known_commands: ["flatpak", "dnf5", "cat", "uname"],
-            availability: [None, None, None, None],
-        }
-    }
-
-    /// Preloads availability of commonly used commands
-    /// Call this at startup to avoid async overhead during actual operations
-    async fn preload_common_commands(&mut self) {
-        // Check commands concurrently with minimum allocations
-        let mut handles = Vec::with_capacity(self.known_commands.len());
-
-        for (idx, &cmd) in self.known_commands.iter().enumerate() {
-            // Spawn a task for each command
-            let handle = tokio::spawn(async move {
-                let available = Command::new("which")
-                    .arg(cmd) // Pass &str directly
-                    .output()
-                    .await
-                    .map(|output| output.status.success())
-                    .unwrap_or(false);
-                (idx, available)
-            });
-
-            handles.push(handle);
-        }
-
-        // Await all tasks and collect results
-        for handle in handles {
-            if let Ok((idx, available)) = handle.await {
-                // Store result in our fixed-size array - no heap allocation
-                self.availability[idx] = Some(available);
-            }
-        }
-    }
-
-    /// Checks if a command is cached as available
-    fn is_cached_available(&self, command: &str) -> Option<bool> {
-        // Check our static array - this is very fast
-        for (idx, &cmd) in self.known_commands.iter().enumerate() {
-            if cmd == command {
-                return self.availability[idx];
-            }
-        }
+    #[command(subcommand)]
+    command: Option<Commands>,
 
-        // Command not in our known list
-        None
-    }
-
-    /// Gets the availability of a command, returning immediately if cached
-    /// This is a convenience method to avoid needing .await when we already know the result
-    /// Returns None if the result isn't cached yet
-    fn get_cached_availability(&self, command: &str) -> Option<bool> {
-        self.is_cached_available(command)
-    }
-
-    /// Checks if a command is available
-    /// Returns immediately with cached result if available
-    async fn is_command_available(&mut self, command: &str) -> bool {
-        // Fast path: return cached result if available
-        if let Some(available) = self.is_cached_available(command) {
-            return available;
-        }
+    /// Output format: colored text, a JSON document, or streamed JSON events
+    #[arg(long, value_enum, global = true)]
+    output: Option<OutputFormat>,
 
-        // Slow path: check command availability and cache the result
-        let available = Command::new("which")
-            .arg(command)
-            .output()
-            .await
-            .map(|output| output.status.success())
-            .unwrap_or(false);
-
-        // Check if this is one of our known commands
-        for (idx, &cmd) in self.known_commands.iter().enumerate() {
-            if cmd == command {
-                self.availability[idx] = Some(available);
-                return available;
-            }
-        }
+    /// Configuration file to use instead of ~/.config/fedora-updater/config.toml
+    #[arg(long, value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
 
-        // Command not in our known list - return false as we don't support it
-        false
-    }
-
-    /// Executes a command if it's available, returns None if command is not available
-    async fn execute_if_available(
-        &mut self,
-        command: &str,
-        args: &[&str],
-    ) -> Option<std::process::Output> {
-        // Fast path: if we already know the command is unavailable, return None immediately
-        if let Some(false) = self.is_cached_available(command) {
-            return None;
-        }
+    /// Backends to run, overriding the configuration (comma-separated)
+    #[arg(long = "backend", value_enum, value_delimiter = ',', global = true)]
+    backends: Vec<BackendKind>,
 
-        // Check if command is available (uses cache if possible)
-        if !self.is_command_available(command).await {
-            return None;
-        }
-
-        // Log the command that's about to be executed
-        let cmd_str = format!("{} {}", command, args.join(" "));
-        println!("{} {}", "Executing command:".cyan().bold(), cmd_str.cyan());
+    /// Options for the default `update` command when no subcommand is given
+    #[command(flatten)]
+    update: UpdateArgs,
+}
 
-        Command::new(command).args(args).output().await.ok()
-    }
+/// Top-level subcommands
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Check for and apply Flatpak and DNF5 updates (default)
+    Update(UpdateArgs),
+    /// Report pending updates without applying anything
+    Check,
+    /// Show system information
+    Info,
+    /// Download the next Fedora release and offer to reboot into its installation
+    UpgradeRelease {
+        /// Release to upgrade to instead of the one after the installed release
+        #[arg(long, value_name = "N")]
+        releasever: Option<u32>,
+        #[command(flatten)]
+        confirm: ConfirmArgs,
+    },
+    /// Show, install or discard the prepared offline update
+    Offline {
+        #[command(subcommand)]
+        command: Option<OfflineCommand>,
+    },
+    /// Generate systemd units that run non-interactive updates on a schedule
+    InstallTimer(TimerArgs),
+    /// Stop the update timer and remove its units
+    UninstallTimer,
+    /// Show the update timer's schedule and last result
+    Timer {
+        #[command(subcommand)]
+        command: Option<TimerCommand>,
+    },
+    /// Undo the last update run, or list and return to the snapshots taken around updates
+    Rollback {
+        #[command(subcommand)]
+        command: Option<RollbackCommand>,
+    },
+    /// List or show past update runs
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+    },
 }
 
-/// Represents a single line of output from a command with a tag to indicate source
-#[derive(Debug, Clone, Copy)]
-enum OutputSource {
-    Stdout,
-    Stderr,
+/// Subcommands of `history`
+#[derive(Subcommand, Debug)]
+enum HistoryCommand {
+    /// List recent update runs, newest first (default)
+    List {
+        /// Maximum number of runs to list
+        #[arg(short = 'n', long, default_value_t = DEFAULT_HISTORY_LIMIT)]
+        limit: usize,
+    },
+    /// Show the backends, packages and commands of one run
+    Show {
+        /// ID of the run, as printed by `history list`
+        id: u64,
+    },
 }
 
-/// A string buffer that can be reused to avoid allocations
-#[derive(Debug)]
-struct StringBuffer {
-    buffer: String,
+/// Subcommands of `rollback`
+#[derive(Subcommand, Debug)]
+enum RollbackCommand {
+    /// List the snapshots taken before and after past update runs (default)
+    Snapshots,
+    /// Return / to the snapshot taken before a run, with snapper or by explaining the btrfs steps
+    Snapshot {
+        /// ID of the run, as printed by `rollback snapshots`
+        run: u64,
+        #[command(flatten)]
+        confirm: ConfirmArgs,
+    },
+    /// Undo the last update run with `dnf5 history undo` and the previous Flatpak commits
+    Last {
+        #[command(flatten)]
+        confirm: ConfirmArgs,
+    },
 }
 
-impl StringBuffer {
-    fn new(capacity: usize) -> Self {
-        Self {
-            buffer: String::with_capacity(capacity),
-        }
-    }
+/// Options of commands that ask before rebooting or rolling back
+#[derive(Args, Debug)]
+struct ConfirmArgs {
+    /// Go ahead without asking for confirmation
+    #[arg(long)]
+    yes: bool,
 
-    fn clear(&mut self) {
-        self.buffer.clear();
-    }
+    /// Never prompt, not even for a sudo password; without --yes nothing is done
+    #[arg(long)]
+    non_interactive: bool,
+}
 
-    fn as_str(&self) -> &str {
-        &self.buffer
-    }
+/// Subcommands of `offline`
+#[derive(Subcommand, Debug)]
+enum OfflineCommand {
+    /// Show whether an offline update is prepared (default)
+    Status,
+    /// Reboot to install the prepared offline update after confirmation
+    Reboot(ConfirmArgs),
+    /// Discard the prepared offline update and its downloaded packages
+    Clean,
 }
 
-/// A pool of string buffers that can be reused
-#[derive(Debug)]
-struct StringBufferPool {
-    buffers: Vec<StringBuffer>,
+/// Subcommands of `timer`
+#[derive(Subcommand, Debug)]
+enum TimerCommand {
+    /// Show whether the timer is enabled, when it runs next and how the last run went (default)
+    Status,
 }
 
-impl StringBufferPool {
-    fn new(size: usize, buffer_capacity: usize) -> Self {
-        let mut buffers = Vec::with_capacity(size);
-        for _ in 0..size {
-            buffers.push(StringBuffer::new(buffer_capacity));
-        }
-        Self { buffers }
-    }
+/// Options for the `install-timer` subcommand
+#[derive(Args, Debug)]
+struct TimerArgs {
+    /// When to run, as a systemd calendar expression such as `daily` or `Sun 03:00`
+    #[arg(long, default_value = "daily", value_name = "CALENDAR")]
+    schedule: String,
+
+    /// Random delay added to each run, as a systemd time span
+    #[arg(long, default_value = "1h", value_name = "SPAN")]
+    randomized_delay: String,
+
+    /// DNF5 update mode for the scheduled runs, overriding the configuration
+    #[arg(long, value_enum)]
+    mode: Option<UpdateMode>,
+
+    /// Only apply DNF5 updates that fix security advisories
+    #[arg(long)]
+    security: bool,
+
+    /// Clean up after each scheduled update
+    #[arg(long)]
+    cleanup: bool,
+
+    /// Run the scheduled updates as this user instead of root, so their Flatpak
+    /// installation, containers and update history are used; sudo must not ask
+    /// them for a password
+    #[arg(long, value_name = "NAME")]
+    user: Option<String>,
+
+    /// Install the units into /etc/systemd/system and start the timer instead of printing them
+    #[arg(long)]
+    install: bool,
+}
 
-    fn get(&mut self) -> StringBuffer {
-        self.buffers
-            .pop()
-            .unwrap_or_else(|| StringBuffer::new(DEFAULT_LINE_CAPACITY))
-    }
+/// Options for the `update` subcommand
+#[derive(Args, Debug, Default)]
+struct UpdateArgs {
+    /// Enable interactive mode for choosing update type
+    #[arg(short, long)]
+    interactive: bool,
 
-    fn return_buffer(&mut self, mut buffer: StringBuffer) {
-        buffer.clear();
-        if self.buffers.len() < STRING_POOL_SIZE {
-            self.buffers.push(buffer);
-        }
-    }
-}
+    /// Never prompt, not even for a sudo password, as in scheduled runs
+    #[arg(long, conflicts_with = "interactive")]
+    non_interactive: bool,
 
-/// Struct to manage output streams and handle line-by-line output
-#[derive(Debug)]
-struct CommandRunner {
-    cmd_cache: CommandCache,
-    // Pre-allocated buffer for command output, reused across commands
-    output_buffer: String,
-}
+    /// Show what would be updated without changing the system
+    #[arg(long)]
+    dry_run: bool,
 
-impl CommandRunner {
-    /// Creates a new CommandRunner with pre-allocated resources
-    fn new() -> Self {
-        Self {
-            cmd_cache: CommandCache::new(),
-            output_buffer: String::with_capacity(DEFAULT_OUTPUT_CAPACITY),
-        }
-    }
+    /// Download Flatpak and DNF5 updates without applying them
+    #[arg(long, conflicts_with_all = ["dry_run", "apply_downloaded"])]
+    download_only: bool,
 
-    /// Preloads common commands into the cache
-    async fn preload_common_commands(&mut self) {
-        self.cmd_cache.preload_common_commands().await;
-    }
+    /// Apply the updates fetched by an earlier --download-only run from the cache only
+    #[arg(long, conflicts_with = "dry_run")]
+    apply_downloaded: bool,
 
-    /// Executes a command and streams its output in real-time
-    async fn execute_command(
-        &mut self,
-        command: &str,
-        args: &[&str],
-        sudo: bool,
-    ) -> Result<(std::process::ExitStatus, &str)> {
-        // Clear the buffer before reusing
-        self.output_buffer.clear();
+    /// Update even on low battery or a metered connection
+    #[arg(long)]
+    ignore_preflight: bool,
 
-        // Log the command execution
-        self.log_command_execution(command, args, sudo);
+    /// Snapshot the root filesystem before and after the DNF5 transaction
+    #[arg(long)]
+    snapshot: bool,
 
-        // Setup command and streams
-        let (mut child, stdout, stderr) = self.setup_command_streams(command, args, sudo).await?;
+    /// Update even when the snapshot before the DNF5 transaction fails
+    #[arg(long)]
+    ignore_snapshot_failure: bool,
 
-        // Create readers for stdout and stderr
-        let stdout_reader = BufReader::new(stdout).lines();
-        let stderr_reader = BufReader::new(stderr).lines();
+    /// DNF5 update mode for non-interactive runs, overriding the configuration
+    #[arg(long, value_enum)]
+    mode: Option<UpdateMode>,
 
-        // Process command output
-        self.process_command_output(stdout_reader, stderr_reader)
-            .await?;
+    /// Only apply DNF5 updates that fix security advisories
+    #[arg(long)]
+    security: bool,
 
-        // Wait for the command to complete
-        let status = child.wait().await?;
+    /// Only apply DNF5 updates from advisories of this severity or higher
+    #[arg(long, value_enum, value_name = "LEVEL")]
+    advisory_severity: Option<AdvisorySeverity>,
 
-        // Return a reference to our buffer to avoid cloning
-        Ok((status, &self.output_buffer))
-    }
+    /// Remove unused Flatpak runtimes, orphaned DNF5 packages and cached
+    /// packages after updating
+    #[arg(long)]
+    cleanup: bool,
+}
 
-    /// Logs the command that's about to be executed
-    fn log_command_execution(&self, command: &str, args: &[&str], sudo: bool) {
-        // Avoid string allocation by building command display directly
-        print!("{} ", "Executing command:".cyan().bold());
-        if sudo {
-            print!("{} ", "sudo".cyan());
-        }
-        print!("{} ", command.cyan());
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    let cli = Cli::parse();
 
-        // Print arguments directly to avoid join allocation
-        for arg in args {
-            print!("{} ", arg.cyan());
-        }
-        println!();
+    let mut config = Config::load(cli.config.as_deref())?;
+    if let Some(format) = cli.output {
+        config.output.format = format;
     }
-
-    /// Sets up the command and its input/output streams
-    async fn setup_command_streams(
-        &self,
-        command: &str,
-        args: &[&str],
-        sudo: bool,
-    ) -> Result<(
-        tokio::process::Child,
-        tokio::process::ChildStdout,
-        tokio::process::ChildStderr,
-    )> {
-        let mut cmd = if sudo {
-            let mut c = Command::new("sudo");
-            c.arg(command);
-            c.args(args);
-            c
-        } else {
-            let mut c = Command::new(command);
-            c.args(args);
-            c
-        };
-
-        // Configure command to pipe stdout and stderr
-        let mut child = cmd
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .with_context(|| format!("Failed to execute {} command", command))?;
-
-        // Get handles to stdout and stderr
-        let stdout = child.stdout.take().context("Failed to capture stdout")?;
-        let stderr = child.stderr.take().context("Failed to capture stderr")?;
-
-        Ok((child, stdout, stderr))
+    if !cli.backends.is_empty() {
+        config.backends = cli.backends.clone();
+    }
+    if !config.output.color {
+        colored::control::set_override(false);
     }
 
-    /// Processes the command output streams
-    async fn process_command_output(
-        &mut self,
-        stdout_reader: tokio::io::Lines<BufReader<tokio::process::ChildStdout>>,
-        stderr_reader: tokio::io::Lines<BufReader<tokio::process::ChildStderr>>,
-    ) -> Result<Arc<Mutex<StringBufferPool>>> {
-        // Create a shared buffer pool for output lines
-        let buffer_pool = Arc::new(Mutex::new(StringBufferPool::new(
-            STRING_POOL_SIZE,
-            DEFAULT_LINE_CAPACITY,
-        )));
-
-        // Create a channel for output handling
-        let (tx, rx) = mpsc::channel(DEFAULT_CHANNEL_CAPACITY);
-        let output_handler_task = tokio::spawn(output_handler(rx, buffer_pool.clone()));
-
-        // Use a channel for accumulating output - now using StringBuffer instead of String
-        let (line_tx, line_rx) = mpsc::channel(DEFAULT_CHANNEL_CAPACITY);
-
-        // Process stdout and stderr
-        let (stdout_task, stderr_task) = self.spawn_output_processing_tasks(
-            stdout_reader,
-            stderr_reader,
-            tx.clone(),
-            line_tx.clone(),
-            buffer_pool.clone(),
-        );
+    let reporter = Reporter::new(config.output.format);
+    let mut cmd_runner = CommandRunner::new(reporter.clone());
 
-        // Close senders to signal completion
-        drop(tx);
-        drop(line_tx);
+    if reporter.is_text() {
+        println!(
+            "{} {}",
+            "Fedora Updater".green().bold(),
+            format!("v{}", env!("CARGO_PKG_VERSION")).yellow()
+        );
+        println!("─────────────────────────────\n");
+    }
 
-        // Wait for output handling to complete
-        let _ = tokio::try_join!(stdout_task, stderr_task)
-            .context("Failed to join stdout/stderr tasks")?;
-        output_handler_task
-            .await
-            .context("Failed to join output handler task")?;
+    let result = match cli.command {
+        // Reading the journal needs neither system information nor any tooling
+        Some(Commands::History { command }) => run_history(&reporter, command),
+        command => {
+            // Preload command availability checks to reduce async overhead later
+            cmd_runner.preload_common_commands().await;
+
+            match cmd_runner.show_system_info().await {
+                Ok(()) => match command {
+                    Some(Commands::Update(args)) => {
+                        run_update(&mut cmd_runner, config, &args).await
+                    }
+                    Some(Commands::Check) => run_check(&mut cmd_runner, &config).await,
+                    Some(Commands::UpgradeRelease {
+                        releasever,
+                        confirm,
+                    }) => run_upgrade_release(&mut cmd_runner, releasever, &confirm).await,
+                    Some(Commands::Offline { command }) => {
+                        run_offline(&mut cmd_runner, command).await
+                    }
+                    Some(Commands::InstallTimer(args)) => {
+                        run_install_timer(&mut cmd_runner, &args).await
+                    }
+                    Some(Commands::UninstallTimer) => timer::uninstall(&mut cmd_runner)
+                        .await
+                        .map(|()| ExitCode::SUCCESS),
+                    Some(Commands::Timer { command }) => match command {
+                        None | Some(TimerCommand::Status) => timer::show_status(&mut cmd_runner)
+                            .await
+                            .map(|()| ExitCode::SUCCESS),
+                    },
+                    Some(Commands::Rollback { command }) => {
+                        run_rollback(&mut cmd_runner, command).await
+                    }
+                    Some(Commands::Info) | Some(Commands::History { .. }) => Ok(ExitCode::SUCCESS),
+                    None => run_update(&mut cmd_runner, config, &cli.update).await,
+                },
+                Err(e) => Err(e),
+            }
+        }
+    };
 
-        // Collect output lines from channel into our pre-allocated buffer
-        self.collect_output_lines(line_rx, buffer_pool.clone())
-            .await;
+    // Emit the JSON document even when the run failed
+    reporter.finish();
+    result
+}
 
-        // Return the buffer pool for potential reuse
-        Ok(buffer_pool)
+/// Applies updates from every backend, prints the summary and records the run
+///
+/// The exit status reports whether a reboot or service restart is needed.
+async fn run_update(
+    cmd_runner: &mut CommandRunner,
+    mut config: Config,
+    args: &UpdateArgs,
+) -> Result<ExitCode> {
+    let started_at = Local::now();
+    // Drop lookups made while collecting system information
+    cmd_runner.take_command_log();
+
+    cmd_runner
+        .reporter()
+        .progress("\nStarting update process...");
+
+    cmd_runner.set_dry_run(args.dry_run);
+    cmd_runner.set_non_interactive(args.non_interactive);
+    if let Some(mode) = args.mode {
+        config.dnf5.mode = mode;
+    }
+    if args.security {
+        config.dnf5.security = true;
+    }
+    if let Some(severity) = args.advisory_severity {
+        config.dnf5.advisory_severity = Some(severity);
+    }
+    if args.cleanup {
+        config.flatpak.cleanup = true;
+        config.dnf5.cleanup = true;
+    }
+    if args.snapshot {
+        config.snapshot.enabled = true;
+    }
+    if args.ignore_snapshot_failure {
+        config.snapshot.required = false;
     }
 
-    /// Spawns tasks to process stdout and stderr streams
-    fn spawn_output_processing_tasks(
-        &self,
-        stdout_reader: tokio::io::Lines<BufReader<tokio::process::ChildStdout>>,
-        stderr_reader: tokio::io::Lines<BufReader<tokio::process::ChildStderr>>,
-        tx: mpsc::Sender<(OutputSource, StringBuffer)>,
-        line_tx: mpsc::Sender<StringBuffer>,
-        buffer_pool: Arc<Mutex<StringBufferPool>>,
-    ) -> (tokio::task::JoinHandle<()>, tokio::task::JoinHandle<()>) {
-        // Create separate clones of the sender for each task
-        let tx_stdout = tx.clone();
-        let line_tx_clone = line_tx.clone();
-        let stdout_pool = buffer_pool.clone();
-
-        let stdout_task = tokio::spawn(async move {
-            let mut stdout_reader = stdout_reader;
-            while let Ok(Some(line)) = stdout_reader.next_line().await {
-                // Get a buffer from the pool for the output handler
-                let mut pool_guard = stdout_pool.lock().await;
-                let mut output_buffer = pool_guard.get();
-                let mut accum_buffer = pool_guard.get();
-                drop(pool_guard); // Release the lock before further operations
-
-                // Fill both buffers with the same content
-                output_buffer.buffer.push_str(&line);
-                accum_buffer.buffer.push_str(&line);
-
-                // Send buffers to their respective channels
-                let _ = line_tx_clone.send(accum_buffer).await;
-                let _ = tx_stdout.send((OutputSource::Stdout, output_buffer)).await;
-            }
-        });
-
-        let tx_stderr = tx.clone();
-        let stderr_pool = buffer_pool.clone();
+    let stage = if args.download_only {
+        UpdateStage::DownloadOnly
+    } else if args.apply_downloaded {
+        UpdateStage::ApplyDownloaded(download::load()?)
+    } else {
+        UpdateStage::Full
+    };
+
+    // Laptops on battery or a metered connection wait for better conditions
+    if !args.dry_run
+        && !args.ignore_preflight
+        && let Some(deferral) = preflight::evaluate(
+            cmd_runner,
+            &config.preflight,
+            std::path::Path::new(preflight::POWER_SUPPLY_DIR),
+            !matches!(stage, UpdateStage::ApplyDownloaded(_)),
+        )
+        .await
+    {
+        return run_deferred(cmd_runner, &config, started_at, deferral).await;
+    }
 
-        let stderr_task = tokio::spawn(async move {
-            let mut stderr_reader = stderr_reader;
-            while let Ok(Some(line)) = stderr_reader.next_line().await {
-                // Get a buffer from the pool and fill it
-                let mut pool_guard = stderr_pool.lock().await;
-                let mut buffer = pool_guard.get();
-                drop(pool_guard); // Release the lock before further operations
+    let mut backends = backend::registry(&config, args.interactive);
+    let reports = backend::run_backends(cmd_runner, &mut backends, &stage).await;
 
-                buffer.buffer.push_str(&line);
+    let summary = print_summary(cmd_runner.reporter(), &reports);
 
-                // Send the buffer to the output handler
-                let _ = tx_stderr.send((OutputSource::Stderr, buffer)).await;
-            }
-        });
+    // Remember what is waiting in the caches until a later run applies it
+    let manifest = match stage {
+        UpdateStage::Full => None,
+        UpdateStage::DownloadOnly => Some(download::DownloadManifest::from_reports(
+            started_at, &reports,
+        )),
+        UpdateStage::ApplyDownloaded(mut manifest) => {
+            manifest.retain_failed(&reports);
+            Some(manifest)
+        }
+    };
+    if let Some(manifest) = manifest
+        && let Err(e) = download::save(&manifest)
+    {
+        cmd_runner
+            .reporter()
+            .warn(&format!("Failed to record the downloaded updates: {:#}", e));
+    }
 
-        (stdout_task, stderr_task)
+    let record = history::RunRecord::new(
+        started_at,
+        args.dry_run,
+        summary.status,
+        &reports,
+        cmd_runner.take_command_log(),
+        &summary.reboot,
+    );
+    // A run that cannot be recorded has still updated the system, so only warn
+    if let Err(e) = history::append(record) {
+        cmd_runner.reporter().warn(&format!(
+            "Failed to record this run in the update history: {:#}",
+            e
+        ));
     }
 
-    /// Collects output lines from the channel into the pre-allocated buffer
-    async fn collect_output_lines(
-        &mut self,
-        mut line_rx: mpsc::Receiver<StringBuffer>,
-        buffer_pool: Arc<Mutex<StringBufferPool>>,
-    ) {
-        while let Some(buffer) = line_rx.recv().await {
-            self.output_buffer.push_str(buffer.as_str());
-            self.output_buffer.push('\n');
-
-            // Return the buffer to the pool
-            let mut pool = buffer_pool.lock().await;
-            pool.return_buffer(buffer);
-        }
+    if summary.status == RunStatus::Failed {
+        return Err(anyhow::anyhow!("All update mechanisms failed"));
     }
 
-    /// Displays system information
-    async fn show_system_info(&mut self) -> Result<()> {
-        println!("{}", "System Information:".blue().bold());
-
-        // Distribution info
-        if let Ok(output) = Command::new("cat").arg("/etc/os-release").output().await {
-            let info = String::from_utf8_lossy(&output.stdout);
-            if let Some(line) = info.lines().find(|l| l.starts_with("PRETTY_NAME=")) {
-                println!(
-                    "Distribution: {}",
-                    line.split('=')
-                        .nth(1)
-                        .unwrap_or("Unknown")
-                        .trim_matches('"')
-                );
-            }
-        }
+    Ok(ExitCode::from(summary.exit_code))
+}
 
-        // Kernel version
-        if let Ok(output) = Command::new("uname").arg("-r").output().await {
-            println!("Kernel: {}", String::from_utf8_lossy(&output.stdout).trim());
+/// Reports why updates were not applied, checking for them instead when configured
+async fn run_deferred(
+    cmd_runner: &mut CommandRunner,
+    config: &Config,
+    started_at: chrono::DateTime<Local>,
+    deferral: preflight::Deferral,
+) -> Result<ExitCode> {
+    let (status, checked) = match deferral.action {
+        PreflightAction::Defer => {
+            cmd_runner
+                .reporter()
+                .warn(&format!("\nUpdates deferred: {}.", deferral.reason));
+            (RunStatus::Deferred, Ok(()))
         }
-
-        // Flatpak version
-        if let Some(output) = self
-            .cmd_cache
-            .execute_if_available("flatpak", &["--version"])
-            .await
-        {
-            println!(
-                "Flatpak: {}",
-                String::from_utf8_lossy(&output.stdout).trim()
-            );
+        PreflightAction::Check => {
+            cmd_runner.reporter().warn(&format!(
+                "\nOnly checking for updates: {}.",
+                deferral.reason
+            ));
+            let mut backends = backend::registry(config, false);
+            let reports = backend::check_backends(cmd_runner, &mut backends).await;
+            (
+                RunStatus::Checked,
+                print_check_summary(cmd_runner.reporter(), &reports),
+            )
         }
+    };
+
+    let reporter = cmd_runner.reporter();
+    reporter.info("Run with --ignore-preflight to update anyway.");
+    reporter.emit(Event::Preflight {
+        action: deferral.action,
+        reason: deferral.reason.clone(),
+    });
+    reporter.emit(Event::Summary {
+        status,
+        updated: false,
+        succeeded: Vec::new(),
+        failed: Vec::new(),
+        exit_code: EXIT_DEFERRED,
+    });
+
+    let mut record = history::RunRecord::new(
+        started_at,
+        false,
+        status,
+        &[],
+        cmd_runner.take_command_log(),
+        &RebootRequirement::None,
+    );
+    record.preflight = Some(deferral.reason);
+    if let Err(e) = history::append(record) {
+        cmd_runner.reporter().warn(&format!(
+            "Failed to record this run in the update history: {:#}",
+            e
+        ));
+    }
 
-        // DNF5 version
-        if let Some(output) = self
-            .cmd_cache
-            .execute_if_available("dnf5", &["--version"])
-            .await
-        {
-            print!("DNF5: {}", String::from_utf8_lossy(&output.stdout));
-        }
+    checked?;
+    Ok(ExitCode::from(EXIT_DEFERRED))
+}
 
-        Ok(())
+/// Whether to go ahead with a reboot, asking unless `--yes` or `--non-interactive` decide
+fn confirmed(reporter: &Reporter, confirm: &ConfirmArgs, question: &str) -> Result<bool> {
+    if confirm.yes {
+        return Ok(true);
+    }
+    if confirm.non_interactive {
+        return Ok(false);
     }
+    reporter.confirm(question, false)
+}
 
-    /// Handles Flatpak updates
-    async fn update_flatpak(&mut self) -> Result<bool> {
-        // Try to use cached result first to avoid async overhead
-        let flatpak_available = match self.cmd_cache.get_cached_availability("flatpak") {
-            Some(available) => available,
-            None => self.cmd_cache.is_command_available("flatpak").await,
-        };
-
-        if !flatpak_available {
-            println!(
-                "{}",
-                "Flatpak is not installed. Skipping Flatpak updates.".yellow()
-            );
-            return Ok(false);
-        }
+/// Downloads the next release and reboots into its installation after confirmation
+async fn run_upgrade_release(
+    cmd_runner: &mut CommandRunner,
+    releasever: Option<u32>,
+    confirm: &ConfirmArgs,
+) -> Result<ExitCode> {
+    cmd_runner.set_non_interactive(confirm.non_interactive);
+    cmd_runner
+        .reporter()
+        .progress("\nPreparing the release upgrade...");
+
+    let target = release::prepare(cmd_runner, releasever).await?;
+    let reporter = cmd_runner.reporter();
+    reporter.success(&format!(
+        "\nFedora {} is downloaded and ready to install.",
+        target
+    ));
+    let question = format!(
+        "Reboot now to install Fedora {}? The installation takes a while.",
+        target
+    );
+    if confirmed(reporter, confirm, &question)? {
+        release::reboot(cmd_runner).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
-        println!("{}", "Updating Flatpak packages...".green());
+    cmd_runner.reporter().warn(&format!(
+        "Run `sudo dnf5 system-upgrade reboot` when you are ready to install Fedora {}.",
+        target
+    ));
+    Ok(ExitCode::from(EXIT_REBOOT_REQUIRED))
+}
 
-        let (status, output) = self
-            .execute_command("flatpak", &["update", "-y"], false)
-            .await?;
+/// Shows, installs or discards the offline transaction prepared by DNF5
+async fn run_offline(
+    cmd_runner: &mut CommandRunner,
+    command: Option<OfflineCommand>,
+) -> Result<ExitCode> {
+    match command.unwrap_or(OfflineCommand::Status) {
+        OfflineCommand::Status => offline::show_status(cmd_runner).await?,
+        OfflineCommand::Reboot(confirm) => {
+            cmd_runner.set_non_interactive(confirm.non_interactive);
+            let status = offline::status(cmd_runner).await;
+            if !status.is_some_and(|status| status.state == offline::OfflineState::Ready) {
+                return Err(anyhow::anyhow!("No offline update is ready to install"));
+            }
 
-        if !status.success() {
-            return Err(anyhow::anyhow!("Flatpak update failed"));
+            let question = "Reboot now to install the offline update?";
+            if !confirmed(cmd_runner.reporter(), &confirm, question)? {
+                cmd_runner
+                    .reporter()
+                    .warn("The offline update stays prepared for the next `dnf5 offline reboot`.");
+                return Ok(ExitCode::from(EXIT_REBOOT_REQUIRED));
+            }
+            offline::reboot(cmd_runner).await?;
+        }
+        OfflineCommand::Clean => {
+            offline::clean(cmd_runner).await?;
+            cmd_runner
+                .reporter()
+                .success("The prepared offline update was discarded.");
         }
-
-        // Check if there were any updates
-        Ok(!output.contains("Nothing to do"))
     }
+    Ok(ExitCode::SUCCESS)
+}
 
-    /// Handles DNF5 updates
-    async fn update_dnf5(&mut self, interactive: bool) -> Result<bool> {
-        // Try to use cached result first to avoid async overhead
-        let dnf5_available = match self.cmd_cache.get_cached_availability("dnf5") {
-            Some(available) => available,
-            None => self.cmd_cache.is_command_available("dnf5").await,
-        };
-
-        if !dnf5_available {
-            println!(
-                "{}",
-                "DNF5 is not installed. Please install it first.".red()
-            );
-            return Err(anyhow::anyhow!("DNF5 not found"));
+/// Undoes the last update run, or lists and returns to the snapshots taken by past runs
+async fn run_rollback(
+    cmd_runner: &mut CommandRunner,
+    command: Option<RollbackCommand>,
+) -> Result<ExitCode> {
+    match command.unwrap_or(RollbackCommand::Snapshots) {
+        RollbackCommand::Snapshots => snapshot::list(
+            cmd_runner.reporter(),
+            &snapshot::from_history(&history::load()?),
+        ),
+        RollbackCommand::Snapshot { run, confirm } => {
+            cmd_runner.set_non_interactive(confirm.non_interactive);
+            let snapshots = snapshot::from_history(&history::load()?);
+            let entry = snapshots
+                .iter()
+                .find(|entry| entry.run == run)
+                .with_context(|| format!("Update run {} took no snapshot", run))?;
+            if snapshot::rollback(cmd_runner, entry, confirm.yes).await? {
+                cmd_runner
+                    .reporter()
+                    .warn("Files were reverted to the snapshot. Reboot to finish the rollback.");
+                return Ok(ExitCode::from(EXIT_REBOOT_REQUIRED));
+            }
         }
+        RollbackCommand::Last { confirm } => {
+            cmd_runner.set_non_interactive(confirm.non_interactive);
+            if rollback::undo_last(cmd_runner, confirm.yes).await? {
+                cmd_runner
+                    .reporter()
+                    .success("The last update run was rolled back.");
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-        println!("{}", "Checking for DNF5 updates...".green());
-
-        // Check for updates - exit code 100 means updates are available
-        let (status, _) = self
-            .execute_command("dnf5", &["--refresh", "check-upgrade"], true)
-            .await?;
+/// Generates the timer units, installing them when asked
+async fn run_install_timer(cmd_runner: &mut CommandRunner, args: &TimerArgs) -> Result<ExitCode> {
+    let options = timer::TimerOptions {
+        schedule: args.schedule.clone(),
+        randomized_delay: args.randomized_delay.clone(),
+        mode: args.mode,
+        security: args.security,
+        cleanup: args.cleanup,
+        user: args.user.clone(),
+    };
+    options.validate()?;
+    let executable =
+        std::env::current_exe().context("Could not determine the path of this executable")?;
+    timer::check_executable(&executable)?;
+    let service = timer::render_service(&executable, &options);
+    let timer_unit = timer::render_timer(&options);
+
+    if !args.install {
+        timer::show_units(cmd_runner, &service, &timer_unit);
+        return Ok(ExitCode::SUCCESS);
+    }
 
-        let has_updates = status.code() == Some(100);
-        if !has_updates {
-            println!("{}", "No DNF5 updates available.".green());
-            return Ok(false);
-        }
+    timer::check_schedule(cmd_runner, &options.schedule).await?;
+    timer::install(cmd_runner, &std::env::temp_dir(), &service, &timer_unit).await?;
+    cmd_runner.reporter().success(&format!(
+        "The update timer is installed and runs {}.",
+        args.schedule
+    ));
+    Ok(ExitCode::SUCCESS)
+}
 
-        println!("{}", "DNF5 updates are available.".green());
+/// Lists past runs or shows a single one from the history journal
+fn run_history(reporter: &Reporter, command: Option<HistoryCommand>) -> Result<ExitCode> {
+    match command.unwrap_or(HistoryCommand::List {
+        limit: DEFAULT_HISTORY_LIMIT,
+    }) {
+        HistoryCommand::List { limit } => history::list(reporter, limit)?,
+        HistoryCommand::Show { id } => history::show(reporter, id)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-        let update_mode = if interactive {
-            println!("\nChoose update mode:");
-            println!("1. Immediate update (type 'now')");
-            println!("2. Offline update (press Enter)");
+/// Looks for pending updates in every backend and reports them
+async fn run_check(cmd_runner: &mut CommandRunner, config: &Config) -> Result<ExitCode> {
+    cmd_runner
+        .reporter()
+        .progress("\nChecking for pending updates...");
 
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input)?;
+    let mut backends = backend::registry(config, false);
+    let reports = backend::check_backends(cmd_runner, &mut backends).await;
 
-            if input.trim().to_lowercase() == "now" {
-                "immediate"
-            } else {
-                "offline"
-            }
-        } else {
-            "immediate"
-        };
-
-        match update_mode {
-            "immediate" => {
-                println!("{}", "Performing immediate DNF5 update...".green());
-                let (status, _) = self
-                    .execute_command("dnf5", &["upgrade", "-y"], true)
-                    .await?;
-                if !status.success() {
-                    return Err(anyhow::anyhow!("DNF5 update failed"));
-                }
+    print_check_summary(cmd_runner.reporter(), &reports)?;
+    Ok(ExitCode::SUCCESS)
+}
 
-                // Check if reboot is needed
-                match self
-                    .execute_command("dnf5", &["needs-restarting"], true)
-                    .await
-                {
-                    Ok(_) => {
-                        // needs-restarting already printed its output
-                        // No need to show additional message as the command itself is clear
-                    }
-                    Err(e) => {
-                        println!(
-                            "{}",
-                            "Warning: Could not determine if restart is needed.".yellow()
-                        );
-                        eprintln!("Error checking restart status: {}", e);
-                    }
+/// Prints which backends have pending updates, failing if any check failed
+fn print_check_summary(reporter: &Reporter, reports: &[CheckReport]) -> Result<()> {
+    let failed = reports.iter().any(|report| report.status.is_err());
+
+    // Machine-readable output already carries a check event per backend
+    if reporter.is_text() {
+        println!("\n{}", "Pending updates:".blue().bold());
+
+        for report in reports {
+            match &report.status {
+                Ok(Some(CheckStatus::Available(pending))) => println!(
+                    "{}: {}",
+                    report.name,
+                    format!("{} updates available", pending.len()).yellow()
+                ),
+                Ok(Some(CheckStatus::UpToDate)) => {
+                    println!("{}: {}", report.name, "up to date".green())
                 }
+                Ok(None) => println!("{}: {}", report.name, "not installed".dimmed()),
+                Err(e) => println!("{}: {} {:#}", report.name, "check failed:".red(), e),
             }
-            "offline" => {
-                println!("{}", "Preparing offline DNF5 update...".green());
-                let (status, _) = self
-                    .execute_command("dnf5", &["upgrade", "--offline", "-y"], true)
-                    .await?;
-                if !status.success() {
-                    return Err(anyhow::anyhow!("DNF5 offline update preparation failed"));
-                }
-                println!(
-                    "{}",
-                    "Offline update prepared. Changes will be applied on next reboot.".yellow()
-                );
+            for note in &report.notes {
+                println!("  {}", note.yellow());
             }
-            _ => unreachable!(),
         }
+    }
 
-        Ok(true)
+    if failed {
+        return Err(anyhow::anyhow!("Some update checks failed"));
     }
-}
 
-/// Handles printing output messages in a serialized manner
-async fn output_handler(
-    mut rx: mpsc::Receiver<(OutputSource, StringBuffer)>,
-    buffer_pool: Arc<Mutex<StringBufferPool>>,
-) {
-    while let Some((source, buffer)) = rx.recv().await {
-        match source {
-            OutputSource::Stdout => println!("{} {}", "[stdout]".blue(), buffer.as_str()),
-            OutputSource::Stderr => eprintln!("{} {}", "[stderr]".red(), buffer.as_str()),
-        }
+    Ok(())
+}
 
-        // Return the buffer to the pool
-        let mut pool = buffer_pool.lock().await;
-        pool.return_buffer(buffer);
-    }
+/// Overall result of an update run
+struct RunSummary {
+    /// Success, partial or failed
+    status: RunStatus,
+    /// Combined reboot requirement of all backends
+    reboot: RebootRequirement,
+    /// Exit status reflecting failures, then the reboot requirement
+    exit_code: u8,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let mut cmd_runner = CommandRunner::new();
+/// Prints the final summary of an update run
+///
+/// The run has failed only when no backend succeeded.
+fn print_summary(reporter: &Reporter, reports: &[BackendReport]) -> RunSummary {
+    let mut failed = Vec::new();
+    let mut succeeded = Vec::new();
+    let mut any_updated = false;
+    let mut any_previewed = false;
+    let mut any_downloaded = false;
+    let mut any_pending = false;
+    let mut reboot = RebootRequirement::None;
+    // Backends whose updates only take effect after a reboot
+    let mut reboot_backends = Vec::new();
+
+    for report in reports {
+        match &report.outcome {
+            Ok(BackendOutcome::Skipped) => {}
+            Ok(BackendOutcome::UpToDate) => succeeded.push(report.name),
+            Ok(BackendOutcome::Updated {
+                reboot: requirement,
+                ..
+            }) => {
+                succeeded.push(report.name);
+                any_updated = true;
+                if *requirement == RebootRequirement::Reboot {
+                    reboot_backends.push(report.name);
+                }
+                reboot = reboot.merge(requirement.clone());
+            }
+            Ok(BackendOutcome::Previewed(transaction)) => {
+                succeeded.push(report.name);
+                any_previewed = true;
+                reporter.info(&format!("{} would {}", report.name, transaction.describe()));
+            }
+            Ok(BackendOutcome::Downloaded(packages)) => {
+                succeeded.push(report.name);
+                any_downloaded = true;
+                reporter.info(&format!(
+                    "{} downloaded {} updates",
+                    report.name,
+                    packages.len()
+                ));
+            }
+            Ok(BackendOutcome::Pending(packages)) => {
+                succeeded.push(report.name);
+                any_pending = true;
+                let names: Vec<&str> = packages.iter().map(|update| update.name.as_str()).collect();
+                reporter.warn(&format!(
+                    "{} left {} updates pending: {}",
+                    report.name,
+                    packages.len(),
+                    names.join(", ")
+                ));
+            }
+            Err(e) => {
+                reporter.error(&format!("{} error: {:#}", report.name, e));
+                failed.push(report.name);
+            }
+        }
 
-    println!(
-        "{} {}",
-        "Fedora Updater".green().bold(),
-        format!("v{}", env!("CARGO_PKG_VERSION")).yellow()
-    );
-    println!("─────────────────────────────\n");
-
-    // Preload command availability checks to reduce async overhead later
-    cmd_runner.preload_common_commands().await;
-
-    cmd_runner.show_system_info().await?;
-    println!("\n{}", "Starting update process...".green());
-
-    let flatpak_result = cmd_runner.update_flatpak().await;
-    let dnf5_result = cmd_runner.update_dnf5(cli.interactive).await;
-
-    match (flatpak_result, dnf5_result) {
-        (Ok(flatpak_updated), Ok(dnf_updated)) => {
-            if flatpak_updated || dnf_updated {
-                println!(
-                    "{}",
-                    "\nUpdates were successfully installed!".green().bold()
-                )
-            } else {
-                println!(
-                    "{}",
-                    "\nSystem is up to date. No updates needed.".green().bold()
-                )
+        // Backends such as containers report each item they updated
+        for item in &report.items {
+            match (&item.error, &item.detail) {
+                (Some(error), _) => reporter.error(&format!("  {}: failed: {}", item.name, error)),
+                (None, Some(detail)) => reporter.info(&format!(
+                    "  {}: {} ({})",
+                    item.name,
+                    item.outcome.label(),
+                    detail
+                )),
+                (None, None) => {
+                    reporter.info(&format!("  {}: {}", item.name, item.outcome.label()))
+                }
             }
         }
-        (Err(_), Ok(_)) => println!(
-            "{}",
-            "\nWarning: Flatpak updates failed, but DNF5 updates succeeded.".yellow()
-        ),
-        (Ok(_), Err(_)) => println!(
-            "{}",
-            "\nWarning: DNF5 updates failed, but Flatpak updates succeeded.".yellow()
-        ),
-        (Err(_), Err(_)) => {
-            println!("{}", "\nError: Both update mechanisms failed.".red().bold());
-            return Err(anyhow::anyhow!("All update mechanisms failed"));
+        for note in &report.notes {
+            reporter.warn(&format!("  {}", note));
         }
     }
 
-    Ok(())
+    let status = if failed.is_empty() {
+        if any_updated {
+            reporter.success("\nUpdates were successfully installed!");
+        } else if any_previewed {
+            reporter.success("\nDry run complete. No changes were made.");
+        } else if any_downloaded {
+            reporter.success(
+                "\nUpdates were downloaded. Apply them with `fedora-updater update --apply-downloaded`.",
+            );
+        } else if any_pending {
+            reporter.warn("\nSome updates are still pending because they were not confirmed.");
+        } else {
+            reporter.success("\nSystem is up to date. No updates needed.");
+        }
+        RunStatus::Success
+    } else if succeeded.is_empty() {
+        reporter.error("\nError: All update mechanisms failed.");
+        RunStatus::Failed
+    } else {
+        reporter.warn(&format!(
+            "\nWarning: {} updates failed, but {} updates succeeded.",
+            failed.join(", "),
+            succeeded.join(", ")
+        ));
+        RunStatus::Partial
+    };
+
+    match &reboot {
+        RebootRequirement::None => {}
+        RebootRequirement::Services(services) => reporter.warn(&format!(
+            "The following services should be restarted: {}",
+            services.join(", ")
+        )),
+        RebootRequirement::Reboot => reporter.warn(&format!(
+            "A reboot is required to finish applying updates from {}.",
+            reboot_backends.join(", ")
+        )),
+    }
+    reporter.emit(Event::Reboot {
+        requirement: reboot.label(),
+        services: match &reboot {
+            RebootRequirement::Services(services) => services.clone(),
+            _ => Vec::new(),
+        },
+    });
+
+    // A pending reboot or restart must not hide that some updates failed
+    let exit_code = match (status, &reboot) {
+        (RunStatus::Failed | RunStatus::Partial, _) => 1,
+        (_, RebootRequirement::None) => 0,
+        (_, RebootRequirement::Services(_)) => EXIT_SERVICES_RESTART,
+        (_, RebootRequirement::Reboot) => EXIT_REBOOT_REQUIRED,
+    };
+    reporter.emit(Event::Summary {
+        status,
+        updated: any_updated,
+        succeeded: succeeded.iter().map(|name| name.to_string()).collect(),
+        failed: failed.iter().map(|name| name.to_string()).collect(),
+        exit_code,
+    });
+
+    RunSummary {
+        status,
+        reboot,
+        exit_code,
+    }
 }
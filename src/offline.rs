@@ -0,0 +1,186 @@
+//! Managing the offline transaction prepared by `dnf5 upgrade --offline` or `system-upgrade download`
+
+use crate::output::Event;
+use crate::runner::CommandRunner;
+use anyhow::Result;
+use colored::*;
+use serde::Serialize;
+
+/// State of the stored offline transaction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OfflineState {
+    /// Nothing is prepared
+    #[default]
+    None,
+    /// Downloaded and installed on the next `dnf5 offline reboot`
+    Ready,
+    /// Started on a previous boot but did not finish
+    Incomplete,
+}
+
+/// Offline transaction reported by `dnf5 offline status`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct OfflineStatus {
+    pub(crate) state: OfflineState,
+    /// Command line that prepared the transaction
+    pub(crate) command: Option<String>,
+}
+
+impl OfflineStatus {
+    /// Text shown for a stored transaction, or None when nothing is prepared
+    pub(crate) fn describe(&self) -> Option<String> {
+        let state = match self.state {
+            OfflineState::None => return None,
+            OfflineState::Ready => "ready to install on the next reboot",
+            OfflineState::Incomplete => "did not finish installing",
+        };
+        Some(match &self.command {
+            Some(command) => format!("{} ({})", state, command),
+            None => state.to_string(),
+        })
+    }
+}
+
+/// Parses `dnf5 offline status` output
+///
+/// ```text
+/// An offline transaction was initiated by the following command:
+///     dnf5 upgrade --offline -y
+/// Run `dnf5 offline reboot` to reboot and perform the offline transaction.
+/// ```
+pub(crate) fn parse_status(output: &str) -> OfflineStatus {
+    let mut lines = output.lines();
+    let mut status = OfflineStatus::default();
+
+    while let Some(line) = lines.next() {
+        let state = if line.starts_with("An offline transaction was initiated") {
+            OfflineState::Ready
+        } else if line.contains("did not finish") {
+            OfflineState::Incomplete
+        } else {
+            continue;
+        };
+
+        // The command line follows on an indented line
+        status = OfflineStatus {
+            state,
+            command: lines
+                .next()
+                .filter(|next| next.starts_with(char::is_whitespace))
+                .map(|next| next.trim().to_string())
+                .filter(|command| !command.is_empty()),
+        };
+        break;
+    }
+
+    status
+}
+
+/// Reads the stored offline transaction; None when DNF5 cannot report it
+pub(crate) async fn status(runner: &mut CommandRunner) -> Option<OfflineStatus> {
+    let output = runner
+        .capture_command("dnf5", &["offline", "status"])
+        .await?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(parse_status(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Prints the stored offline transaction
+pub(crate) async fn show_status(runner: &mut CommandRunner) -> Result<()> {
+    let status = status(runner)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Could not read the offline transaction from DNF5"))?;
+
+    let reporter = runner.reporter();
+    if !reporter.is_text() {
+        reporter.emit(Event::OfflineStatus { status });
+        return Ok(());
+    }
+
+    println!("\n{}", "Offline update:".blue().bold());
+    match status.state {
+        OfflineState::None => println!("{}", "nothing prepared".green()),
+        OfflineState::Ready => {
+            println!("{}", "ready to install on the next reboot".yellow());
+            if let Some(command) = &status.command {
+                println!("Prepared by: {}", command);
+            }
+            reporter.info("Install it with `offline reboot` or discard it with `offline clean`.");
+        }
+        OfflineState::Incomplete => {
+            println!("{}", "did not finish installing".red());
+            if let Some(command) = &status.command {
+                println!("Prepared by: {}", command);
+            }
+            reporter
+                .info("See `dnf5 offline log` for details, then discard it with `offline clean`.");
+        }
+    }
+
+    Ok(())
+}
+
+/// Reboots to install the prepared transaction
+pub(crate) async fn reboot(runner: &mut CommandRunner) -> Result<()> {
+    let (status, _) = runner
+        .execute_command("dnf5", &["offline", "reboot", "-y"], true)
+        .await?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("dnf5 offline reboot failed"));
+    }
+    Ok(())
+}
+
+/// Discards the prepared transaction and its downloaded packages
+pub(crate) async fn clean(runner: &mut CommandRunner) -> Result<()> {
+    let (status, _) = runner
+        .execute_command("dnf5", &["offline", "clean"], true)
+        .await?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("dnf5 offline clean failed"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{OutputFormat, Reporter};
+    use crate::process::fake::ScriptedExecutor;
+    use std::sync::Arc;
+
+    const STATUS_PENDING: &str = include_str!("../tests/fixtures/dnf5/offline-status-pending.txt");
+
+    #[test]
+    fn parses_pending_transaction() {
+        let status = parse_status(STATUS_PENDING);
+
+        assert_eq!(status.state, OfflineState::Ready);
+        assert_eq!(status.command.as_deref(), Some("dnf5 upgrade --offline -y"));
+        assert_eq!(
+            status.describe().as_deref(),
+            Some("ready to install on the next reboot (dnf5 upgrade --offline -y)")
+        );
+
+        let none = parse_status("No offline transaction is stored.\n");
+        assert_eq!(none, OfflineStatus::default());
+        assert_eq!(none.describe(), None);
+    }
+
+    #[tokio::test]
+    async fn status_is_unknown_without_offline_support() {
+        let executor = Arc::new(ScriptedExecutor::new(&["dnf5"]).expect(
+            "dnf5 offline status",
+            2,
+            "Unknown argument",
+        ));
+        let mut runner =
+            CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone());
+
+        assert_eq!(status(&mut runner).await, None);
+        executor.assert_done();
+    }
+}
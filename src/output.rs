@@ -0,0 +1,298 @@
+//! Human and machine-readable reporting of everything a run does
+
+use anyhow::Result;
+use clap::ValueEnum;
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// How progress and results are written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputFormat {
+    /// Color-coded text for humans
+    #[default]
+    Text,
+    /// A single JSON document with every event, printed when the run ends
+    Json,
+    /// One JSON event per line, streamed as the run progresses
+    Ndjson,
+}
+
+/// Severity and styling of a free-form message
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Level {
+    Info,
+    Progress,
+    Success,
+    Warning,
+    Error,
+}
+
+/// Stream a line of command output came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OutputSource {
+    Stdout,
+    Stderr,
+}
+
+/// A structured event emitted in JSON output modes
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Event {
+    /// A status message that is printed as colored text in text mode
+    Message { level: Level, text: String },
+    /// Distribution, kernel and tool versions
+    SystemInfo {
+        distribution: Option<String>,
+        kernel: Option<String>,
+        flatpak: Option<String>,
+        dnf5: Option<String>,
+        /// Offline transaction already prepared for the next reboot
+        offline_update: Option<crate::offline::OfflineStatus>,
+    },
+    /// A command is about to be executed
+    Command {
+        program: String,
+        args: Vec<String>,
+        sudo: bool,
+    },
+    /// A command finished; `exit_code` is absent when killed by a signal
+    CommandExit {
+        program: String,
+        exit_code: Option<i32>,
+    },
+    /// A line streamed from a running command
+    Line { source: OutputSource, text: String },
+    /// Result of checking a backend for pending updates
+    Check {
+        backend: String,
+        status: &'static str,
+        pending: Vec<crate::backend::PendingUpdate>,
+        error: Option<String>,
+        /// Notes about the pending updates, such as advisory counts
+        notes: Vec<String>,
+    },
+    /// Transaction a backend would run, resolved by a dry run
+    Transaction {
+        backend: String,
+        summary: crate::backend::TransactionSummary,
+    },
+    /// Final result of a backend in an update run
+    Outcome {
+        backend: String,
+        outcome: crate::backend::OutcomeKind,
+        error: Option<String>,
+        /// Results of items updated independently, such as containers
+        items: Vec<crate::backend::ItemReport>,
+        notes: Vec<String>,
+    },
+    /// Combined reboot requirement of all backends
+    Reboot {
+        requirement: &'static str,
+        services: Vec<String>,
+    },
+    /// Overall result of the run
+    Summary {
+        status: crate::history::RunStatus,
+        updated: bool,
+        succeeded: Vec<String>,
+        failed: Vec<String>,
+        exit_code: u8,
+    },
+    /// Power or network conditions kept the run from applying updates
+    Preflight {
+        action: crate::preflight::PreflightAction,
+        reason: String,
+    },
+    /// A past update run read from the history journal
+    HistoryEntry {
+        #[serde(flatten)]
+        run: crate::history::RunRecord,
+    },
+    /// The offline transaction stored by DNF5
+    OfflineStatus {
+        #[serde(flatten)]
+        status: crate::offline::OfflineStatus,
+    },
+    /// Units generated by `install-timer` without `--install`
+    TimerUnits { service: String, timer: String },
+    /// The update timer and the last run of its service
+    TimerStatus {
+        #[serde(flatten)]
+        status: crate::timer::TimerStatus,
+    },
+    /// Snapshots taken by past update runs, newest first
+    Snapshots {
+        snapshots: Vec<crate::snapshot::RunSnapshots>,
+    },
+    /// Steps that return `/` to the snapshot taken before a run, and whether they ran
+    SnapshotRollback {
+        run: u64,
+        steps: Vec<String>,
+        performed: bool,
+    },
+    /// Changes of an update run being undone, previewed first and then performed
+    Rollback {
+        run: u64,
+        steps: Vec<crate::rollback::UndoStep>,
+        performed: bool,
+    },
+}
+
+/// Top-level JSON document printed in `Json` mode
+#[derive(Serialize)]
+struct Document<'a> {
+    version: &'static str,
+    events: &'a [Event],
+}
+
+/// Routes messages and events to the selected output format
+///
+/// Cloning is cheap; all clones share the same collected events.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Reporter {
+    format: OutputFormat,
+    // Events collected for the final document in `Json` mode
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl Reporter {
+    pub(crate) fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            events: Arc::default(),
+        }
+    }
+
+    /// Whether human-readable text should be printed
+    pub(crate) fn is_text(&self) -> bool {
+        self.format == OutputFormat::Text
+    }
+
+    /// Records a structured event; does nothing in text mode
+    pub(crate) fn emit(&self, event: Event) {
+        match self.format {
+            OutputFormat::Text => {}
+            OutputFormat::Json => {
+                if let Ok(mut events) = self.events.lock() {
+                    events.push(event);
+                }
+            }
+            OutputFormat::Ndjson => {
+                if let Ok(line) = serde_json::to_string(&event) {
+                    println!("{}", line);
+                }
+            }
+        }
+    }
+
+    /// Prints the collected events as a single JSON document in `Json` mode
+    pub(crate) fn finish(&self) {
+        if self.format != OutputFormat::Json {
+            return;
+        }
+
+        let events = match self.events.lock() {
+            Ok(mut events) => std::mem::take(&mut *events),
+            Err(_) => return,
+        };
+        let document = Document {
+            version: env!("CARGO_PKG_VERSION"),
+            events: &events,
+        };
+        if let Ok(text) = serde_json::to_string_pretty(&document) {
+            println!("{}", text);
+        }
+    }
+
+    /// Prints a message in the style matching its level, or emits it as an event
+    fn message(&self, level: Level, text: &str) {
+        if !self.is_text() {
+            self.emit(Event::Message {
+                level,
+                text: text.trim().to_string(),
+            });
+            return;
+        }
+
+        match level {
+            Level::Info => println!("{}", text),
+            Level::Progress => println!("{}", text.green()),
+            Level::Success => println!("{}", text.green().bold()),
+            Level::Warning => println!("{}", text.yellow()),
+            Level::Error => println!("{}", text.red()),
+        }
+    }
+
+    /// Plain informational text
+    pub(crate) fn info(&self, text: &str) {
+        self.message(Level::Info, text);
+    }
+
+    /// A step of the update process, printed in green
+    pub(crate) fn progress(&self, text: &str) {
+        self.message(Level::Progress, text);
+    }
+
+    /// A successful result, printed in bold green
+    pub(crate) fn success(&self, text: &str) {
+        self.message(Level::Success, text);
+    }
+
+    /// Something that needs the user's attention, printed in yellow
+    pub(crate) fn warn(&self, text: &str) {
+        self.message(Level::Warning, text);
+    }
+
+    /// A failure, printed in red
+    pub(crate) fn error(&self, text: &str) {
+        self.message(Level::Error, text);
+    }
+
+    /// Asks a question on the terminal, returning the trimmed and lowercased answer
+    ///
+    /// JSON output is buffered or parsed by other programs, so the question goes to stderr there.
+    pub(crate) fn ask(&self, question: &str) -> Result<String> {
+        if self.is_text() {
+            println!("{}", question);
+        } else {
+            eprintln!("{}", question);
+        }
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        Ok(input.trim().to_lowercase())
+    }
+
+    /// Asks a yes/no question, answered with `default` when the reply is empty
+    pub(crate) fn confirm(&self, question: &str, default: bool) -> Result<bool> {
+        let hint = if default { "[Y/n]" } else { "[y/N]" };
+        Ok(
+            match self.ask(&format!("{} {}", question, hint))?.as_str() {
+                "" => default,
+                answer => matches!(answer, "y" | "yes"),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_tagged_with_their_type() {
+        let event = Event::Line {
+            source: OutputSource::Stderr,
+            text: "Error: Failed to download metadata".to_string(),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"line","source":"stderr","text":"Error: Failed to download metadata"}"#
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Power, network and disk space conditions checked before updates are applied
+
+use crate::config::PreflightConfig;
+use crate::runner::CommandRunner;
+use crate::size::format_size;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Where the kernel lists power supplies
+pub(crate) const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// What a run does when its conditions are not met
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PreflightAction {
+    /// Skip the run entirely
+    #[default]
+    Defer,
+    /// Only report pending updates
+    Check,
+}
+
+/// Why a run was deferred or reduced to a check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Deferral {
+    pub(crate) action: PreflightAction,
+    pub(crate) reason: String,
+}
+
+/// Power source and charge of the system batteries
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct PowerState {
+    pub(crate) on_ac: bool,
+    /// Average charge of the system batteries; None without a battery
+    pub(crate) battery: Option<u8>,
+}
+
+/// Reads a sysfs attribute, trimmed
+fn attribute(dir: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(dir.join(name))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+/// Reads the power state from a `/sys/class/power_supply` style directory
+///
+/// Batteries of peripherals such as mice report `scope` `Device` and are ignored.
+pub(crate) fn read_power(dir: &Path) -> PowerState {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return PowerState {
+            on_ac: true,
+            battery: None,
+        };
+    };
+
+    let mut on_ac = false;
+    let mut charges: Vec<u32> = Vec::new();
+    for supply in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        match attribute(&supply, "type").as_deref() {
+            Some("Mains" | "USB") => {
+                on_ac |= attribute(&supply, "online").as_deref() == Some("1");
+            }
+            Some("Battery") if attribute(&supply, "scope").as_deref() != Some("Device") => {
+                // A charging battery means external power even without a Mains supply
+                on_ac |= matches!(
+                    attribute(&supply, "status").as_deref(),
+                    Some("Charging" | "Full")
+                );
+                if let Some(capacity) =
+                    attribute(&supply, "capacity").and_then(|capacity| capacity.parse().ok())
+                {
+                    charges.push(capacity);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let battery = (!charges.is_empty())
+        .then(|| (charges.iter().sum::<u32>() / charges.len() as u32).min(100) as u8);
+    PowerState {
+        // Desktops have no battery and are always on external power
+        on_ac: on_ac || battery.is_none(),
+        battery,
+    }
+}
+
+/// Returns true if `nmcli -t -f GENERAL.DEVICE,GENERAL.METERED device show` lists a metered device
+///
+/// NetworkManager reports `yes`, `no`, `unknown`, or a guess such as `yes (guessed)`.
+pub(crate) fn parse_metered(output: &str) -> bool {
+    output.lines().any(|line| {
+        line.strip_prefix("GENERAL.METERED:")
+            .is_some_and(|value| value.starts_with("yes"))
+    })
+}
+
+/// Asks NetworkManager whether a connected device is metered
+///
+/// Without NetworkManager the connection is assumed not to be metered.
+async fn is_metered(runner: &mut CommandRunner) -> bool {
+    runner
+        .capture_command(
+            "nmcli",
+            &[
+                "-t",
+                "-f",
+                "GENERAL.DEVICE,GENERAL.METERED",
+                "device",
+                "show",
+            ],
+        )
+        .await
+        .is_some_and(|output| parse_metered(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Checks power and network conditions, returning why the run should not apply updates
+///
+/// `needs_network` is false when updates are applied from the cache only.
+pub(crate) async fn evaluate(
+    runner: &mut CommandRunner,
+    config: &PreflightConfig,
+    power_supply_dir: &Path,
+    needs_network: bool,
+) -> Option<Deferral> {
+    let mut reasons = Vec::new();
+
+    let power = read_power(power_supply_dir);
+    if let Some(charge) = power.battery
+        && !power.on_ac
+        && charge < config.min_battery
+    {
+        reasons.push(format!(
+            "running on battery at {}% (minimum {}%)",
+            charge, config.min_battery
+        ));
+    }
+
+    if needs_network && !config.allow_metered && is_metered(runner).await {
+        reasons.push("the network connection is metered".to_string());
+    }
+
+    (!reasons.is_empty()).then(|| Deferral {
+        action: config.action,
+        reason: reasons.join(" and "),
+    })
+}
+
+/// Free space kept in reserve on top of a transaction's estimate
+const SPACE_HEADROOM: u64 = 100 * 1024 * 1024;
+
+/// Space a transaction needs on the filesystem that holds `path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SpaceRequirement {
+    pub(crate) path: PathBuf,
+    pub(crate) bytes: u64,
+    /// What the space is for, such as `package downloads`
+    pub(crate) purpose: String,
+}
+
+impl SpaceRequirement {
+    pub(crate) fn new(path: impl Into<PathBuf>, bytes: u64, purpose: &str) -> Self {
+        Self {
+            path: path.into(),
+            bytes,
+            purpose: purpose.to_string(),
+        }
+    }
+}
+
+/// A filesystem and everything a transaction puts on it
+struct Filesystem {
+    mount: String,
+    available: u64,
+    needed: u64,
+    purposes: Vec<String>,
+}
+
+/// Parses `df --output=target,avail -B1 PATH` into the mount point and its free bytes
+pub(crate) fn parse_df(output: &str) -> Option<(String, u64)> {
+    let line = output.lines().nth(1)?;
+    let (mount, available) = line.trim().rsplit_once(char::is_whitespace)?;
+    Some((mount.trim().to_string(), available.parse().ok()?))
+}
+
+/// Fails if a filesystem lacks room for what `backend` is about to put on it
+///
+/// Requirements on the same filesystem add up. Filesystems whose free space
+/// cannot be read are not checked.
+pub(crate) async fn check_space(
+    runner: &mut CommandRunner,
+    backend: &str,
+    requirements: &[SpaceRequirement],
+) -> Result<()> {
+    let mut filesystems: Vec<Filesystem> = Vec::new();
+
+    for requirement in requirements.iter().filter(|r| r.bytes > 0) {
+        // Directories such as the package cache may not have been created yet
+        let Some(path) = requirement.path.ancestors().find(|path| path.exists()) else {
+            continue;
+        };
+        let path = path.display().to_string();
+        let Some(output) = runner
+            .capture_command("df", &["--output=target,avail", "-B1", &path])
+            .await
+        else {
+            continue;
+        };
+        let Some((mount, available)) = parse_df(&String::from_utf8_lossy(&output.stdout)) else {
+            continue;
+        };
+
+        let purpose = format!("{} {}", requirement.purpose, format_size(requirement.bytes));
+        match filesystems.iter_mut().find(|fs| fs.mount == mount) {
+            Some(fs) => {
+                fs.needed += requirement.bytes;
+                fs.purposes.push(purpose);
+            }
+            None => filesystems.push(Filesystem {
+                mount,
+                available,
+                needed: requirement.bytes,
+                purposes: vec![purpose],
+            }),
+        }
+    }
+
+    let short: Vec<String> = filesystems
+        .iter()
+        .filter(|fs| fs.needed + SPACE_HEADROOM > fs.available)
+        .map(|fs| {
+            format!(
+                "{} needs {} ({}) but has {} free",
+                fs.mount,
+                format_size(fs.needed),
+                fs.purposes.join(", "),
+                format_size(fs.available)
+            )
+        })
+        .collect();
+    if short.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow::anyhow!(
+        "Not enough disk space for {} updates: {}. Free some space, for example with `update --cleanup`, and try again",
+        backend,
+        short.join("; ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{OutputFormat, Reporter};
+    use crate::process::fake::ScriptedExecutor;
+    use std::sync::Arc;
+
+    const LAPTOP: &str = "tests/fixtures/power_supply/laptop";
+    const NMCLI_METERED: &str = include_str!("../tests/fixtures/nmcli/device-show-metered.txt");
+
+    #[test]
+    fn reads_system_battery_and_ignores_peripherals() {
+        assert_eq!(
+            read_power(Path::new(LAPTOP)),
+            PowerState {
+                on_ac: false,
+                battery: Some(23),
+            }
+        );
+        // No power supplies at all is a desktop
+        assert_eq!(
+            read_power(Path::new("tests/fixtures/power_supply/missing")),
+            PowerState {
+                on_ac: true,
+                battery: None,
+            }
+        );
+    }
+
+    #[test]
+    fn reads_free_space_of_the_mount_point() {
+        assert_eq!(
+            parse_df("Mounted on        Avail\n/var         7516192768\n"),
+            Some(("/var".to_string(), 7_516_192_768))
+        );
+        assert_eq!(parse_df("Mounted on Avail\n"), None);
+    }
+
+    #[tokio::test]
+    async fn adds_up_requirements_on_the_same_filesystem() {
+        let executor = Arc::new(
+            ScriptedExecutor::new(&["df"])
+                .expect(
+                    "df --output=target,avail -B1",
+                    0,
+                    "Mounted on Avail\n/ 1073741824\n",
+                )
+                .expect(
+                    "df --output=target,avail -B1",
+                    0,
+                    "Mounted on Avail\n/ 1073741824\n",
+                )
+                .expect(
+                    "df --output=target,avail -B1",
+                    0,
+                    "Mounted on Avail\n/boot 734003200\n",
+                ),
+        );
+        let mut runner =
+            CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone());
+        let requirements = [
+            SpaceRequirement::new("/var/cache/libdnf5", 600 * 1024 * 1024, "package downloads"),
+            SpaceRequirement::new("/", 400 * 1024 * 1024, "installed packages"),
+            SpaceRequirement::new("/boot", 128 * 1024 * 1024, "new kernels"),
+        ];
+
+        let error = check_space(&mut runner, "DNF5", &requirements)
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.starts_with(
+            "Not enough disk space for DNF5 updates: / needs 1000.0 MiB \
+             (package downloads 600.0 MiB, installed packages 400.0 MiB) but has 1.0 GiB free."
+        ));
+        executor.assert_done();
+    }
+
+    #[test]
+    fn detects_metered_devices() {
+        assert!(parse_metered(NMCLI_METERED));
+        assert!(!parse_metered(
+            "GENERAL.DEVICE:enp0s31f6\nGENERAL.METERED:no (guessed)\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn defers_on_low_battery_and_metered_network() {
+        let executor = Arc::new(ScriptedExecutor::new(&["nmcli"]).expect(
+            "nmcli -t -f GENERAL.DEVICE,GENERAL.METERED device show",
+            0,
+            NMCLI_METERED,
+        ));
+        let mut runner =
+            CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone());
+        let config = PreflightConfig {
+            action: PreflightAction::Check,
+            ..PreflightConfig::default()
+        };
+
+        let deferral = evaluate(&mut runner, &config, Path::new(LAPTOP), true)
+            .await
+            .unwrap();
+
+        assert_eq!(deferral.action, PreflightAction::Check);
+        assert_eq!(
+            deferral.reason,
+            "running on battery at 23% (minimum 30%) and the network connection is metered"
+        );
+        executor.assert_done();
+
+        // Applying from the cache does not use the network
+        let config = PreflightConfig {
+            min_battery: 20,
+            ..config
+        };
+        assert_eq!(
+            evaluate(&mut runner, &config, Path::new(LAPTOP), false).await,
+            None
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Process execution behind a trait, so the updater can be driven by scripted fakes in tests
+
+use async_trait::async_trait;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::process::{ExitStatus, Output, Stdio};
+use tokio::io::AsyncRead;
+use tokio::process::Command;
+
+/// A stream of output from a spawned process
+pub(crate) type OutputStream = Box<dyn AsyncRead + Send + Unpin>;
+
+/// A process started by `ProcessExecutor::spawn` whose output is still being produced
+pub(crate) struct SpawnedProcess {
+    pub(crate) stdout: OutputStream,
+    pub(crate) stderr: OutputStream,
+    /// Resolves to the exit status once the process has finished
+    pub(crate) status: Pin<Box<dyn Future<Output = io::Result<ExitStatus>> + Send>>,
+}
+
+/// Starts external programs on behalf of `CommandRunner`
+#[async_trait]
+pub(crate) trait ProcessExecutor: Send + Sync + std::fmt::Debug {
+    /// Runs a program to completion and captures its output
+    async fn output(&self, program: &str, args: &[&str]) -> io::Result<Output>;
+
+    /// Spawns a program with piped stdout and stderr for streaming
+    async fn spawn(&self, program: &str, args: &[&str]) -> io::Result<SpawnedProcess>;
+}
+
+/// Runs real processes through `tokio::process`
+#[derive(Debug, Default)]
+pub(crate) struct SystemExecutor;
+
+#[async_trait]
+impl ProcessExecutor for SystemExecutor {
+    async fn output(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        Command::new(program).args(args).output().await
+    }
+
+    async fn spawn(&self, program: &str, args: &[&str]) -> io::Result<SpawnedProcess> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Both handles are present because the streams were configured as piped
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| io::Error::other("Failed to capture stdout"))?;
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| io::Error::other("Failed to capture stderr"))?;
+
+        Ok(SpawnedProcess {
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+            status: Box::pin(async move { child.wait().await }),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::os::unix::process::ExitStatusExt;
+    use std::sync::Mutex;
+
+    /// A command the fake expects next, and what it answers
+    #[derive(Debug)]
+    struct Expectation {
+        /// Prefix of the expected command line, including `sudo` when used
+        command_line: String,
+        exit_code: i32,
+        stdout: String,
+    }
+
+    /// Executor that answers commands from a script instead of running them
+    ///
+    /// `which` succeeds for the installed programs; every other command must
+    /// match the next expectation in order, or the test panics.
+    #[derive(Debug, Default)]
+    pub(crate) struct ScriptedExecutor {
+        installed: Vec<String>,
+        script: Mutex<VecDeque<Expectation>>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedExecutor {
+        /// Creates a fake on which only the given programs are installed
+        pub(crate) fn new(installed: &[&str]) -> Self {
+            Self {
+                installed: installed.iter().map(|name| name.to_string()).collect(),
+                ..Self::default()
+            }
+        }
+
+        /// Expects a command starting with `command_line` and answers it
+        pub(crate) fn expect(self, command_line: &str, exit_code: i32, stdout: &str) -> Self {
+            self.script.lock().unwrap().push_back(Expectation {
+                command_line: command_line.to_string(),
+                exit_code,
+                stdout: stdout.to_string(),
+            });
+            self
+        }
+
+        /// Command lines executed so far, excluding `which` lookups
+        pub(crate) fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        /// Panics if some expected commands were never executed
+        pub(crate) fn assert_done(&self) {
+            let script = self.script.lock().unwrap();
+            assert!(
+                script.is_empty(),
+                "expected commands were not run: {:?}",
+                script
+                    .iter()
+                    .map(|expectation| &expectation.command_line)
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        /// Matches a command against the script, returning its exit status and stdout
+        fn answer(&self, program: &str, args: &[&str]) -> (ExitStatus, Vec<u8>) {
+            if program == "which" {
+                let installed = args
+                    .first()
+                    .is_some_and(|name| self.installed.iter().any(|program| program == name));
+                return (
+                    ExitStatus::from_raw(if installed { 0 } else { 1 << 8 }),
+                    Vec::new(),
+                );
+            }
+
+            let command_line = std::iter::once(program)
+                .chain(args.iter().copied())
+                .collect::<Vec<_>>()
+                .join(" ");
+            self.calls.lock().unwrap().push(command_line.clone());
+
+            let expectation = self
+                .script
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| panic!("unexpected command: {}", command_line));
+            assert!(
+                command_line.starts_with(&expectation.command_line),
+                "expected `{}`, got `{}`",
+                expectation.command_line,
+                command_line
+            );
+
+            (
+                ExitStatus::from_raw(expectation.exit_code << 8),
+                expectation.stdout.into_bytes(),
+            )
+        }
+    }
+
+    #[async_trait]
+    impl ProcessExecutor for ScriptedExecutor {
+        async fn output(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+            let (status, stdout) = self.answer(program, args);
+            Ok(Output {
+                status,
+                stdout,
+                stderr: Vec::new(),
+            })
+        }
+
+        async fn spawn(&self, program: &str, args: &[&str]) -> io::Result<SpawnedProcess> {
+            let (status, stdout) = self.answer(program, args);
+            Ok(SpawnedProcess {
+                stdout: Box::new(io::Cursor::new(stdout)),
+                stderr: Box::new(io::Cursor::new(Vec::new())),
+                status: Box::pin(async move { Ok(status) }),
+            })
+        }
+    }
+}
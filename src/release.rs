@@ -0,0 +1,214 @@
+//! Upgrading to the next Fedora release with `dnf5 system-upgrade`
+
+use crate::backend::run_dnf5;
+use crate::runner::CommandRunner;
+use anyhow::{Context, Result};
+
+/// Exit status of `dnf5 check-upgrade` when updates are available
+const EXIT_UPDATES_AVAILABLE: i32 = 100;
+
+/// What went wrong while downloading the next release
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct DownloadProblems {
+    /// Repositories whose metadata could not be downloaded for the new release
+    pub(crate) repositories: Vec<String>,
+    /// Dependency problems reported by the solver
+    pub(crate) dependencies: Vec<String>,
+}
+
+/// Reads the release number from the `VERSION_ID` line of an os-release file
+pub(crate) fn parse_version_id(os_release: &str) -> Option<u32> {
+    os_release.lines().find_map(|line| {
+        let value = line.strip_prefix("VERSION_ID=")?;
+        value.trim().trim_matches('"').parse().ok()
+    })
+}
+
+/// Collects missing repositories and dependency problems from `system-upgrade download` output
+pub(crate) fn parse_download_problems(output: &str) -> DownloadProblems {
+    let mut problems = DownloadProblems::default();
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        // Failed to download metadata (baseurl: "...") for repository "google-chrome"
+        if let Some((_, repository)) = line.split_once("for repository ") {
+            let repository = repository
+                .trim_matches(|c: char| c == '"' || c == '\'' || c == ':')
+                .to_string();
+            if !repository.is_empty() && !problems.repositories.contains(&repository) {
+                problems.repositories.push(repository);
+            }
+            continue;
+        }
+
+        // Problem: package foo requires bar, but none of the providers can be installed
+        if line.starts_with("Problem")
+            && let Some((_, problem)) = line.split_once(": ")
+        {
+            problems.dependencies.push(problem.trim().to_string());
+        }
+    }
+
+    problems
+}
+
+/// Reads the running release from /etc/os-release
+async fn current_release(runner: &mut CommandRunner) -> Result<u32> {
+    let output = runner
+        .capture_command("cat", &["/etc/os-release"])
+        .await
+        .context("Failed to read /etc/os-release")?;
+    parse_version_id(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| anyhow::anyhow!("Could not read VERSION_ID from /etc/os-release"))
+}
+
+/// Checks that the current release is fully updated and downloads the next one
+///
+/// Returns the release that was downloaded and is ready to be installed by
+/// `dnf5 system-upgrade reboot`.
+pub(crate) async fn prepare(runner: &mut CommandRunner, target: Option<u32>) -> Result<u32> {
+    let current = current_release(runner).await?;
+    let target = target.unwrap_or(current + 1);
+    if target <= current {
+        return Err(anyhow::anyhow!(
+            "Fedora {} is not newer than the installed Fedora {}",
+            target,
+            current
+        ));
+    }
+
+    runner.reporter().progress(&format!(
+        "Checking that Fedora {} is fully updated...",
+        current
+    ));
+    let (status, _) = runner
+        .execute_command("dnf5", &["--refresh", "check-upgrade"], true)
+        .await?;
+    match status.code() {
+        Some(0) => {}
+        Some(EXIT_UPDATES_AVAILABLE) => {
+            return Err(anyhow::anyhow!(
+                "Updates are pending for Fedora {}. Run `fedora-updater update` and reboot before upgrading the release",
+                current
+            ));
+        }
+        _ => return Err(anyhow::anyhow!("DNF5 update check failed")),
+    }
+
+    runner
+        .reporter()
+        .progress(&format!("Downloading Fedora {}...", target));
+    let releasever = format!("--releasever={}", target);
+    let (status, output) =
+        run_dnf5(runner, &["system-upgrade", "download", &releasever, "-y"]).await?;
+
+    if !status.success() {
+        let problems = parse_download_problems(&output);
+        let reporter = runner.reporter();
+        if !problems.repositories.is_empty() {
+            reporter.error(&format!(
+                "These repositories have no packages for Fedora {} yet: {}",
+                target,
+                problems.repositories.join(", ")
+            ));
+            reporter
+                .info("Disable them with --setopt=<repo>.enabled=0 or wait for them to catch up.");
+        }
+        for problem in &problems.dependencies {
+            reporter.error(&format!("Dependency problem: {}", problem));
+        }
+        if !problems.dependencies.is_empty() {
+            reporter.info(
+                "Removing the packages involved, or upgrading with --allowerasing, may help.",
+            );
+        }
+        return Err(anyhow::anyhow!(
+            "Downloading the Fedora {} upgrade failed",
+            target
+        ));
+    }
+
+    Ok(target)
+}
+
+/// Reboots into the downloaded release to install it
+pub(crate) async fn reboot(runner: &mut CommandRunner) -> Result<()> {
+    let (status, _) = runner
+        .execute_command("dnf5", &["system-upgrade", "reboot"], true)
+        .await?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("dnf5 system-upgrade reboot failed"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{OutputFormat, Reporter};
+    use crate::process::fake::ScriptedExecutor;
+    use std::sync::Arc;
+
+    const OS_RELEASE: &str = include_str!("../tests/fixtures/containers/os-release-fedora.txt");
+    const DOWNLOAD_PROBLEMS: &str =
+        include_str!("../tests/fixtures/dnf5/system-upgrade-download-problems.txt");
+
+    fn runner(executor: &Arc<ScriptedExecutor>) -> CommandRunner {
+        CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone())
+    }
+
+    #[test]
+    fn reads_release_number() {
+        assert_eq!(parse_version_id(OS_RELEASE), Some(41));
+        assert_eq!(parse_version_id("VERSION_ID=\"41\"\n"), Some(41));
+        assert_eq!(parse_version_id("ID=fedora\n"), None);
+    }
+
+    #[test]
+    fn collects_repositories_and_dependency_problems() {
+        let problems = parse_download_problems(DOWNLOAD_PROBLEMS);
+
+        assert_eq!(
+            problems.repositories,
+            ["google-chrome", "copr:example:tools"]
+        );
+        assert_eq!(problems.dependencies.len(), 1);
+        assert!(problems.dependencies[0].starts_with("package legacy-tool-1.2-3.fc41.x86_64"));
+    }
+
+    #[tokio::test]
+    async fn refuses_to_upgrade_with_pending_updates() {
+        let executor = Arc::new(
+            ScriptedExecutor::new(&["cat", "dnf5"])
+                .expect("cat /etc/os-release", 0, OS_RELEASE)
+                .expect("sudo dnf5 --refresh check-upgrade", 100, ""),
+        );
+
+        let error = prepare(&mut runner(&executor), None).await.unwrap_err();
+
+        assert!(
+            error
+                .to_string()
+                .starts_with("Updates are pending for Fedora 41")
+        );
+        executor.assert_done();
+    }
+
+    #[tokio::test]
+    async fn downloads_the_next_release() {
+        let executor = Arc::new(
+            ScriptedExecutor::new(&["cat", "dnf5"])
+                .expect("cat /etc/os-release", 0, OS_RELEASE)
+                .expect("sudo dnf5 --refresh check-upgrade", 0, "")
+                .expect(
+                    "sudo env LC_ALL=C dnf5 system-upgrade download --releasever=42 -y",
+                    0,
+                    "Complete!\n",
+                ),
+        );
+
+        assert_eq!(prepare(&mut runner(&executor), None).await.unwrap(), 42);
+        executor.assert_done();
+    }
+}
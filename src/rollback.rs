@@ -0,0 +1,470 @@
+//! Undoing the last update run with `dnf5 history undo` and Flatpak's previous commits
+
+use crate::backend::{ItemOutcome, ItemReport, OutcomeKind, RebootRequirement, resolve_undo};
+use crate::history::{self, BackendRecord, RunRecord, RunStatus};
+use crate::output::Event;
+use crate::runner::CommandRunner;
+use anyhow::Result;
+use chrono::Local;
+use colored::*;
+use serde::{Deserialize, Serialize};
+
+/// A change made by an update run and how to revert it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum UndoStep {
+    /// A DNF5 transaction, reverted with `dnf5 history undo`
+    Dnf5Transaction { id: u64 },
+    /// An updated Flatpak ref, reverted by deploying the commit it had before
+    FlatpakCommit {
+        /// Option selecting the installation, such as `--user`
+        installation: String,
+        /// Ref in `ID//BRANCH` form
+        reference: String,
+        commit: String,
+    },
+}
+
+impl UndoStep {
+    /// Backend that made the change
+    fn backend(&self) -> &'static str {
+        match self {
+            Self::Dnf5Transaction { .. } => "DNF5",
+            Self::FlatpakCommit { .. } => "Flatpak",
+        }
+    }
+
+    /// Short description used in the preview and the history
+    fn describe(&self) -> String {
+        match self {
+            Self::Dnf5Transaction { id } => format!("DNF5 transaction {}", id),
+            Self::FlatpakCommit {
+                installation,
+                reference,
+                commit,
+            } => format!(
+                "{} ({}) back to commit {}",
+                reference,
+                installation.trim_start_matches("--"),
+                &commit[..commit.len().min(12)]
+            ),
+        }
+    }
+
+    /// Reverts the change
+    async fn run(&self, runner: &mut CommandRunner) -> Result<()> {
+        match self {
+            Self::Dnf5Transaction { id } => {
+                let id = id.to_string();
+                let (status, _) = runner
+                    .execute_command("dnf5", &["history", "undo", &id, "-y"], true)
+                    .await?;
+                if !status.success() {
+                    return Err(anyhow::anyhow!("dnf5 history undo {} failed", id));
+                }
+            }
+            Self::FlatpakCommit {
+                installation,
+                reference,
+                commit,
+            } => {
+                let commit = format!("--commit={}", commit);
+                let (status, _) = runner
+                    .execute_command(
+                        "flatpak",
+                        &["update", installation, "-y", &commit, reference],
+                        false,
+                    )
+                    .await?;
+                if !status.success() {
+                    return Err(anyhow::anyhow!(
+                        "Could not deploy the previous commit of {}",
+                        reference
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `rollback` is a rollback of `run` that undid every step
+fn completes(rollback: &RunRecord, run: &RunRecord) -> bool {
+    rollback.rolled_back == Some(run.id) && rollback.status == RunStatus::Success
+}
+
+/// Finds the newest run that applied updates, refusing one that was already rolled back
+///
+/// A rollback that failed or only partly succeeded does not count, so it can be retried.
+pub(crate) fn last_run(runs: &[RunRecord]) -> Result<&RunRecord> {
+    let run = runs
+        .iter()
+        .rev()
+        .filter(|run| !run.dry_run && run.rolled_back.is_none())
+        .find(|run| {
+            run.backends
+                .iter()
+                .any(|backend| backend.outcome == OutcomeKind::Updated)
+        })
+        .ok_or_else(|| anyhow::anyhow!("No update run has applied updates yet"))?;
+
+    if runs.iter().any(|later| completes(later, run)) {
+        return Err(anyhow::anyhow!(
+            "Update run {} was already rolled back",
+            run.id
+        ));
+    }
+    Ok(run)
+}
+
+/// Changes recorded by a run, in the order they are undone
+///
+/// DNF5 transactions are undone newest first so each undo applies to the
+/// state the next one left behind.
+pub(crate) fn undo_steps(run: &RunRecord) -> Vec<UndoStep> {
+    let recorded: Vec<&UndoStep> = run
+        .backends
+        .iter()
+        .flat_map(|backend| &backend.undo)
+        .collect();
+
+    let mut transactions: Vec<u64> = recorded
+        .iter()
+        .filter_map(|step| match step {
+            UndoStep::Dnf5Transaction { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    transactions.sort_unstable_by(|a, b| b.cmp(a));
+
+    transactions
+        .into_iter()
+        .map(|id| UndoStep::Dnf5Transaction { id })
+        .chain(
+            recorded
+                .into_iter()
+                .filter(|step| !matches!(step, UndoStep::Dnf5Transaction { .. }))
+                .cloned(),
+        )
+        .collect()
+}
+
+/// Steps of `run` that earlier, partly failed rollbacks have not undone yet
+pub(crate) fn remaining_steps(runs: &[RunRecord], run: &RunRecord) -> Vec<UndoStep> {
+    let undone: Vec<&str> = runs
+        .iter()
+        .filter(|rollback| rollback.rolled_back == Some(run.id))
+        .flat_map(|rollback| &rollback.backends)
+        .flat_map(|backend| &backend.items)
+        .filter(|item| item.outcome == ItemOutcome::RolledBack)
+        .map(|item| item.name.as_str())
+        .collect();
+
+    undo_steps(run)
+        .into_iter()
+        .filter(|step| !undone.contains(&step.describe().as_str()))
+        .collect()
+}
+
+/// Shows what undoing the steps would change without changing anything
+///
+/// Fails when DNF5 cannot resolve one of the undos, so nothing is attempted.
+async fn preview(runner: &mut CommandRunner, run: &RunRecord, steps: &[UndoStep]) -> Result<()> {
+    runner.reporter().emit(Event::Rollback {
+        run: run.id,
+        steps: steps.to_vec(),
+        performed: false,
+    });
+    if runner.reporter().is_text() {
+        println!(
+            "\n{}",
+            format!(
+                "Rolling back update run {} from {}:",
+                run.id,
+                run.started_at.format("%Y-%m-%d %H:%M:%S")
+            )
+            .blue()
+            .bold()
+        );
+        for step in steps {
+            println!("  {}", step.describe());
+        }
+    }
+
+    for step in steps {
+        if let UndoStep::Dnf5Transaction { id } = step {
+            runner
+                .reporter()
+                .progress(&format!("Resolving the undo of DNF5 transaction {}...", id));
+            let transaction = resolve_undo(runner, *id).await?;
+            runner.reporter().info(&format!(
+                "Undoing DNF5 transaction {} would {}",
+                id,
+                transaction.describe()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Previews and, once confirmed, undoes the last update run, recording the rollback
+///
+/// Returns whether anything was rolled back.
+pub(crate) async fn undo_last(runner: &mut CommandRunner, confirmed: bool) -> Result<bool> {
+    let started_at = Local::now();
+    runner.take_command_log();
+
+    let runs = history::load()?;
+    let run = last_run(&runs)?;
+    let steps = remaining_steps(&runs, run);
+    if steps.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Update run {} recorded nothing that can be undone. Offline and rpm-ostree updates are rolled back by booting the previous deployment or snapshot",
+            run.id
+        ));
+    }
+
+    preview(runner, run, &steps).await?;
+    if !confirmed {
+        if runner.non_interactive() {
+            return Err(anyhow::anyhow!(
+                "Refusing to roll back without confirmation; pass --yes to roll back non-interactively"
+            ));
+        }
+        if !runner
+            .reporter()
+            .confirm("Roll back these changes?", false)?
+        {
+            return Ok(false);
+        }
+    }
+
+    // One failed step does not stop the others
+    let mut backends: Vec<BackendRecord> = Vec::new();
+    for step in &steps {
+        let result = step.run(runner).await;
+        if let Err(e) = &result {
+            runner
+                .reporter()
+                .error(&format!("Rolling back {} failed: {:#}", step.describe(), e));
+        }
+
+        let item = ItemReport {
+            name: step.describe(),
+            outcome: if result.is_ok() {
+                ItemOutcome::RolledBack
+            } else {
+                ItemOutcome::Failed
+            },
+            error: result.err().map(|e| format!("{:#}", e)),
+            detail: None,
+        };
+        match backends
+            .iter_mut()
+            .find(|backend| backend.name == step.backend())
+        {
+            Some(backend) => backend.items.push(item),
+            None => backends.push(BackendRecord {
+                name: step.backend().to_string(),
+                outcome: OutcomeKind::RolledBack,
+                error: None,
+                packages: Vec::new(),
+                items: vec![item],
+                notes: Vec::new(),
+                snapshots: Vec::new(),
+                undo: Vec::new(),
+            }),
+        }
+    }
+    for backend in &mut backends {
+        let failed = backend
+            .items
+            .iter()
+            .any(|item| item.outcome == ItemOutcome::Failed);
+        if failed {
+            backend.outcome = OutcomeKind::Failed;
+        }
+    }
+
+    let failed = backends
+        .iter()
+        .filter(|b| b.outcome == OutcomeKind::Failed)
+        .count();
+    let status = match failed {
+        0 => RunStatus::Success,
+        n if n == backends.len() => RunStatus::Failed,
+        _ => RunStatus::Partial,
+    };
+    let mut record = RunRecord::new(
+        started_at,
+        false,
+        status,
+        &[],
+        runner.take_command_log(),
+        &RebootRequirement::None,
+    );
+    record.backends = backends;
+    record.rolled_back = Some(run.id);
+    if let Err(e) = history::append(record) {
+        runner.reporter().warn(&format!(
+            "Failed to record the rollback in the update history: {:#}",
+            e
+        ));
+    }
+
+    runner.reporter().emit(Event::Rollback {
+        run: run.id,
+        steps,
+        performed: true,
+    });
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "Rolling back update run {} did not fully succeed",
+            run.id
+        ));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(id: u64, outcome: OutcomeKind, undo: Vec<UndoStep>) -> RunRecord {
+        let mut run = RunRecord::new(
+            Local::now(),
+            false,
+            RunStatus::Success,
+            &[],
+            Vec::new(),
+            &RebootRequirement::None,
+        );
+        run.id = id;
+        run.backends = vec![BackendRecord {
+            name: "DNF5".to_string(),
+            outcome,
+            error: None,
+            packages: Vec::new(),
+            items: Vec::new(),
+            notes: Vec::new(),
+            snapshots: Vec::new(),
+            undo,
+        }];
+        run
+    }
+
+    #[test]
+    fn picks_the_newest_run_that_applied_updates() {
+        let firefox = UndoStep::FlatpakCommit {
+            installation: "--user".to_string(),
+            reference: "org.mozilla.firefox//stable".to_string(),
+            commit: "9f3c1e7a2b5d8f0c4e6a".to_string(),
+        };
+        let mut updated = run(
+            1,
+            OutcomeKind::Updated,
+            vec![
+                firefox.clone(),
+                UndoStep::Dnf5Transaction { id: 13 },
+                UndoStep::Dnf5Transaction { id: 14 },
+            ],
+        );
+        updated.backends[0].name = "Flatpak".to_string();
+        let runs = vec![updated, run(2, OutcomeKind::UpToDate, Vec::new())];
+
+        let last = last_run(&runs).unwrap();
+        assert_eq!(last.id, 1);
+        assert_eq!(
+            undo_steps(last),
+            [
+                UndoStep::Dnf5Transaction { id: 14 },
+                UndoStep::Dnf5Transaction { id: 13 },
+                firefox.clone()
+            ]
+        );
+        assert_eq!(
+            firefox.describe(),
+            "org.mozilla.firefox//stable (user) back to commit 9f3c1e7a2b5d"
+        );
+
+        // A rollback is never rolled back, and a rolled back run is not undone twice
+        let mut rollback = run(3, OutcomeKind::RolledBack, Vec::new());
+        rollback.rolled_back = Some(1);
+        let runs = [runs, vec![rollback]].concat();
+        assert_eq!(
+            last_run(&runs).unwrap_err().to_string(),
+            "Update run 1 was already rolled back"
+        );
+    }
+
+    #[test]
+    fn retries_what_a_failed_rollback_left() {
+        let firefox = UndoStep::FlatpakCommit {
+            installation: "--system".to_string(),
+            reference: "org.mozilla.firefox//stable".to_string(),
+            commit: "9f3c1e7a2b5d8f0c4e6a".to_string(),
+        };
+        let transaction = UndoStep::Dnf5Transaction { id: 14 };
+        let updated = run(
+            1,
+            OutcomeKind::Updated,
+            vec![transaction.clone(), firefox.clone()],
+        );
+
+        // The Flatpak ref went back, but the older packages were gone from the repositories
+        let mut rollback = run(2, OutcomeKind::Failed, Vec::new());
+        rollback.status = RunStatus::Partial;
+        rollback.rolled_back = Some(1);
+        rollback.backends[0].items = vec![
+            ItemReport {
+                name: transaction.describe(),
+                outcome: ItemOutcome::Failed,
+                error: Some("dnf5 history undo 14 failed".to_string()),
+                detail: None,
+            },
+            ItemReport {
+                name: firefox.describe(),
+                outcome: ItemOutcome::RolledBack,
+                error: None,
+                detail: None,
+            },
+        ];
+        let runs = vec![updated, rollback];
+
+        let last = last_run(&runs).unwrap();
+        assert_eq!(last.id, 1);
+        assert_eq!(remaining_steps(&runs, last), [transaction]);
+    }
+
+    #[tokio::test]
+    async fn stops_before_confirmation_when_dnf5_cannot_undo() {
+        use crate::output::{OutputFormat, Reporter};
+        use crate::process::fake::ScriptedExecutor;
+        use std::sync::Arc;
+
+        let executor = Arc::new(ScriptedExecutor::new(&["dnf5"]).expect(
+            "sudo env LC_ALL=C dnf5 history undo 14 --assumeno",
+            1,
+            "Failed to resolve the transaction:\n\
+             Cannot find rpm nevra \"firefox-133.0.3-1.fc41.x86_64\".\n",
+        ));
+        let mut runner =
+            CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone());
+        let steps = [UndoStep::Dnf5Transaction { id: 14 }];
+
+        let error = preview(
+            &mut runner,
+            &run(1, OutcomeKind::Updated, steps.to_vec()),
+            &steps,
+        )
+        .await
+        .unwrap_err();
+
+        executor.assert_done();
+        assert!(
+            error
+                .to_string()
+                .starts_with("DNF5 cannot undo transaction 14")
+        );
+    }
+}
@@ -0,0 +1,784 @@
+use crate::history::CommandRecord;
+use crate::output::{Event, OutputSource, Reporter};
+use crate::process::{OutputStream, ProcessExecutor, SystemExecutor};
+use anyhow::{Context, Result};
+use colored::*;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::{Mutex, mpsc};
+
+/// Pre-allocated buffer capacity for command output strings
+const DEFAULT_OUTPUT_CAPACITY: usize = 16384;
+const DEFAULT_CHANNEL_CAPACITY: usize = 200;
+const DEFAULT_LINE_CAPACITY: usize = 256;
+const STRING_POOL_SIZE: usize = 32;
+
+/// Commands whose availability is tracked by `CommandCache`
+const KNOWN_COMMANDS: [&str; 14] = [
+    "flatpak",
+    "dnf5",
+    "rpm-ostree",
+    "fwupdmgr",
+    "toolbox",
+    "distrobox",
+    "rpm",
+    "cat",
+    "uname",
+    "systemctl",
+    "nmcli",
+    "df",
+    "snapper",
+    "findmnt",
+];
+
+/// Subcommands that only read system state, allowed in dry-run mode
+const READ_ONLY_SUBCOMMANDS: [(&str, &str); 6] = [
+    ("dnf5", "check-upgrade"),
+    ("dnf5", "needs-restarting"),
+    ("dnf5", "advisory"),
+    ("flatpak", "remote-ls"),
+    ("flatpak", "list"),
+    ("rpm-ostree", "status"),
+];
+
+/// Subcommands that only resolve what they would do when given the flag, allowed in dry-run mode
+const RESOLVE_ONLY_SUBCOMMANDS: [(&str, &[&str], &str); 3] = [
+    ("dnf5", &["upgrade"], "--assumeno"),
+    ("dnf5", &["history", "undo"], "--assumeno"),
+    ("rpm-ostree", &["upgrade"], "--check"),
+];
+
+/// Returns true if running the command cannot change the system
+///
+/// Commands run through `env VAR=value` are judged by the program `env` starts.
+/// A resolve-only flag only counts when nothing on the command line asks to
+/// go ahead anyway, such as `-y`.
+fn is_read_only(command: &str, args: &[&str]) -> bool {
+    let (command, args) = match command {
+        "env" => {
+            let mut rest = args.iter().skip_while(|arg| arg.contains('='));
+            match rest.next() {
+                Some(command) => (*command, rest.copied().collect::<Vec<_>>()),
+                None => return false,
+            }
+        }
+        _ => (command, args.to_vec()),
+    };
+
+    let words: Vec<&str> = args
+        .iter()
+        .copied()
+        .filter(|arg| !arg.starts_with('-'))
+        .collect();
+    if words
+        .first()
+        .is_some_and(|subcommand| READ_ONLY_SUBCOMMANDS.contains(&(command, *subcommand)))
+    {
+        return true;
+    }
+
+    let confirmed = args
+        .iter()
+        .any(|arg| matches!(*arg, "-y" | "--assumeyes" | "--apply-only" | "--reboot"));
+    !confirmed
+        && RESOLVE_ONLY_SUBCOMMANDS
+            .iter()
+            .any(|(program, subcommand, flag)| {
+                *program == command && words.starts_with(subcommand) && args.contains(flag)
+            })
+}
+
+/// Struct to manage command availability caching
+#[derive(Debug)]
+struct CommandCache {
+    // Use a static array of known commands to avoid heap allocations
+    // This acts as a simple string interning mechanism
+    known_commands: [&'static str; KNOWN_COMMANDS.len()],
+    // Store availability as a fixed-size array matching known_commands
+    availability: [Option<bool>; KNOWN_COMMANDS.len()],
+    // Runs the `which` lookups and captured commands
+    executor: Arc<dyn ProcessExecutor>,
+}
+
+impl CommandCache {
+    fn new(executor: Arc<dyn ProcessExecutor>) -> Self {
+        Self {
+            known_commands: KNOWN_COMMANDS,
+            availability: [None; KNOWN_COMMANDS.len()],
+            executor,
+        }
+    }
+
+    /// Preloads availability of commonly used commands
+    /// Call this at startup to avoid async overhead during actual operations
+    async fn preload_common_commands(&mut self) {
+        // Check commands concurrently with minimum allocations
+        let mut handles = Vec::with_capacity(self.known_commands.len());
+
+        for (idx, &cmd) in self.known_commands.iter().enumerate() {
+            let executor = self.executor.clone();
+            // Spawn a task for each command
+            let handle = tokio::spawn(async move {
+                let available = executor
+                    .output("which", &[cmd]) // Pass &str directly
+                    .await
+                    .map(|output| output.status.success())
+                    .unwrap_or(false);
+                (idx, available)
+            });
+
+            handles.push(handle);
+        }
+
+        // Await all tasks and collect results
+        for handle in handles {
+            if let Ok((idx, available)) = handle.await {
+                // Store result in our fixed-size array - no heap allocation
+                self.availability[idx] = Some(available);
+            }
+        }
+    }
+
+    /// Checks if a command is cached as available
+    fn is_cached_available(&self, command: &str) -> Option<bool> {
+        // Check our static array - this is very fast
+        for (idx, &cmd) in self.known_commands.iter().enumerate() {
+            if cmd == command {
+                return self.availability[idx];
+            }
+        }
+
+        // Command not in our known list
+        None
+    }
+
+    /// Gets the availability of a command, returning immediately if cached
+    /// This is a convenience method to avoid needing .await when we already know the result
+    /// Returns None if the result isn't cached yet
+    fn get_cached_availability(&self, command: &str) -> Option<bool> {
+        self.is_cached_available(command)
+    }
+
+    /// Checks if a command is available
+    /// Returns immediately with cached result if available
+    async fn is_command_available(&mut self, command: &str) -> bool {
+        // Fast path: return cached result if available
+        if let Some(available) = self.is_cached_available(command) {
+            return available;
+        }
+
+        // Slow path: check command availability and cache the result
+        let available = self
+            .executor
+            .output("which", &[command])
+            .await
+            .map(|output| output.status.success())
+            .unwrap_or(false);
+
+        // Check if this is one of our known commands
+        for (idx, &cmd) in self.known_commands.iter().enumerate() {
+            if cmd == command {
+                self.availability[idx] = Some(available);
+                return available;
+            }
+        }
+
+        // Command not in our known list - return false as we don't support it
+        false
+    }
+
+    /// Executes a command if it's available, returns None if command is not available
+    async fn execute_if_available(
+        &mut self,
+        command: &str,
+        args: &[&str],
+    ) -> Option<std::process::Output> {
+        // Fast path: if we already know the command is unavailable, return None immediately
+        if let Some(false) = self.is_cached_available(command) {
+            return None;
+        }
+
+        // Check if command is available (uses cache if possible)
+        if !self.is_command_available(command).await {
+            return None;
+        }
+
+        self.executor.output(command, args).await.ok()
+    }
+}
+
+/// A string buffer that can be reused to avoid allocations
+#[derive(Debug)]
+struct StringBuffer {
+    buffer: String,
+}
+
+impl StringBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            buffer: String::with_capacity(capacity),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    fn as_str(&self) -> &str {
+        &self.buffer
+    }
+}
+
+/// A pool of string buffers that can be reused
+#[derive(Debug)]
+struct StringBufferPool {
+    buffers: Vec<StringBuffer>,
+}
+
+impl StringBufferPool {
+    fn new(size: usize, buffer_capacity: usize) -> Self {
+        let mut buffers = Vec::with_capacity(size);
+        for _ in 0..size {
+            buffers.push(StringBuffer::new(buffer_capacity));
+        }
+        Self { buffers }
+    }
+
+    fn get(&mut self) -> StringBuffer {
+        self.buffers
+            .pop()
+            .unwrap_or_else(|| StringBuffer::new(DEFAULT_LINE_CAPACITY))
+    }
+
+    fn return_buffer(&mut self, mut buffer: StringBuffer) {
+        buffer.clear();
+        if self.buffers.len() < STRING_POOL_SIZE {
+            self.buffers.push(buffer);
+        }
+    }
+}
+
+/// Struct to manage output streams and handle line-by-line output
+#[derive(Debug)]
+pub(crate) struct CommandRunner {
+    cmd_cache: CommandCache,
+    // Starts every process, so tests can substitute a scripted fake
+    executor: Arc<dyn ProcessExecutor>,
+    // Pre-allocated buffer for command output, reused across commands
+    output_buffer: String,
+    reporter: Reporter,
+    // When set, privileged commands that could change the system are refused
+    dry_run: bool,
+    // When set, sudo fails instead of asking for a password
+    non_interactive: bool,
+    // Every command executed so far, recorded in the update history
+    command_log: Vec<CommandRecord>,
+}
+
+impl CommandRunner {
+    /// Creates a new CommandRunner with pre-allocated resources
+    pub(crate) fn new(reporter: Reporter) -> Self {
+        Self::with_executor(reporter, Arc::new(SystemExecutor))
+    }
+
+    /// Creates a CommandRunner that starts processes through `executor`
+    pub(crate) fn with_executor(reporter: Reporter, executor: Arc<dyn ProcessExecutor>) -> Self {
+        Self {
+            cmd_cache: CommandCache::new(executor.clone()),
+            executor,
+            output_buffer: String::with_capacity(DEFAULT_OUTPUT_CAPACITY),
+            reporter,
+            dry_run: false,
+            non_interactive: false,
+            command_log: Vec::new(),
+        }
+    }
+
+    /// Enables or disables dry-run mode
+    pub(crate) fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Makes sudo fail rather than prompt, for runs without a terminal
+    pub(crate) fn set_non_interactive(&mut self, non_interactive: bool) {
+        self.non_interactive = non_interactive;
+    }
+
+    /// Whether this run must not change the system
+    pub(crate) fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Whether this run must not wait for the user
+    pub(crate) fn non_interactive(&self) -> bool {
+        self.non_interactive
+    }
+
+    /// Returns the commands executed since the last call, oldest first
+    pub(crate) fn take_command_log(&mut self) -> Vec<CommandRecord> {
+        std::mem::take(&mut self.command_log)
+    }
+
+    /// Reporter used for all messages and events of this run
+    pub(crate) fn reporter(&self) -> &Reporter {
+        &self.reporter
+    }
+
+    /// Preloads common commands into the cache
+    pub(crate) async fn preload_common_commands(&mut self) {
+        self.cmd_cache.preload_common_commands().await;
+    }
+
+    /// Checks whether a command is installed, preferring the cached result
+    pub(crate) async fn is_available(&mut self, command: &str) -> bool {
+        // Try to use cached result first to avoid async overhead
+        match self.cmd_cache.get_cached_availability(command) {
+            Some(available) => available,
+            None => self.cmd_cache.is_command_available(command).await,
+        }
+    }
+
+    /// Runs a command without streaming its output, for quiet lookups
+    /// Returns None if the command is not available or failed to spawn
+    pub(crate) async fn capture_command(
+        &mut self,
+        command: &str,
+        args: &[&str],
+    ) -> Option<std::process::Output> {
+        if !self.is_available(command).await {
+            return None;
+        }
+
+        // Log the command that's about to be executed
+        self.log_command_execution(command, args, false);
+
+        let output = self.cmd_cache.execute_if_available(command, args).await;
+        if let Some(output) = &output {
+            self.log_command_exit(command, output.status);
+        }
+        output
+    }
+
+    /// Executes a command and streams its output in real-time
+    pub(crate) async fn execute_command(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        sudo: bool,
+    ) -> Result<(std::process::ExitStatus, &str)> {
+        // Never let a dry run run anything that changes the system, with or without sudo
+        if self.dry_run && !is_read_only(command, args) {
+            return Err(anyhow::anyhow!(
+                "Refusing to run '{}{} {}' in dry-run mode",
+                if sudo { "sudo " } else { "" },
+                command,
+                args.join(" ")
+            ));
+        }
+
+        // Clear the buffer before reusing
+        self.output_buffer.clear();
+
+        // Log the command execution
+        self.log_command_execution(command, args, sudo);
+
+        // Setup command and streams
+        let process = self.setup_command_streams(command, args, sudo).await?;
+
+        // Create readers for stdout and stderr
+        let stdout_reader = BufReader::new(process.stdout).lines();
+        let stderr_reader = BufReader::new(process.stderr).lines();
+
+        // Process command output
+        self.process_command_output(stdout_reader, stderr_reader)
+            .await?;
+
+        // Wait for the command to complete
+        let status = process.status.await?;
+        self.log_command_exit(command, status);
+
+        // Return a reference to our buffer to avoid cloning
+        Ok((status, &self.output_buffer))
+    }
+
+    /// Logs the command that's about to be executed
+    fn log_command_execution(&mut self, command: &str, args: &[&str], sudo: bool) {
+        self.command_log.push(CommandRecord {
+            program: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            sudo,
+            exit_code: None,
+        });
+
+        if !self.reporter.is_text() {
+            self.reporter.emit(Event::Command {
+                program: command.to_string(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+                sudo,
+            });
+            return;
+        }
+
+        // Avoid string allocation by building command display directly
+        print!("{} ", "Executing command:".cyan().bold());
+        if sudo {
+            print!("{} ", "sudo".cyan());
+        }
+        print!("{} ", command.cyan());
+
+        // Print arguments directly to avoid join allocation
+        for arg in args {
+            print!("{} ", arg.cyan());
+        }
+        println!();
+    }
+
+    /// Records the exit status of a finished command in the log and machine-readable output
+    fn log_command_exit(&mut self, command: &str, status: std::process::ExitStatus) {
+        if let Some(record) = self.command_log.last_mut() {
+            record.exit_code = status.code();
+        }
+
+        self.reporter.emit(Event::CommandExit {
+            program: command.to_string(),
+            exit_code: status.code(),
+        });
+    }
+
+    /// Sets up the command and its input/output streams
+    async fn setup_command_streams(
+        &self,
+        command: &str,
+        args: &[&str],
+        sudo: bool,
+    ) -> Result<crate::process::SpawnedProcess> {
+        let process = if sudo {
+            let mut sudo_args = Vec::with_capacity(args.len() + 2);
+            if self.non_interactive {
+                sudo_args.push("-n");
+            }
+            sudo_args.push(command);
+            sudo_args.extend_from_slice(args);
+            self.executor.spawn("sudo", &sudo_args).await
+        } else {
+            self.executor.spawn(command, args).await
+        };
+
+        // Stdout and stderr are piped by the executor
+        process.with_context(|| format!("Failed to execute {} command", command))
+    }
+
+    /// Processes the command output streams
+    async fn process_command_output(
+        &mut self,
+        stdout_reader: tokio::io::Lines<BufReader<OutputStream>>,
+        stderr_reader: tokio::io::Lines<BufReader<OutputStream>>,
+    ) -> Result<Arc<Mutex<StringBufferPool>>> {
+        // Create a shared buffer pool for output lines
+        let buffer_pool = Arc::new(Mutex::new(StringBufferPool::new(
+            STRING_POOL_SIZE,
+            DEFAULT_LINE_CAPACITY,
+        )));
+
+        // Create a channel for output handling
+        let (tx, rx) = mpsc::channel(DEFAULT_CHANNEL_CAPACITY);
+        let output_handler_task = tokio::spawn(output_handler(
+            rx,
+            buffer_pool.clone(),
+            self.reporter.clone(),
+        ));
+
+        // Use a channel for accumulating output - now using StringBuffer instead of String
+        let (line_tx, line_rx) = mpsc::channel(DEFAULT_CHANNEL_CAPACITY);
+
+        // Process stdout and stderr
+        let (stdout_task, stderr_task) = self.spawn_output_processing_tasks(
+            stdout_reader,
+            stderr_reader,
+            tx.clone(),
+            line_tx.clone(),
+            buffer_pool.clone(),
+        );
+
+        // Close senders to signal completion
+        drop(tx);
+        drop(line_tx);
+
+        // Wait for output handling to complete
+        let _ = tokio::try_join!(stdout_task, stderr_task)
+            .context("Failed to join stdout/stderr tasks")?;
+        output_handler_task
+            .await
+            .context("Failed to join output handler task")?;
+
+        // Collect output lines from channel into our pre-allocated buffer
+        self.collect_output_lines(line_rx, buffer_pool.clone())
+            .await;
+
+        // Return the buffer pool for potential reuse
+        Ok(buffer_pool)
+    }
+
+    /// Spawns tasks to process stdout and stderr streams
+    fn spawn_output_processing_tasks(
+        &self,
+        stdout_reader: tokio::io::Lines<BufReader<OutputStream>>,
+        stderr_reader: tokio::io::Lines<BufReader<OutputStream>>,
+        tx: mpsc::Sender<(OutputSource, StringBuffer)>,
+        line_tx: mpsc::Sender<StringBuffer>,
+        buffer_pool: Arc<Mutex<StringBufferPool>>,
+    ) -> (tokio::task::JoinHandle<()>, tokio::task::JoinHandle<()>) {
+        // Create separate clones of the sender for each task
+        let tx_stdout = tx.clone();
+        let line_tx_clone = line_tx.clone();
+        let stdout_pool = buffer_pool.clone();
+
+        let stdout_task = tokio::spawn(async move {
+            let mut stdout_reader = stdout_reader;
+            while let Ok(Some(line)) = stdout_reader.next_line().await {
+                // Get a buffer from the pool for the output handler
+                let mut pool_guard = stdout_pool.lock().await;
+                let mut output_buffer = pool_guard.get();
+                let mut accum_buffer = pool_guard.get();
+                drop(pool_guard); // Release the lock before further operations
+
+                // Fill both buffers with the same content
+                output_buffer.buffer.push_str(&line);
+                accum_buffer.buffer.push_str(&line);
+
+                // Send buffers to their respective channels
+                let _ = line_tx_clone.send(accum_buffer).await;
+                let _ = tx_stdout.send((OutputSource::Stdout, output_buffer)).await;
+            }
+        });
+
+        let tx_stderr = tx.clone();
+        let stderr_pool = buffer_pool.clone();
+
+        let stderr_task = tokio::spawn(async move {
+            let mut stderr_reader = stderr_reader;
+            while let Ok(Some(line)) = stderr_reader.next_line().await {
+                // Get a buffer from the pool and fill it
+                let mut pool_guard = stderr_pool.lock().await;
+                let mut buffer = pool_guard.get();
+                drop(pool_guard); // Release the lock before further operations
+
+                buffer.buffer.push_str(&line);
+
+                // Send the buffer to the output handler
+                let _ = tx_stderr.send((OutputSource::Stderr, buffer)).await;
+            }
+        });
+
+        (stdout_task, stderr_task)
+    }
+
+    /// Collects output lines from the channel into the pre-allocated buffer
+    async fn collect_output_lines(
+        &mut self,
+        mut line_rx: mpsc::Receiver<StringBuffer>,
+        buffer_pool: Arc<Mutex<StringBufferPool>>,
+    ) {
+        while let Some(buffer) = line_rx.recv().await {
+            self.output_buffer.push_str(buffer.as_str());
+            self.output_buffer.push('\n');
+
+            // Return the buffer to the pool
+            let mut pool = buffer_pool.lock().await;
+            pool.return_buffer(buffer);
+        }
+    }
+
+    /// Displays system information
+    pub(crate) async fn show_system_info(&mut self) -> Result<()> {
+        // Distribution info
+        let mut distribution = None;
+        if let Ok(output) = self.executor.output("cat", &["/etc/os-release"]).await {
+            let info = String::from_utf8_lossy(&output.stdout);
+            if let Some(line) = info.lines().find(|l| l.starts_with("PRETTY_NAME=")) {
+                distribution = Some(
+                    line.split('=')
+                        .nth(1)
+                        .unwrap_or("Unknown")
+                        .trim_matches('"')
+                        .to_string(),
+                );
+            }
+        }
+
+        // Kernel version
+        let kernel = self
+            .executor
+            .output("uname", &["-r"])
+            .await
+            .ok()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+        // Flatpak version
+        let flatpak = self
+            .capture_command("flatpak", &["--version"])
+            .await
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+        // DNF5 version
+        let dnf5 = self
+            .capture_command("dnf5", &["--version"])
+            .await
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+        // Offline transaction left from an earlier run, so it is not prepared twice
+        let offline_update = crate::offline::status(self)
+            .await
+            .filter(|status| status.state != crate::offline::OfflineState::None);
+
+        if !self.reporter.is_text() {
+            self.reporter.emit(Event::SystemInfo {
+                distribution,
+                kernel,
+                flatpak,
+                dnf5,
+                offline_update,
+            });
+            return Ok(());
+        }
+
+        println!("{}", "System Information:".blue().bold());
+        let fields = [
+            ("Distribution", distribution),
+            ("Kernel", kernel),
+            ("Flatpak", flatpak),
+            ("DNF5", dnf5),
+        ];
+        for (label, value) in fields {
+            if let Some(value) = value {
+                println!("{}: {}", label, value);
+            }
+        }
+        if let Some(description) = offline_update.as_ref().and_then(|status| status.describe()) {
+            println!("Offline update: {}", description.yellow());
+        }
+
+        Ok(())
+    }
+}
+
+/// Handles printing output messages in a serialized manner
+async fn output_handler(
+    mut rx: mpsc::Receiver<(OutputSource, StringBuffer)>,
+    buffer_pool: Arc<Mutex<StringBufferPool>>,
+    reporter: Reporter,
+) {
+    while let Some((source, buffer)) = rx.recv().await {
+        if reporter.is_text() {
+            match source {
+                OutputSource::Stdout => println!("{} {}", "[stdout]".blue(), buffer.as_str()),
+                OutputSource::Stderr => eprintln!("{} {}", "[stderr]".red(), buffer.as_str()),
+            }
+        } else {
+            reporter.emit(Event::Line {
+                source,
+                text: buffer.as_str().to_string(),
+            });
+        }
+
+        // Return the buffer to the pool
+        let mut pool = buffer_pool.lock().await;
+        pool.return_buffer(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_read_only_commands() {
+        assert!(is_read_only("dnf5", &["--refresh", "check-upgrade"]));
+        assert!(is_read_only("dnf5", &["needs-restarting", "-s"]));
+        assert!(is_read_only("dnf5", &["upgrade", "--assumeno"]));
+        assert!(is_read_only(
+            "dnf5",
+            &["history", "undo", "14", "--assumeno"]
+        ));
+        assert!(is_read_only(
+            "env",
+            &["LC_ALL=C", "flatpak", "remote-ls", "--user", "--updates"]
+        ));
+        assert!(is_read_only("rpm-ostree", &["upgrade", "--check"]));
+    }
+
+    #[test]
+    fn treats_transactions_as_mutating() {
+        assert!(!is_read_only("dnf5", &["upgrade", "-y"]));
+        assert!(!is_read_only("dnf5", &["upgrade", "--offline", "-y"]));
+        assert!(!is_read_only("flatpak", &["update", "-y"]));
+        assert!(!is_read_only(
+            "env",
+            &["LC_ALL=C", "flatpak", "update", "-y"]
+        ));
+        assert!(!is_read_only(
+            "env",
+            &["LC_ALL=C", "flatpak", "uninstall", "--unused", "-y"]
+        ));
+        assert!(!is_read_only("fwupdmgr", &["refresh"]));
+        assert!(!is_read_only(
+            "toolbox",
+            &[
+                "run",
+                "--container",
+                "fedora",
+                "sudo",
+                "dnf5",
+                "upgrade",
+                "-y"
+            ]
+        ));
+    }
+
+    #[test]
+    fn accepts_assumeno_only_for_resolving_calls() {
+        assert!(!is_read_only(
+            "dnf5",
+            &["history", "undo", "14", "--assumeno", "-y"]
+        ));
+        assert!(!is_read_only(
+            "dnf5",
+            &["upgrade", "--assumeno", "--assumeyes"]
+        ));
+        assert!(!is_read_only(
+            "dnf5",
+            &["remove", "kernel-core", "--assumeno"]
+        ));
+        assert!(!is_read_only(
+            "dnf5",
+            &["history", "rollback", "14", "--assumeno"]
+        ));
+        assert!(!is_read_only(
+            "rpm-ostree",
+            &["upgrade", "--check", "--reboot"]
+        ));
+    }
+
+    #[tokio::test]
+    async fn dry_run_refuses_changes_without_sudo() {
+        use crate::process::fake::ScriptedExecutor;
+
+        let executor = Arc::new(ScriptedExecutor::new(&["flatpak"]));
+        let mut runner = CommandRunner::with_executor(
+            Reporter::new(crate::output::OutputFormat::Json),
+            executor.clone(),
+        );
+        runner.set_dry_run(true);
+
+        let error = runner
+            .execute_command("env", &["LC_ALL=C", "flatpak", "update", "-y"], false)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Refusing to run 'env LC_ALL=C flatpak update -y' in dry-run mode"
+        );
+        assert!(executor.calls().is_empty());
+    }
+}
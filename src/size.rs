@@ -0,0 +1,93 @@
+//! Parsing and formatting of human-readable sizes printed by DNF5 and Flatpak
+
+/// Units accepted by `parse_size`, with their multiplier in bytes
+const UNITS: [(&str, u64); 11] = [
+    ("B", 1),
+    ("bytes", 1),
+    ("KiB", 1 << 10),
+    ("MiB", 1 << 20),
+    ("GiB", 1 << 30),
+    ("TiB", 1 << 40),
+    ("kB", 1_000),
+    ("KB", 1_000),
+    ("MB", 1_000_000),
+    ("GB", 1_000_000_000),
+    ("TB", 1_000_000_000_000),
+];
+
+/// Parses sizes such as `56 MiB`, `1.2 GB` or `< 300.5 MB` into bytes
+///
+/// Both binary (DNF5) and decimal (Flatpak) units are understood. A leading
+/// `<`, used by Flatpak for upper-bound estimates, is ignored.
+pub(crate) fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim().trim_start_matches('<').trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let number: f64 = number.parse().ok()?;
+    let unit = unit.trim();
+    let multiplier = if unit.is_empty() {
+        1
+    } else {
+        UNITS.iter().find(|(name, _)| *name == unit)?.1
+    };
+
+    Some((number * multiplier as f64).round() as u64)
+}
+
+/// Formats a byte count using binary units, as DNF5 does
+pub(crate) fn format_size(bytes: u64) -> String {
+    const STEPS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for step in STEPS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = step;
+    }
+
+    format!("{:.1} {}", value, unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_binary_and_decimal_units() {
+        assert_eq!(parse_size("56 MiB"), Some(56 * 1024 * 1024));
+        assert_eq!(parse_size("1.5 KiB"), Some(1536));
+        assert_eq!(parse_size("300.5 MB"), Some(300_500_000));
+        assert_eq!(parse_size("12 bytes"), Some(12));
+        assert_eq!(parse_size("0 B"), Some(0));
+    }
+
+    #[test]
+    fn parses_flatpak_estimates() {
+        assert_eq!(parse_size("< 1.2\u{a0}GB"), Some(1_200_000_000));
+        assert_eq!(parse_size("< 64 kB"), Some(64_000));
+    }
+
+    #[test]
+    fn rejects_unknown_units() {
+        assert_eq!(parse_size("12 parsecs"), None);
+        assert_eq!(parse_size("MiB"), None);
+        assert_eq!(parse_size(""), None);
+    }
+
+    #[test]
+    fn formats_binary_units() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(56 * 1024 * 1024), "56.0 MiB");
+    }
+}
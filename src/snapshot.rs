@@ -0,0 +1,553 @@
+//! Btrfs snapshots taken around DNF5 transactions, and rolling back to them
+
+use crate::config::SnapshotConfig;
+use crate::history::RunRecord;
+use crate::output::{Event, Reporter};
+use crate::runner::CommandRunner;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use colored::*;
+use serde::{Deserialize, Serialize};
+
+/// Description attached to snapper snapshots
+const SNAPPER_DESCRIPTION: &str = "fedora-updater";
+
+/// How snapshots are taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SnapshotMethod {
+    /// Snapper when it has a configuration for the root filesystem, btrfs otherwise
+    #[default]
+    Auto,
+    /// Pre and post snapshots in a snapper configuration
+    Snapper,
+    /// Read-only `btrfs subvolume snapshot` of `/`
+    Btrfs,
+}
+
+/// Whether a snapshot was taken before or after the transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SnapshotKind {
+    Pre,
+    Post,
+}
+
+/// A snapshot created by the updater
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    /// `snapper` or `btrfs`, never `auto`
+    pub(crate) method: SnapshotMethod,
+    pub(crate) kind: SnapshotKind,
+    /// Snapper snapshot number, or the path of a btrfs snapshot
+    pub(crate) id: String,
+    /// Snapper configuration holding the snapshot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) config: Option<String>,
+}
+
+/// Lists the configurations in `snapper --csvout list-configs` output
+pub(crate) fn parse_snapper_configs(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split(',').next())
+        .map(|config| config.trim().to_string())
+        .filter(|config| !config.is_empty())
+        .collect()
+}
+
+/// Reads the number printed by `snapper create --print-number`
+fn parse_snapshot_number(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .rev()
+        .find_map(|line| line.trim().parse().ok())
+}
+
+/// Picks snapper or btrfs for `auto`, failing when neither can snapshot `/`
+async fn resolve_method(
+    runner: &mut CommandRunner,
+    config: &SnapshotConfig,
+) -> Result<SnapshotMethod> {
+    if config.method != SnapshotMethod::Auto {
+        return Ok(config.method);
+    }
+
+    if let Some(output) = runner
+        .capture_command("snapper", &["--csvout", "list-configs"])
+        .await
+        && output.status.success()
+        && parse_snapper_configs(&String::from_utf8_lossy(&output.stdout))
+            .contains(&config.snapper_config)
+    {
+        return Ok(SnapshotMethod::Snapper);
+    }
+
+    let filesystem = runner
+        .capture_command("findmnt", &["-n", "-o", "FSTYPE", "/"])
+        .await
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    if filesystem.as_deref() == Some("btrfs") {
+        return Ok(SnapshotMethod::Btrfs);
+    }
+
+    Err(anyhow::anyhow!(
+        "/ is not on Btrfs and snapper has no `{}` configuration",
+        config.snapper_config
+    ))
+}
+
+/// Creates a snapshot of `/`; post snapshots are paired with `pre`
+async fn create(
+    runner: &mut CommandRunner,
+    config: &SnapshotConfig,
+    kind: SnapshotKind,
+    pre: Option<&Snapshot>,
+) -> Result<Snapshot> {
+    let method = match pre {
+        Some(pre) => pre.method,
+        None => resolve_method(runner, config).await?,
+    };
+
+    match method {
+        SnapshotMethod::Snapper => {
+            let mut args = vec![
+                "-c",
+                &config.snapper_config,
+                "create",
+                "--type",
+                match kind {
+                    SnapshotKind::Pre => "pre",
+                    SnapshotKind::Post => "post",
+                },
+            ];
+            if let Some(pre) = pre {
+                args.extend(["--pre-number", &pre.id]);
+            }
+            args.extend([
+                "--cleanup-algorithm",
+                "number",
+                "--print-number",
+                "--description",
+                SNAPPER_DESCRIPTION,
+            ]);
+
+            let (status, output) = runner.execute_command("snapper", &args, true).await?;
+            if !status.success() {
+                return Err(anyhow::anyhow!("snapper create failed"));
+            }
+            let number = parse_snapshot_number(output)
+                .context("snapper did not print the number of the new snapshot")?;
+
+            Ok(Snapshot {
+                method,
+                kind,
+                id: number.to_string(),
+                config: Some(config.snapper_config.clone()),
+            })
+        }
+        _ => {
+            let dir = config.btrfs_dir.display().to_string();
+            let (status, _) = runner.execute_command("mkdir", &["-p", &dir], true).await?;
+            if !status.success() {
+                return Err(anyhow::anyhow!("Could not create {}", dir));
+            }
+
+            let path = config
+                .btrfs_dir
+                .join(format!(
+                    "fedora-updater-{}-{}",
+                    Local::now().format("%Y%m%d-%H%M%S"),
+                    match kind {
+                        SnapshotKind::Pre => "pre",
+                        SnapshotKind::Post => "post",
+                    }
+                ))
+                .display()
+                .to_string();
+            let (status, _) = runner
+                .execute_command("btrfs", &["subvolume", "snapshot", "-r", "/", &path], true)
+                .await?;
+            if !status.success() {
+                return Err(anyhow::anyhow!("btrfs subvolume snapshot failed"));
+            }
+
+            Ok(Snapshot {
+                method: SnapshotMethod::Btrfs,
+                kind,
+                id: path,
+                config: None,
+            })
+        }
+    }
+}
+
+/// Takes the snapshot before a transaction, if snapshots are enabled
+///
+/// Failing to snapshot blocks the transaction unless snapshots are optional.
+pub(crate) async fn before(
+    runner: &mut CommandRunner,
+    config: &SnapshotConfig,
+) -> Result<Option<Snapshot>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    runner
+        .reporter()
+        .progress("Creating a snapshot before updating...");
+    match create(runner, config, SnapshotKind::Pre, None).await {
+        Ok(snapshot) => Ok(Some(snapshot)),
+        Err(e) if config.required => Err(anyhow::anyhow!(
+            "Could not create a snapshot before updating: {:#}. Run with --ignore-snapshot-failure to update without one",
+            e
+        )),
+        Err(e) => {
+            runner.reporter().warn(&format!(
+                "Could not create a snapshot before updating: {:#}. Updating without one.",
+                e
+            ));
+            Ok(None)
+        }
+    }
+}
+
+/// Takes the snapshot after a transaction; the update already succeeded, so failures only warn
+pub(crate) async fn after(
+    runner: &mut CommandRunner,
+    config: &SnapshotConfig,
+    pre: &Snapshot,
+) -> Option<Snapshot> {
+    runner
+        .reporter()
+        .progress("Creating a snapshot after updating...");
+    match create(runner, config, SnapshotKind::Post, Some(pre)).await {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            runner.reporter().warn(&format!(
+                "Could not create a snapshot after updating: {:#}",
+                e
+            ));
+            None
+        }
+    }
+}
+
+/// One-line note naming the snapshots taken around a transaction
+pub(crate) fn describe(snapshots: &[Snapshot]) -> Option<String> {
+    let pre = snapshots.iter().find(|s| s.kind == SnapshotKind::Pre)?;
+    let method = match pre.method {
+        SnapshotMethod::Snapper => "snapper",
+        _ => "btrfs",
+    };
+    Some(
+        match snapshots.iter().find(|s| s.kind == SnapshotKind::Post) {
+            Some(post) => format!(
+                "Created {} snapshot {} before and {} after the update",
+                method, pre.id, post.id
+            ),
+            None => format!("Created {} snapshot {} before the update", method, pre.id),
+        },
+    )
+}
+
+/// Snapshots taken during one recorded update run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct RunSnapshots {
+    pub(crate) run: u64,
+    pub(crate) started_at: DateTime<Local>,
+    pub(crate) pre: Snapshot,
+    pub(crate) post: Option<Snapshot>,
+}
+
+/// Collects the snapshots recorded in the history, newest run first
+pub(crate) fn from_history(runs: &[RunRecord]) -> Vec<RunSnapshots> {
+    runs.iter()
+        .rev()
+        .filter_map(|run| {
+            let snapshots: Vec<&Snapshot> = run
+                .backends
+                .iter()
+                .flat_map(|backend| &backend.snapshots)
+                .collect();
+            let pre = snapshots.iter().find(|s| s.kind == SnapshotKind::Pre)?;
+            Some(RunSnapshots {
+                run: run.id,
+                started_at: run.started_at,
+                pre: (*pre).clone(),
+                post: snapshots
+                    .iter()
+                    .find(|s| s.kind == SnapshotKind::Post)
+                    .map(|post| (*post).clone()),
+            })
+        })
+        .collect()
+}
+
+/// Lists the snapshots the updater created, newest first
+pub(crate) fn list(reporter: &Reporter, snapshots: &[RunSnapshots]) {
+    if !reporter.is_text() {
+        reporter.emit(Event::Snapshots {
+            snapshots: snapshots.to_vec(),
+        });
+        return;
+    }
+
+    if snapshots.is_empty() {
+        println!("No snapshots were taken by past update runs.");
+        reporter.info("Enable them with `update --snapshot` or `enabled = true` in [snapshot].");
+        return;
+    }
+
+    println!(
+        "{}",
+        format!(
+            "{:>4}  {:<19}  {:<7}  {:<8}  After",
+            "Run", "Started", "Method", "Before"
+        )
+        .blue()
+        .bold()
+    );
+    for entry in snapshots {
+        let method = match entry.pre.method {
+            SnapshotMethod::Snapper => "snapper",
+            _ => "btrfs",
+        };
+        println!(
+            "{:>4}  {:<19}  {:<7}  {:<8}  {}",
+            entry.run,
+            entry.started_at.format("%Y-%m-%d %H:%M:%S"),
+            method,
+            entry.pre.id,
+            entry.post.as_ref().map_or("-", |post| post.id.as_str())
+        );
+    }
+}
+
+/// Steps that return `/` to the snapshot taken before a run
+///
+/// Snapper reverts the changed files in place. A plain btrfs snapshot can only
+/// replace the root subvolume from a rescue system, so those steps are printed
+/// for the user to follow.
+pub(crate) fn rollback_steps(entry: &RunSnapshots) -> Vec<String> {
+    match entry.pre.method {
+        SnapshotMethod::Snapper => {
+            let config = entry.pre.config.as_deref().unwrap_or("root");
+            // Snapshot 0 is the current system
+            let post = entry.post.as_ref().map_or("0", |post| post.id.as_str());
+            vec![format!(
+                "sudo snapper -c {} undochange {}..{}",
+                config, entry.pre.id, post
+            )]
+        }
+        _ => vec![
+            "mount -o subvolid=5 /dev/<root device> /mnt".to_string(),
+            "mv /mnt/root /mnt/root.broken".to_string(),
+            format!(
+                "btrfs subvolume snapshot /mnt/root.broken{} /mnt/root",
+                entry.pre.id
+            ),
+            "umount /mnt && reboot".to_string(),
+        ],
+    }
+}
+
+/// Explains the rollback to the snapshot taken before a run, performing it with snapper
+///
+/// Returns whether the system was rolled back.
+pub(crate) async fn rollback(
+    runner: &mut CommandRunner,
+    entry: &RunSnapshots,
+    confirmed: bool,
+) -> Result<bool> {
+    let steps = rollback_steps(entry);
+    let performable = entry.pre.method == SnapshotMethod::Snapper;
+
+    let reporter = runner.reporter();
+    if reporter.is_text() {
+        if performable {
+            println!(
+                "Snapper reverts every file changed between snapshot {} and {} to its state before run {}.",
+                entry.pre.id,
+                entry.post.as_ref().map_or("now", |post| post.id.as_str()),
+                entry.run
+            );
+            println!("Changes made since, including later updates, may be reverted as well.");
+        } else {
+            println!(
+                "{} holds / as it was before run {}. A running system cannot replace its own \
+                 root subvolume, so boot a rescue system and run, for Fedora's default layout:",
+                entry.pre.id, entry.run
+            );
+        }
+        for step in &steps {
+            println!("  {}", step.cyan());
+        }
+    }
+
+    if !performable {
+        reporter.emit(Event::SnapshotRollback {
+            run: entry.run,
+            steps,
+            performed: false,
+        });
+        return Ok(false);
+    }
+
+    if !confirmed {
+        if runner.non_interactive() {
+            return Err(anyhow::anyhow!(
+                "Refusing to roll back without confirmation; pass --yes to roll back non-interactively"
+            ));
+        }
+        if !runner.reporter().confirm("Roll back now?", false)? {
+            return Ok(false);
+        }
+    }
+
+    let config = entry.pre.config.as_deref().unwrap_or("root");
+    let range = format!(
+        "{}..{}",
+        entry.pre.id,
+        entry.post.as_ref().map_or("0", |post| post.id.as_str())
+    );
+    let (status, _) = runner
+        .execute_command("snapper", &["-c", config, "undochange", &range], true)
+        .await?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("snapper undochange {} failed", range));
+    }
+
+    runner.reporter().emit(Event::SnapshotRollback {
+        run: entry.run,
+        steps,
+        performed: true,
+    });
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputFormat;
+    use crate::process::fake::ScriptedExecutor;
+    use std::sync::Arc;
+
+    fn runner(executor: &Arc<ScriptedExecutor>) -> CommandRunner {
+        CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone())
+    }
+
+    fn enabled() -> SnapshotConfig {
+        SnapshotConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reads_snapper_configs() {
+        assert_eq!(
+            parse_snapper_configs("config,subvolume\nroot,/\nhome,/home\n"),
+            ["root", "home"]
+        );
+        assert_eq!(parse_snapshot_number("42\n"), Some(42));
+    }
+
+    #[tokio::test]
+    async fn pairs_snapper_snapshots_around_the_update() {
+        let executor = Arc::new(
+            ScriptedExecutor::new(&["snapper"])
+                .expect(
+                    "snapper --csvout list-configs",
+                    0,
+                    "config,subvolume\nroot,/\n",
+                )
+                .expect("sudo snapper -c root create --type pre", 0, "42\n")
+                .expect(
+                    "sudo snapper -c root create --type post --pre-number 42",
+                    0,
+                    "43\n",
+                ),
+        );
+        let mut runner = runner(&executor);
+
+        let pre = before(&mut runner, &enabled()).await.unwrap().unwrap();
+        let post = after(&mut runner, &enabled(), &pre).await.unwrap();
+
+        assert_eq!(pre.method, SnapshotMethod::Snapper);
+        assert_eq!(post.id, "43");
+        assert_eq!(
+            describe(&[pre, post]).as_deref(),
+            Some("Created snapper snapshot 42 before and 43 after the update")
+        );
+        executor.assert_done();
+    }
+
+    #[tokio::test]
+    async fn failed_snapshot_blocks_the_update_unless_optional() {
+        let executor = Arc::new(
+            ScriptedExecutor::new(&["findmnt"])
+                .expect("findmnt -n -o FSTYPE /", 0, "xfs\n")
+                .expect("findmnt -n -o FSTYPE /", 0, "xfs\n"),
+        );
+        let mut runner = runner(&executor);
+
+        let error = before(&mut runner, &enabled()).await.unwrap_err();
+        assert!(error.to_string().starts_with(
+            "Could not create a snapshot before updating: / is not on Btrfs and snapper has no `root` configuration"
+        ));
+
+        let optional = SnapshotConfig {
+            required: false,
+            ..enabled()
+        };
+        assert_eq!(before(&mut runner, &optional).await.unwrap(), None);
+        executor.assert_done();
+    }
+
+    #[test]
+    fn btrfs_rollback_is_explained_from_a_rescue_system() {
+        let entry = RunSnapshots {
+            run: 7,
+            started_at: Local::now(),
+            pre: Snapshot {
+                method: SnapshotMethod::Btrfs,
+                kind: SnapshotKind::Pre,
+                id: "/var/lib/fedora-updater/snapshots/fedora-updater-20261016-030000-pre"
+                    .to_string(),
+                config: None,
+            },
+            post: None,
+        };
+
+        assert_eq!(
+            rollback_steps(&entry)[2],
+            "btrfs subvolume snapshot /mnt/root.broken/var/lib/fedora-updater/snapshots/\
+             fedora-updater-20261016-030000-pre /mnt/root"
+        );
+    }
+
+    #[tokio::test]
+    async fn unattended_rollback_needs_yes() {
+        let executor = Arc::new(ScriptedExecutor::new(&["snapper"]));
+        let mut runner = runner(&executor);
+        runner.set_non_interactive(true);
+        let entry = RunSnapshots {
+            run: 7,
+            started_at: Local::now(),
+            pre: Snapshot {
+                method: SnapshotMethod::Snapper,
+                kind: SnapshotKind::Pre,
+                id: "42".to_string(),
+                config: Some("root".to_string()),
+            },
+            post: None,
+        };
+
+        let error = rollback(&mut runner, &entry, false).await.unwrap_err();
+
+        assert!(error.to_string().starts_with("Refusing to roll back"));
+        assert!(executor.calls().is_empty());
+    }
+}
@@ -0,0 +1,589 @@
+//! systemd service and timer units for unattended update runs
+
+use crate::backend::UpdateMode;
+use crate::output::Event;
+use crate::runner::CommandRunner;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use colored::*;
+use serde::Serialize;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Directory holding units installed by the administrator
+const UNIT_DIR: &str = "/etc/systemd/system";
+const SERVICE_UNIT: &str = "fedora-updater.service";
+const TIMER_UNIT: &str = "fedora-updater.timer";
+
+/// How the generated units schedule and run the updater
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TimerOptions {
+    /// `OnCalendar=` expression, such as `daily` or `Sun 03:00`
+    pub(crate) schedule: String,
+    /// `RandomizedDelaySec=` span, so machines do not all update at once
+    pub(crate) randomized_delay: String,
+    /// DNF5 update mode; the configuration decides when unset
+    pub(crate) mode: Option<UpdateMode>,
+    pub(crate) security: bool,
+    pub(crate) cleanup: bool,
+    /// User the service runs as; root when unset
+    pub(crate) user: Option<String>,
+}
+
+impl TimerOptions {
+    /// Rejects values that would add lines or directives to the generated units
+    pub(crate) fn validate(&self) -> Result<()> {
+        let values = [
+            ("--schedule", Some(&self.schedule)),
+            ("--randomized-delay", Some(&self.randomized_delay)),
+            ("--user", self.user.as_ref()),
+        ];
+        for (option, value) in values {
+            if let Some(value) = value
+                && (value.is_empty() || value.contains(char::is_control))
+            {
+                return Err(anyhow::anyhow!(
+                    "{} must not be empty or contain control characters",
+                    option
+                ));
+            }
+        }
+
+        // The characters systemd accepts in user names
+        if let Some(user) = &self.user
+            && !user
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            return Err(anyhow::anyhow!("'{}' is not a valid user name", user));
+        }
+        Ok(())
+    }
+}
+
+/// Checks the schedule with `systemd-analyze calendar`, which parses it as the timer will
+pub(crate) async fn check_schedule(runner: &mut CommandRunner, schedule: &str) -> Result<()> {
+    let (status, _) = runner
+        .execute_command("systemd-analyze", &["calendar", schedule], false)
+        .await?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "'{}' is not a calendar expression systemd understands",
+            schedule
+        ));
+    }
+    Ok(())
+}
+
+/// Quotes a path for an `ExecStart=` line when it contains whitespace
+fn quote_exec(path: &Path) -> String {
+    let path = path.display().to_string();
+    if path.contains(char::is_whitespace) {
+        format!("\"{}\"", path)
+    } else {
+        path
+    }
+}
+
+/// Refuses an executable in a home directory, which SELinux keeps systemd from running
+pub(crate) fn check_executable(executable: &Path) -> Result<()> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let in_home = executable.starts_with("/home")
+        || executable.starts_with("/root")
+        || home.is_some_and(|home| home != Path::new("/") && executable.starts_with(home));
+    if in_home {
+        return Err(anyhow::anyhow!(
+            "{} is in a home directory, where SELinux does not let systemd run it. \
+             Install it system-wide first, for example with \
+             `sudo install -m 0755 {} /usr/local/bin/`, and run install-timer from there",
+            executable.display(),
+            executable.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Renders the oneshot service that runs a non-interactive update
+pub(crate) fn render_service(executable: &Path, options: &TimerOptions) -> String {
+    let mut command = format!("{} update --non-interactive", quote_exec(executable));
+    if let Some(mode) = options.mode.and_then(|mode| mode.to_possible_value()) {
+        command.push_str(&format!(" --mode {}", mode.get_name()));
+    }
+    if options.security {
+        command.push_str(" --security");
+    }
+    if options.cleanup {
+        command.push_str(" --cleanup");
+    }
+
+    let account = match &options.user {
+        // The user's history, Flatpak installation and containers are used, and
+        // privileged steps go through sudo without a password prompt
+        Some(user) => format!(
+            "User={}
+# Lets toolbox and distrobox reach the user's rootless podman
+Environment=XDG_RUNTIME_DIR=/run/user/%U
+",
+            user
+        ),
+        None => "# Keeps the update history in /var/lib/fedora-updater
+Environment=XDG_STATE_HOME=/var/lib
+StateDirectory=fedora-updater
+"
+        .to_string(),
+    };
+
+    format!(
+        "[Unit]
+Description=Update Fedora with fedora-updater
+Documentation=https://github.com/khs-kks/fedora-updater
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=oneshot
+ExecStart={}
+# Exit statuses 10 and 11 report that a reboot or service restart is needed,
+# and 12 that the run was deferred on battery or a metered connection
+SuccessExitStatus=10 11 12
+Environment=NO_COLOR=1
+{}Nice=10
+IOSchedulingClass=idle
+",
+        command, account
+    )
+}
+
+/// Renders the timer that starts the service on the configured schedule
+pub(crate) fn render_timer(options: &TimerOptions) -> String {
+    format!(
+        "[Unit]
+Description=Run fedora-updater on a schedule
+
+[Timer]
+OnCalendar={}
+RandomizedDelaySec={}
+# Catch up on runs missed while the machine was off
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+",
+        options.schedule, options.randomized_delay
+    )
+}
+
+/// Prints the generated units so they can be reviewed or installed by hand
+pub(crate) fn show_units(runner: &CommandRunner, service: &str, timer: &str) {
+    let reporter = runner.reporter();
+    if !reporter.is_text() {
+        reporter.emit(Event::TimerUnits {
+            service: service.to_string(),
+            timer: timer.to_string(),
+        });
+        return;
+    }
+
+    for (name, unit) in [(SERVICE_UNIT, service), (TIMER_UNIT, timer)] {
+        println!("\n{}", format!("# {}/{}", UNIT_DIR, name).blue().bold());
+        print!("{}", unit);
+    }
+    reporter.info("\nRun with --install to install and enable these units.");
+}
+
+/// Runs a privileged systemctl command, failing on a non-zero exit status
+async fn systemctl(runner: &mut CommandRunner, args: &[&str]) -> Result<()> {
+    let (status, _) = runner.execute_command("systemctl", args, true).await?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("systemctl {} failed", args.join(" ")));
+    }
+    Ok(())
+}
+
+/// Creates a fresh directory under `parent` that only the current user can enter
+///
+/// The directory must not exist yet, so nobody else can have prepared it or
+/// its contents before the units are copied out of it with sudo.
+fn private_dir(parent: &Path) -> Result<PathBuf> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.mode(0o700);
+
+    let mut attempt = 0;
+    loop {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.subsec_nanos());
+        let dir = parent.join(format!(
+            "fedora-updater-units-{}-{:08x}",
+            std::process::id(),
+            nanos
+        ));
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 8 => {
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to create {}", dir.display()));
+            }
+        }
+    }
+}
+
+/// Installs the units into /etc/systemd/system and starts the timer
+///
+/// The units are written to a private directory under `temp_dir` first and
+/// copied into place with sudo.
+pub(crate) async fn install(
+    runner: &mut CommandRunner,
+    temp_dir: &Path,
+    service: &str,
+    timer: &str,
+) -> Result<()> {
+    let staging_dir = private_dir(temp_dir)?;
+    let service_path = staging_dir.join(SERVICE_UNIT);
+    let timer_path = staging_dir.join(TIMER_UNIT);
+    for (path, unit) in [(&service_path, service), (&timer_path, timer)] {
+        let written = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o644)
+            .open(path)
+            .and_then(|mut file| file.write_all(unit.as_bytes()));
+        if let Err(e) = written {
+            let _ = std::fs::remove_dir_all(&staging_dir);
+            return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+        }
+    }
+
+    let service_path = service_path.display().to_string();
+    let timer_path = timer_path.display().to_string();
+    let (status, _) = runner
+        .execute_command(
+            "install",
+            &["-m", "0644", &service_path, &timer_path, UNIT_DIR],
+            true,
+        )
+        .await?;
+    // The staged copies are no longer needed either way
+    let _ = std::fs::remove_dir_all(&staging_dir);
+    if !status.success() {
+        return Err(anyhow::anyhow!("Failed to copy the units to {}", UNIT_DIR));
+    }
+
+    systemctl(runner, &["daemon-reload"]).await?;
+    systemctl(runner, &["enable", "--now", TIMER_UNIT]).await
+}
+
+/// Stops the timer and removes both units
+pub(crate) async fn uninstall(runner: &mut CommandRunner) -> Result<()> {
+    if !status(runner).await?.installed {
+        runner.reporter().info("The update timer is not installed.");
+        return Ok(());
+    }
+
+    systemctl(runner, &["disable", "--now", TIMER_UNIT]).await?;
+    let service_path = format!("{}/{}", UNIT_DIR, SERVICE_UNIT);
+    let timer_path = format!("{}/{}", UNIT_DIR, TIMER_UNIT);
+    let (status, _) = runner
+        .execute_command("rm", &["-f", &service_path, &timer_path], true)
+        .await?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "Failed to remove the units from {}",
+            UNIT_DIR
+        ));
+    }
+    systemctl(runner, &["daemon-reload"]).await
+}
+
+/// State of the installed timer and the last run of its service
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct TimerStatus {
+    pub(crate) installed: bool,
+    /// `enabled`, `disabled` and so on, from the unit file state
+    pub(crate) enabled: Option<String>,
+    /// `active` while the timer is waiting for its next elapse
+    pub(crate) active: Option<String>,
+    pub(crate) next_run: Option<String>,
+    pub(crate) last_run: Option<String>,
+    /// `success` or the reason the last run failed
+    pub(crate) last_result: Option<String>,
+    pub(crate) last_exit_code: Option<i32>,
+}
+
+/// Reads a property from `systemctl show` output, treating empty values as unset
+fn property(output: &str, name: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key == name && !value.is_empty() && value != "n/a").then(|| value.to_string())
+    })
+}
+
+/// Parses `systemctl show` output for the timer and its service
+pub(crate) fn parse_status(timer: &str, service: &str) -> TimerStatus {
+    let installed = property(timer, "LoadState").as_deref() == Some("loaded");
+    if !installed {
+        return TimerStatus::default();
+    }
+
+    // The service's result means nothing until the timer has started it
+    let last_run = property(timer, "LastTriggerUSec");
+    TimerStatus {
+        installed,
+        enabled: property(timer, "UnitFileState"),
+        active: property(timer, "ActiveState"),
+        next_run: property(timer, "NextElapseUSecRealtime"),
+        last_result: last_run.as_ref().and_then(|_| property(service, "Result")),
+        last_exit_code: last_run
+            .as_ref()
+            .and_then(|_| property(service, "ExecMainStatus"))
+            .and_then(|code| code.parse().ok()),
+        last_run,
+    }
+}
+
+/// Queries systemd for the timer and its service
+async fn status(runner: &mut CommandRunner) -> Result<TimerStatus> {
+    let timer = runner
+        .capture_command(
+            "systemctl",
+            &[
+                "show",
+                TIMER_UNIT,
+                "--property=LoadState,ActiveState,UnitFileState,NextElapseUSecRealtime,LastTriggerUSec",
+            ],
+        )
+        .await
+        .context("systemctl is not available")?;
+    let service = runner
+        .capture_command(
+            "systemctl",
+            &["show", SERVICE_UNIT, "--property=Result,ExecMainStatus"],
+        )
+        .await
+        .context("systemctl is not available")?;
+
+    Ok(parse_status(
+        &String::from_utf8_lossy(&timer.stdout),
+        &String::from_utf8_lossy(&service.stdout),
+    ))
+}
+
+/// Prints whether the timer is installed, when it runs next and how the last run went
+pub(crate) async fn show_status(runner: &mut CommandRunner) -> Result<()> {
+    let status = status(runner).await?;
+
+    let reporter = runner.reporter();
+    if !reporter.is_text() {
+        reporter.emit(Event::TimerStatus { status });
+        return Ok(());
+    }
+
+    println!("\n{}", "Update timer:".blue().bold());
+    if !status.installed {
+        println!("{}", "not installed".dimmed());
+        reporter.info("Run `fedora-updater install-timer` to set it up.");
+        return Ok(());
+    }
+
+    let fields = [
+        ("Enabled", status.enabled),
+        ("State", status.active),
+        ("Next run", status.next_run),
+        ("Last run", status.last_run),
+    ];
+    for (label, value) in fields {
+        if let Some(value) = value {
+            println!("{}: {}", label, value);
+        }
+    }
+    if let Some(result) = &status.last_result {
+        let result = match status.last_exit_code {
+            Some(code) => format!("{} (exit status {})", result, code),
+            None => result.clone(),
+        };
+        if status.last_result.as_deref() == Some("success") {
+            println!("Last result: {}", result.green());
+        } else {
+            println!("Last result: {}", result.red());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{OutputFormat, Reporter};
+    use crate::process::fake::ScriptedExecutor;
+    use std::sync::Arc;
+
+    const SHOW_TIMER: &str = include_str!("../tests/fixtures/systemd/show-timer.txt");
+    const SHOW_SERVICE: &str = include_str!("../tests/fixtures/systemd/show-service.txt");
+
+    fn options() -> TimerOptions {
+        TimerOptions {
+            schedule: "Sun 03:00".to_string(),
+            randomized_delay: "30min".to_string(),
+            mode: Some(UpdateMode::Offline),
+            security: false,
+            cleanup: true,
+            user: None,
+        }
+    }
+
+    #[test]
+    fn renders_non_interactive_units() {
+        let service = render_service(Path::new("/usr/local/bin/fedora-updater"), &options());
+        let timer = render_timer(&options());
+
+        assert!(service.contains(
+            "ExecStart=/usr/local/bin/fedora-updater update --non-interactive --mode offline --cleanup\n"
+        ));
+        assert!(service.contains("SuccessExitStatus=10 11 12\n"));
+        assert!(service.contains("Environment=XDG_STATE_HOME=/var/lib\n"));
+        assert!(!service.contains("User="));
+        assert!(timer.contains("OnCalendar=Sun 03:00\nRandomizedDelaySec=30min\n"));
+        assert!(
+            render_service(Path::new("/opt/my tools/fedora-updater"), &options())
+                .contains("ExecStart=\"/opt/my tools/fedora-updater\" update")
+        );
+    }
+
+    #[test]
+    fn runs_as_the_chosen_user_with_their_own_state() {
+        let options = TimerOptions {
+            user: Some("alice".to_string()),
+            ..options()
+        };
+        let service = render_service(Path::new("/usr/local/bin/fedora-updater"), &options);
+
+        assert!(service.contains("User=alice\n"));
+        assert!(service.contains("Environment=XDG_RUNTIME_DIR=/run/user/%U\n"));
+        assert!(!service.contains("XDG_STATE_HOME"));
+    }
+
+    #[test]
+    fn refuses_executables_in_home_directories() {
+        assert!(check_executable(Path::new("/usr/local/bin/fedora-updater")).is_ok());
+        let error = check_executable(Path::new("/home/alice/.cargo/bin/fedora-updater"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("/home/alice/.cargo/bin/fedora-updater is in a home directory"));
+    }
+
+    #[test]
+    fn parses_timer_and_last_run() {
+        let status = parse_status(SHOW_TIMER, SHOW_SERVICE);
+
+        assert!(status.installed);
+        assert_eq!(status.enabled.as_deref(), Some("enabled"));
+        assert_eq!(
+            status.next_run.as_deref(),
+            Some("Sun 2026-10-18 03:12:44 CEST")
+        );
+        assert_eq!(status.last_result.as_deref(), Some("success"));
+        assert_eq!(status.last_exit_code, Some(10));
+
+        let missing = parse_status("LoadState=not-found\nActiveState=inactive\n", "");
+        assert_eq!(missing, TimerStatus::default());
+    }
+
+    #[tokio::test]
+    async fn uninstall_leaves_a_missing_timer_alone() {
+        let executor = Arc::new(
+            ScriptedExecutor::new(&["systemctl"])
+                .expect(
+                    "systemctl show fedora-updater.timer",
+                    0,
+                    "LoadState=not-found\n",
+                )
+                .expect("systemctl show fedora-updater.service", 0, ""),
+        );
+        let mut runner =
+            CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone());
+
+        uninstall(&mut runner).await.unwrap();
+
+        executor.assert_done();
+    }
+
+    #[tokio::test]
+    async fn installs_and_starts_the_timer() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("fedora-updater-timer-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let executor = Arc::new(
+            ScriptedExecutor::new(&["install", "systemctl"])
+                .expect("sudo install -m 0644", 0, "")
+                .expect("sudo systemctl daemon-reload", 0, "")
+                .expect("sudo systemctl enable --now fedora-updater.timer", 0, ""),
+        );
+        let mut runner =
+            CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone());
+
+        install(&mut runner, &temp_dir, "[Service]\n", "[Timer]\n")
+            .await
+            .unwrap();
+
+        executor.assert_done();
+        // The units were staged in a directory of their own, removed afterwards
+        let staged = &executor.calls()[0];
+        assert!(staged.contains(&format!("{}/fedora-updater-units-", temp_dir.display())));
+        assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 0);
+        std::fs::remove_dir(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_values_that_add_unit_lines() {
+        let schedule = TimerOptions {
+            schedule: "daily\nExecStartPre=/bin/sh -c id".to_string(),
+            ..options()
+        };
+        assert!(schedule.validate().is_err());
+
+        let user = TimerOptions {
+            user: Some("alice\nUser=root".to_string()),
+            ..options()
+        };
+        assert!(user.validate().is_err());
+        assert!(options().validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn refuses_a_schedule_systemd_cannot_parse() {
+        let executor = Arc::new(ScriptedExecutor::new(&["systemd-analyze"]).expect(
+            "systemd-analyze calendar Sunday 3am",
+            1,
+            "Failed to parse calendar specification 'Sunday 3am': Invalid argument\n",
+        ));
+        let mut runner =
+            CommandRunner::with_executor(Reporter::new(OutputFormat::Json), executor.clone());
+
+        let error = check_schedule(&mut runner, "Sunday 3am").await.unwrap_err();
+
+        executor.assert_done();
+        assert_eq!(
+            error.to_string(),
+            "'Sunday 3am' is not a calendar expression systemd understands"
+        );
+    }
+
+    #[test]
+    fn stages_units_in_a_new_private_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let parent = std::env::temp_dir();
+        let first = private_dir(&parent).unwrap();
+        let second = private_dir(&parent).unwrap();
+
+        assert_ne!(first, second);
+        let mode = std::fs::metadata(&first).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        std::fs::remove_dir(&first).unwrap();
+        std::fs::remove_dir(&second).unwrap();
+    }
+}